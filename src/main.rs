@@ -1,1030 +1,9013 @@
-use pancurses::{curs_set, endwin, initscr, noecho, Attribute, Input, Window};
+use pancurses::{
+    beep, chtype, curs_set, endwin, has_colors, init_pair, initscr, newwin, noecho, resize_term,
+    start_color, use_default_colors, Attribute, ColorPair, Input, Window, COLORS, COLOR_GREEN,
+    COLOR_PAIRS, COLOR_RED, COLOR_YELLOW,
+};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::fmt::{format, Display, Formatter};
+use std::fmt::{Display, Formatter};
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-// idek
-const SECTION_LABELS: [&str; 16] = [
-    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P",
-];
+// edits u can step back through
+const UNDO_LIMIT: usize = 100;
 
-#[derive(Serialize, Deserialize)]
-struct Song {
-    title: String,
-    sections: Vec<Section>,
-}
+// bumped whenever Song::migrate learns a new step
+const FORMAT_VERSION: u32 = 2;
 
-impl Song {
-    fn new() -> Self {
-        Self {
-            title: "untitled".to_string(),
-            sections: vec![Section {
-                label: "A".to_string(),
-                bars: vec![Bar::default()],
-                repeats: false,
-                wrap: 4,
-            }],
+// edits between writes to the recovery file
+const AUTOSAVE_EVERY: usize = 20;
+
+// plain keys :doctor suggests for special keys some terminals never deliver.
+// nothing is rebound until the keys file says so
+const KEY_FALLBACKS: [(&str, Input, char); 8] = [
+    ("Delete", Input::KeyDC, 'X'),
+    ("PgUp", Input::KeyPPage, ']'),
+    ("PgDn", Input::KeyNPage, '['),
+    ("F3", Input::KeyF3, '{'),
+    ("F4", Input::KeyF4, '}'),
+    ("F5", Input::KeyF5, '#'),
+    ("Shift+Left", Input::KeySLeft, '<'),
+    ("Shift+Right", Input::KeySRight, '>'),
+];
+
+// A..Z, AA..AZ, BA.. like spreadsheet columns, counting from 0
+fn section_label(mut n: usize) -> String {
+    let mut label = Vec::new();
+    loop {
+        label.push((b'A' + (n % 26) as u8) as char);
+        if n < 26 {
+            break;
         }
+        n = n / 26 - 1;
     }
+    label.iter().rev().collect()
 }
 
-#[derive(Serialize, Deserialize)]
-struct Section {
-    label: String,
-    bars: Vec<Bar>,
-    repeats: bool,
-    wrap: usize, // bars
+// inverse of section_label, None for labels the user made up
+fn section_label_index(label: &str) -> Option<usize> {
+    if label.is_empty() || !label.chars().all(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+    label
+        .bytes()
+        .try_fold(0usize, |n, b| {
+            n.checked_mul(26)?.checked_add((b - b'A') as usize + 1)
+        })
+        .map(|n| n - 1)
 }
 
-#[derive(Serialize, Deserialize)]
-struct Bar {
-    beats: usize,
-    subdivision: usize,
-    chords: BTreeMap<usize, Chord>, // position in subdivisions
+#[derive(Clone, Serialize, Deserialize)]
+struct Song {
+    // files from before versioning read as 0
+    #[serde(default)]
+    version: u32,
+    title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subtitle: Option<String>,
+    sections: Vec<Section>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    auto_exports: Vec<AutoExport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    practice: Option<Practice>,
+    // printed above and below the chart, None falls back to :set print_header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    print_header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    print_footer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<Key>,
 }
 
-impl Default for Bar {
-    fn default() -> Self {
-        Bar {
-            beats: 4,
-            subdivision: 4,
-            chords: BTreeMap::new(),
-        }
-    }
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Practice {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target_tempo: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current_tempo: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_practiced: Option<String>, // YYYY-MM-DD
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
 }
 
-impl Bar {
-    fn new(beats: usize, subdivision: usize) -> Self {
-        Bar {
-            beats,
-            subdivision,
-            chords: BTreeMap::new(),
-        }
-    }
-    fn get_chord(&self, subdivision: usize) -> Option<&Chord> {
-        for (i, c) in &self.chords {
-            if *i == subdivision {
-                return Some(c);
-            }
-        }
-        None
-    }
-    fn get_chord_mut(&mut self, subdivision: usize) -> Option<&mut Chord> {
-        for (i, c) in &mut self.chords {
-            if *i == subdivision {
-                return Some(c);
-            }
-        }
-        None
-    }
-    fn try_reduce_subdivision(&mut self) -> bool {
-        if self.subdivision == 1 {
-            return false;
-        }
-        let new = self.subdivision / 2;
-        if self.chords.len() > new {
-            return false; // won't fit
-        }
-        for chord_i in self.chords.clone().into_keys() {
-            let chord = self.chords.remove(&chord_i).unwrap();
-            let new_i = chord_i / 2;
-            self.chords.insert(new_i, chord);
-        }
-        self.subdivision = new;
-        true
-    }
-    fn double_subdivision(&mut self) {
-        if self.subdivision >= 16 {
-            return;
+impl Display for Practice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(tempo) = self.current_tempo {
+            parts.push(format!("at {}", tempo));
         }
-        self.subdivision *= 2;
-        let old = self.chords.clone();
-        self.chords.clear();
-        for (i, c) in old {
-            self.chords.insert(i * 2, c);
+        if let Some(tempo) = self.target_tempo {
+            parts.push(format!("goal {}", tempo));
         }
+        parts.push(format!(
+            "last {}",
+            self.last_practiced.as_deref().unwrap_or("never")
+        ));
+        write!(f, "{}", parts.join(", "))
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct Chord {
-    note: Note,
-    accidental: Accidental,
-    quality: Quality,
-    over: Option<Note>,
-    special: bool,
-    question: bool,
+#[derive(Debug, PartialEq)]
+enum PracticeCommand {
+    Tempo(u32),
+    Note(String),
+    Done,
 }
 
-impl Chord {
-    fn parse(s: &str) -> Result<Self, ()> {
-        // silly regex i partially stole from some random place (https://regex101.com/r/T5GuGD/1 is my copy)
-        // groups:
-        // 1. note
-        // 2. accidental
-        // 3. combined quality + extensions (we use)
-        // 4. quality alone
-        // 5. extensions alone
-        // 6. over
-        // 7. special (!)
-        // 8. question (?)
-        let re = Regex::new(r"([CDEFGABcdefgab])([#b])?((M|-|\+|\^|m|o|aug|dim|sus|add|hd)?(6|7|9|11|13|5|b5)?)(/[CDEFGABcdefgab])?(!)?(\?)?").unwrap();
-        let caps = re.captures(s).ok_or(())?;
-
-        let note_s = caps.get(1).ok_or(())?;
-        let note = Note::try_from(note_s.as_str().chars().nth(0).unwrap()).unwrap();
-        let accidental = if let Some(accidental_s) = caps.get(2) {
-            match accidental_s.as_str() {
-                "#" => Accidental::Sharp,
-                "b" => Accidental::Flat,
-                _ => unreachable!(),
-            }
-        } else {
-            Accidental::None
-        };
-
-        let quality = if let Some(quality_s) = caps.get(3) {
-            match quality_s.as_str() {
-                "" => Quality::Maj, // idk why but that's what it does
-                "-" | "m" => Quality::Min,
-                "7" => Quality::Dom7,
-                "-7" | "m7" => Quality::Min7,
-                "^" | "^7" | "M7" => Quality::Maj7,
-                "dim" | "o" => Quality::Dim,
-                "dim7" | "o7" => Quality::Dim7,
-                "hd" => Quality::HalfDim,
-                "6" => Quality::Maj6,
-                "m6" | "-6" => Quality::Min6,
+impl PracticeCommand {
+    // :practice's arguments, Err is the usage to show
+    fn parse(args: &[&str]) -> Result<Self, &'static str> {
+        match args {
+            ["tempo", tempo] => tempo
+                .parse()
+                .map(PracticeCommand::Tempo)
+                .map_err(|_| "usage: practice tempo <bpm>"),
+            ["note", note @ ..] if !note.is_empty() => Ok(PracticeCommand::Note(note.join(" "))),
+            ["done"] => Ok(PracticeCommand::Done),
+            _ => Err("usage: practice tempo <bpm> | note <text> | done"),
+        }
+    }
+}
 
-                // TODO
-                _ => return Err(()),
-            }
-        } else {
-            Quality::Maj
-        };
-        let over = caps
-            .get(6)
-            .and_then(|over_s| over_s.as_str().chars().nth(1).map(Note::try_from))
-            .transpose()?;
+// {title} {date} {page} {pages} in print headers and footers
+fn expand_print_text(text: &str, title: &str, page: usize, pages: usize) -> String {
+    text.replace("{title}", title)
+        .replace("{date}", &today())
+        .replace("{pages}", &pages.to_string())
+        .replace("{page}", &page.to_string())
+}
 
-        Ok(Chord {
-            note,
-            accidental,
-            quality,
-            over,
-            special: caps.get(7).is_some(),
-            question: caps.get(8).is_some(),
-        })
-    }
-    fn toggle_question(&mut self) {
-        self.question = !self.question;
+// a print header or footer as CSS content, the browser counts the pages
+fn print_text_css(text: &str, title: &str) -> String {
+    let quote = |s: &str| {
+        let s = expand_print_text(s, title, 0, 0);
+        if s.is_empty() {
+            return None;
+        }
+        // \3C keeps a </style> in the text from closing the stylesheet
+        let s = s.replace('\\', "\\\\").replace('"', "\\\"");
+        Some(format!(
+            "\"{}\"",
+            s.replace('\n', "\\A ").replace('<', "\\3C ")
+        ))
+    };
+    static TOKENS: OnceLock<Regex> = OnceLock::new();
+    let tokens = TOKENS.get_or_init(|| Regex::new(r"\{pages?\}").unwrap());
+    let (mut parts, mut last) = (Vec::new(), 0);
+    for token in tokens.find_iter(text) {
+        parts.extend(quote(&text[last..token.start()]));
+        parts.push(match token.as_str() {
+            "{page}" => "counter(page)".to_string(),
+            _ => "counter(pages)".to_string(),
+        });
+        last = token.end();
     }
-    fn toggle_special(&mut self) {
-        self.special = !self.special;
+    parts.extend(quote(&text[last..]));
+    if parts.is_empty() {
+        "\"\"".to_string()
+    } else {
+        parts.join(" ")
     }
 }
 
-impl Display for Chord {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        //dbg!(&self);
-        write!(f, "{}{}{}", self.note, self.accidental, self.quality)?;
-        if let Some(n) = &self.over {
-            write!(f, "/{}", n)?;
-        }
-        if self.special {
-            write!(f, "!")?;
+// whole blocks onto pages of `room` lines, splitting only a block longer
+// than a page. 0 is one page however long
+fn paginate(blocks: Vec<Vec<String>>, room: usize) -> Vec<Vec<String>> {
+    let mut pages = vec![Vec::new()];
+    for block in blocks {
+        let page = pages.last().unwrap();
+        if room > 0 && !page.is_empty() && page.len() + block.len() > room {
+            pages.push(Vec::new());
         }
-        if self.question {
-            write!(f, "?")?;
+        for line in block {
+            if room > 0 && pages.last().unwrap().len() == room {
+                pages.push(Vec::new());
+            }
+            pages.last_mut().unwrap().push(line);
         }
-        Ok(())
     }
+    pages
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-enum Note {
-    A,
-    B,
-    C,
-    D,
-    E,
-    F,
-    G,
+// YYYY-MM-DD in UTC, days-to-civil from Howard Hinnant's date algorithms
+fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as i64;
+    let z = secs.div_euclid(86_400) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
 }
 
-impl TryFrom<char> for Note {
-    type Error = ();
-
-    fn try_from(value: char) -> Result<Self, Self::Error> {
-        match value.to_ascii_uppercase() {
-            'A' => Ok(Self::A),
-            'B' => Ok(Self::B),
-            'C' => Ok(Self::C),
-            'D' => Ok(Self::D),
-            'E' => Ok(Self::E),
-            'F' => Ok(Self::F),
-            'G' => Ok(Self::G),
-            _ => Err(()),
-        }
-    }
+// a trip through one section when the song is played start to end
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pass {
+    Once(usize),
+    Vamp(usize), // round and round until cued
 }
 
-impl Display for Note {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Note::A => 'A',
-                Note::B => 'B',
-                Note::C => 'C',
-                Note::D => 'D',
-                Note::E => 'E',
-                Note::F => 'F',
-                Note::G => 'G',
-            }
-        )
-    }
+// derived file that gets rewritten every time the song is saved
+#[derive(Clone, Serialize, Deserialize)]
+struct AutoExport {
+    format: ExportFormat,
+    path: PathBuf, // relative paths are relative to the song file
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-enum Accidental {
-    None,
-    Sharp,
-    Flat,
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum ExportFormat {
+    Html,
+    Layout, // the editor's own layout as JSON, for other renderers
+    Midi,   // block chords at 120 bpm
+    MusicXml,
+    Text, // the grid as drawn, for pasting into email
+    Markdown,
+    ChordPro, // grid sections, for chordpro's own renderers
 }
 
-impl Display for Accidental {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if matches!(self, Accidental::None) {
-            return Ok(());
+impl ExportFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "html" => Some(ExportFormat::Html),
+            "layout" => Some(ExportFormat::Layout),
+            "midi" => Some(ExportFormat::Midi),
+            "musicxml" => Some(ExportFormat::MusicXml),
+            "txt" | "text" => Some(ExportFormat::Text),
+            "md" | "markdown" => Some(ExportFormat::Markdown),
+            "chordpro" | "cho" => Some(ExportFormat::ChordPro),
+            _ => None,
         }
-        write!(
-            f,
-            "{}",
-            match self {
-                Accidental::None => unreachable!(),
-                Accidental::Sharp => '#',
-                Accidental::Flat => 'b',
+    }
+    fn render(&self, song: &Song, settings: &Settings) -> Vec<u8> {
+        match self {
+            ExportFormat::Html => song.to_html(settings, 0).into_bytes(),
+            ExportFormat::Layout => {
+                let width = Some(settings.layout_width as i32).filter(|&w| w > 0);
+                let layout = song.layout_at(settings, Notation::Letters, width);
+                serde_json::to_vec_pretty(&layout).unwrap()
             }
-        )
+            ExportFormat::Midi => song.to_midi(settings.vamp_times),
+            ExportFormat::MusicXml => song.to_musicxml().into_bytes(),
+            ExportFormat::Text => song.to_text(settings).into_bytes(),
+            ExportFormat::Markdown => song.to_markdown(settings).into_bytes(),
+            ExportFormat::ChordPro => song.to_chordpro(settings).into_bytes(),
+        }
+    }
+    fn is_text(&self) -> bool {
+        !matches!(self, ExportFormat::Midi)
     }
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-enum Quality {
-    Maj,
-    Min,
-    Dom7,
-    Maj7,
-    Min7,
-    Dim,
-    Dim7,
-    HalfDim,
-    Aug,
-    Dom9,
-    Maj9,
-    Min9,
-    Flat9,
-    Sharp9,
-    Maj11,
-    Sharp11,
-    Dom13,
-    Maj13,
-    Flat13,
-    Sus,
-    Sus4,
-    Sus2,
-    Maj6,
-    Min6,
-    // more complex chords out of scope :) (those r all i could think of that i use off the top of my head)
-}
-
-impl Display for Quality {
+impl Display for ExportFormat {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
             "{}",
             match self {
-                Quality::Maj => "",
-                Quality::Min => "-",
-                Quality::Dom7 => "7",
-                Quality::Maj7 => "^",
-                Quality::Min7 => "-7",
-                Quality::Dim => "o",
-                Quality::Dim7 => "o7",
-                Quality::HalfDim => "m7b5",
-                Quality::Aug => "+",
-                Quality::Dom9 => "9",
-                Quality::Maj9 => "^9",
-                Quality::Min9 => "-9",
-                Quality::Flat9 => "b9",
-                Quality::Sharp9 => "#9",
-                Quality::Maj11 => "^11",
-                Quality::Sharp11 => "#11",
-                Quality::Dom13 => "13",
-                Quality::Maj13 => "^13",
-                Quality::Flat13 => "b13",
-                Quality::Sus => "sus",
-                Quality::Sus4 => "sus4",
-                Quality::Sus2 => "sus2",
-                Quality::Maj6 => "6",
-                Quality::Min6 => "m6",
+                ExportFormat::Html => "html",
+                ExportFormat::Layout => "layout",
+                ExportFormat::Midi => "midi",
+                ExportFormat::MusicXml => "musicxml",
+                ExportFormat::Text => "txt",
+                ExportFormat::Markdown => "md",
+                ExportFormat::ChordPro => "chordpro",
             }
         )
     }
 }
 
-#[derive(Default, Debug, Copy, Clone)]
-struct CursorPos {
-    section: usize,
-    bar: usize,
-    subdivision: usize,
+// how far a save's auto-exports got
+struct ExportRun {
+    total: usize,
+    attempted: usize,
+    failed: Vec<String>,
+    cancelled: bool,
 }
 
-struct Toast {
-    message: Option<String>,
-    ticks: u32,
-}
-impl Default for Toast {
-    fn default() -> Self {
-        Toast {
-            message: None,
-            ticks: 0,
+impl ExportRun {
+    fn summary(&self, path: &Path) -> String {
+        let mut message = format!("Saved to {}", path.display());
+        if self.cancelled {
+            message += &format!(
+                ", exports cancelled after {}/{}",
+                self.attempted, self.total
+            );
+        }
+        if !self.failed.is_empty() {
+            message += &format!(", but export failed: {}", self.failed.join(", "));
         }
+        message
     }
 }
 
-struct State {
-    win: Window,
-    song: Song,
-    cursor: CursorPos,
-    should_clear: bool,
-    should_quit: bool,
-    toast: Toast,
-    filename: Option<PathBuf>,
-}
-
-impl State {
-    fn schedule_clear(&mut self) {
-        self.should_clear = true;
-    }
-    fn quit(&mut self) {
-        self.should_quit = true;
-    }
-    fn find_cursor(&self) -> (i32, i32) {
-        let mut ypos: i32 = 2;
-        let mut xpos: i32 = 1;
-        for s in self.song.sections.iter().take(self.cursor.section) {
-            let x = ((s.bars.len() - 1) / s.wrap) as i32;
-            ypos += x + 3;
-        }
-        ypos += 1;
-        let wrap = self.song.sections[self.cursor.section].wrap;
-        let col_widths = self.calc_widths(self.current_section());
-
-        for i in 0..=self.cursor.bar {
-            let width = col_widths[i % wrap] as i32;
-            if i % wrap == 0 && i > 0 {
-                ypos += 1;
-                xpos = 1;
-            }
-            if i < self.cursor.bar {
-                xpos +=
-                    1 + width * self.song.sections[self.cursor.section].bars[i].subdivision as i32;
-            } else {
-                xpos += width * self.cursor.subdivision as i32;
+impl Song {
+    // writes every auto-export for a song saved at path, asking keep_going
+    // before each one. a broken export never blocks the exports after it
+    fn write_auto_exports(
+        &self,
+        path: &Path,
+        settings: &Settings,
+        mut keep_going: impl FnMut(usize) -> bool,
+    ) -> ExportRun {
+        let mut run = ExportRun {
+            total: self.auto_exports.len(),
+            attempted: 0,
+            failed: Vec::new(),
+            cancelled: false,
+        };
+        for (i, export) in self.auto_exports.iter().enumerate() {
+            if !keep_going(i) {
+                run.cancelled = true;
+                break;
             }
+            let target = match path.parent() {
+                Some(dir) if export.path.is_relative() => dir.join(&export.path),
+                _ => export.path.clone(),
+            };
+            if let Err(e) = fs::write(&target, export.format.render(self, settings)) {
+                run.failed.push(format!("{} ({})", target.display(), e));
+            }
+            run.attempted += 1;
         }
-
-        (ypos, xpos)
+        run
     }
-    fn calc_widths(&self, section: &Section) -> Vec<usize> {
-        let wrap = section.wrap;
-        let mut widths = vec![0; wrap];
-
-        for (i, bar) in section.bars.iter().enumerate() {
-            let idx = i % wrap;
-            for subdivision in 0..bar.subdivision {
-                if let Some(chord) = bar.get_chord(subdivision) {
-                    let chord_str = format!("{} ", chord);
-                    widths[idx] = chord_str.chars().count().max(widths[idx]);
-                } else {
-                    widths[idx] = 2.max(widths[idx]); // minimum width
+    // brings an older file up to FORMAT_VERSION, one step at a time. it
+    // works on the json so a step can read names the structs no longer have
+    fn migrate(json: &mut serde_json::Value) -> Result<(), String> {
+        let mut version = json.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+        if version > FORMAT_VERSION as u64 {
+            return Err(format!(
+                "written by a newer chordchart (format {})",
+                version
+            ));
+        }
+        if version == 0 {
+            // everything added before versioning has a serde default
+            version = 1;
+        }
+        if version == 1 {
+            // qualities were one flat list, Flat9 is now Dom with a b9
+            let chords = json["sections"]
+                .as_array_mut()
+                .into_iter()
+                .flatten()
+                .filter_map(|section| section["bars"].as_array_mut())
+                .flatten()
+                .filter_map(|bar| bar["chords"].as_object_mut())
+                .flat_map(|chords| chords.values_mut())
+                .filter(|chord| chord.is_object());
+            for chord in chords {
+                // a missing quality is left for serde to report
+                let Some(name) = chord["quality"].as_str() else {
+                    continue;
+                };
+                let (quality, extensions, alteration) = Quality::migrate(name)
+                    .ok_or_else(|| format!("unknown chord quality {}", name))?;
+                chord["quality"] = serde_json::to_value(quality).unwrap();
+                if !extensions.is_empty() {
+                    chord["extensions"] = serde_json::to_value(extensions).unwrap();
+                }
+                if let Some(alteration) = alteration {
+                    let alterations = chord
+                        .as_object_mut()
+                        .unwrap()
+                        .entry("alterations")
+                        .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+                    if let Some(list) = alterations.as_array_mut() {
+                        list.insert(0, serde_json::to_value(alteration).unwrap());
+                    }
                 }
             }
+            version = 2;
         }
-        widths
+        json["version"] = version.into();
+        Ok(())
     }
-    fn draw(&mut self) {
-        if self.should_clear {
-            self.win.clear();
-        }
-
-        // Header
-        self.win.mvprintw(0, 0, "SONG: ");
-        self.win.printw(&self.song.title);
-
-        for (section_i, section) in self.song.sections.iter().enumerate() {
-            let mut ypos = 1;
-
-            for s in self.song.sections.iter().take(section_i) {
-                let x = ((s.bars.len() - 1) / s.wrap) as i32;
-                ypos += x + 3;
-            }
-            ypos += 1;
-            self.win.mvaddch(ypos, 0, '[');
-            self.win.addstr(&section.label);
-            self.win.addch(']');
-            ypos += 1;
-            self.win.mv(ypos, 0);
-            let col_widths = self.calc_widths(section);
-            for (bar_i, bar) in section.bars.iter().enumerate() {
-                if bar_i % section.wrap == 0 && bar_i > 0 {
-                    self.win.addch('|'); // terminating
-                    ypos += 1; // wow this code is gonna suck
-                    self.win.mv(ypos, 0);
+    fn new() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            title: "untitled".to_string(),
+            subtitle: None,
+            sections: vec![Section {
+                label: "A".to_string(),
+                bars: vec![Bar::default()],
+                repeats: false,
+                repeat_count: 1,
+                vamp: false,
+                wrap: 4,
+                align: None,
+            }],
+            auto_exports: Vec::new(),
+            practice: None,
+            print_header: None,
+            print_footer: None,
+            key: None,
+        }
+    }
+    // with a capo the shapes go under each concert chord
+    fn to_html(&self, settings: &Settings, capo: i32) -> String {
+        // render pleasingly
+        // oh we should use html that would be funny
+        let preamble = "<style>
+            html {
+                font-size: 24px;
+                font-family: sans-serif;
+            }
+
+            Section {
+                width: 100%;
+                display: flex;
+                flex-wrap: wrap;
+                gap: 2px 0;
+            }
+
+            Bar {
+                display: flex;
+                position: relative;
+                box-sizing: border-box;
+                border-left: 1px solid black;
+                padding: 2px;
+            }
+
+            Sub {
+                display: flex;
+            }
+
+            Row {
+                display: flex;
+                margin-bottom: 1em;
+            }
+
+            Row > Section {
+                flex: 1;
+            }
+
+            Label {
+                padding-right: 8px;
+                font-weight: bold;
+            }
+        </style>\n";
+        let mut content = String::from(preamble);
+        // printed in the page margins where the browser numbers the pages,
+        // on screen it's all one page
+        let print_text = |text: &String| {
+            format!(
+                "<p class=\"print-text\" style=\"font-size: 0.6em; color: gray;\">{}</p>\n",
+                expand_print_text(&escape_html(text), &escape_html(&self.title), 1, 1)
+            )
+        };
+        let (header, footer) = self.print_texts(settings);
+        if header.is_some() || footer.is_some() {
+            content.push_str("<style>\n@media print { .print-text { display: none; } }\n@page {\n");
+            for (text, place) in [(header, "top-center"), (footer, "bottom-center")] {
+                if let Some(text) = text {
+                    content.push_str(&format!(
+                        "    @{} {{ content: {}; font-size: 0.6em; color: gray; }}\n",
+                        place,
+                        print_text_css(text, &self.title)
+                    ));
+                }
+            }
+            content.push_str("}\n</style>\n");
+        }
+        if let Some(header) = header {
+            content.push_str(&print_text(header));
+        }
+        content.push_str(&format!("<h1>{}</h1>\n", escape_html(&self.title)));
+        if let Some(subtitle) = &self.subtitle {
+            content.push_str(&format!(
+                "<p style=\"font-size: 0.8em; font-style: italic;\">{}</p>\n",
+                escape_html(subtitle)
+            ));
+        }
+        if let Some(key) = self.key {
+            content.push_str(&format!(
+                "<p style=\"font-size: 0.8em;\">Key: {}</p>\n",
+                settings.glyphs(key.to_string())
+            ));
+        }
+        if capo > 0 {
+            content.push_str(&format!(
+                "<p style=\"font-size: 0.8em;\">Capo {}, shapes shown small</p>\n",
+                capo
+            ));
+        }
+        for (section_i, section) in self.sections.iter().enumerate() {
+            // a count is easy to miss at the far end of the last row
+            let plays = match section.plays() {
+                n if n > 2 => format!(" {}x", n),
+                _ => String::new(),
+            };
+            // section header
+            match settings.section_headers {
+                HeaderStyle::Line => content.push_str(&format!(
+                    "<h2>{}{}{}</h2>",
+                    section.label,
+                    section.label_note(),
+                    plays
+                )),
+                HeaderStyle::Inline => content.push_str(&format!(
+                    "<Row><Label>[{}]{}{}</Label>",
+                    section.label,
+                    section.label_note(),
+                    plays
+                )),
+                HeaderStyle::Margin => content.push_str(&format!(
+                    "<Row><Label style=\"writing-mode: vertical-rl;\">{}</Label>",
+                    section.label
+                )),
+            }
+            content.push_str("<Section>");
+            if section.repeats {
+                content.push_str("<Label>|:</Label>\n");
+            }
+            for (i, bar) in section.bars.iter().enumerate() {
+                // vamps open and close with repeat barlines
+                let barline = if section.vamp && i == 0 {
+                    " border-left: 4px double black;"
+                } else {
+                    ""
+                };
+                let time = self.time_change(section_i, i);
+                // room for the marker over the chords
+                let headroom = if bar.marker.is_some() || time.is_some() {
+                    " padding-top: 1.2em;"
+                } else {
+                    ""
+                };
+                content.push_str(&format!(
+                    "<Bar style=\"width: calc(100%/{});{}{}\">",
+                    section.effective_wrap(),
+                    barline,
+                    headroom
+                ));
+                // time signature first, then a landing sign after it
+                let (mut left, mut right) = (time.into_iter().collect::<Vec<_>>(), None);
+                match bar.marker {
+                    Some(marker) if marker.at_end() => right = Some(marker.to_string()),
+                    Some(marker) => left.push(marker.to_string()),
+                    None => {}
+                }
+                for (side, text) in [("left", Some(left.join(" "))), ("right", right)] {
+                    if let Some(text) = text.filter(|t| !t.is_empty()) {
+                        content.push_str(&format!(
+                            "<small style=\"position: absolute; top: 0; {}: 4px;\">{}</small>",
+                            side, text
+                        ));
+                    }
                 }
-                self.win.addch('|');
                 for s in 0..bar.subdivision {
-                    let selected = if self.cursor.section == section_i
-                        && self.cursor.bar == bar_i
-                        && self.cursor.subdivision == s
-                    {
-                        self.win.attron(Attribute::Reverse);
-                        true
+                    content.push_str(&format!(
+                        "<Sub style=\"width: calc(100%/{});\">",
+                        bar.subdivision
+                    ));
+                    if let Some(chord) = bar.get_chord(s) {
+                        // print chord
+                        content.push_str(&settings.glyphs(chord.to_string()));
+                        if capo > 0 {
+                            content.push_str(&format!(
+                                "<small style=\"color: gray; padding-left: 4px;\">{}</small>",
+                                settings.glyphs(chord.symbol(self.capo(capo)))
+                            ));
+                        }
+                    }
+                    content.push_str("</Sub>\n");
+                }
+                content.push_str("</Bar>\n");
+            }
+            if section.vamp {
+                content.push_str(
+                    "<Label style=\"border-left: 4px double black; padding-left: 4px;\">until cue</Label>\n",
+                );
+            } else if let Some(mark) = section.closing_mark() {
+                content.push_str(&format!(
+                    "<Label style=\"padding-left: 8px;\">{}</Label>\n",
+                    mark
+                ));
+            }
+            content.push_str("</Section>\n");
+            if !matches!(settings.section_headers, HeaderStyle::Line) {
+                content.push_str("</Row>\n");
+            }
+        }
+        let questions = self.questions();
+        if !questions.is_empty() {
+            content.push_str("<h2>Questions</h2>\n<ul>\n");
+            for (pos, chord) in questions {
+                content.push_str(&format!(
+                    "<li>{} {}{}</li>\n",
+                    self.location(pos),
+                    settings.glyphs(chord.to_string()),
+                    chord
+                        .question_note
+                        .as_ref()
+                        .map(|n| format!(": {}", escape_html(n)))
+                        .unwrap_or_default()
+                ));
+            }
+            content.push_str("</ul>\n");
+        }
+        if let Some(footer) = footer {
+            content.push_str(&print_text(footer));
+        }
+        content
+    }
+    // the song start to end, a section per pass. a vamp is one pass for
+    // whatever plays it to loop until the cue
+    fn expand(&self) -> Vec<Pass> {
+        self.sections
+            .iter()
+            .enumerate()
+            .flat_map(|(i, section)| match section.vamp {
+                true => vec![Pass::Vamp(i)],
+                false => vec![Pass::Once(i); section.plays()],
+            })
+            .collect()
+    }
+    // sections in playing order, each vamp taken round `vamp_times`
+    fn played(&self, vamp_times: usize) -> impl Iterator<Item = (usize, &Section)> {
+        self.expand().into_iter().flat_map(move |pass| {
+            let (i, times) = match pass {
+                Pass::Once(i) => (i, 1),
+                Pass::Vamp(i) => (i, vamp_times),
+            };
+            std::iter::repeat_n((i, &self.sections[i]), times)
+        })
+    }
+    // seconds at `bpm` quarters a minute, and whether a vamp made it a guess
+    fn duration(&self, bpm: u32, vamp_times: usize) -> (u64, bool) {
+        let beats: usize = self
+            .played(vamp_times)
+            .flat_map(|(_, section)| &section.bars)
+            .map(|bar| bar.beats)
+            .sum();
+        let guess = self.sections.iter().any(|s| s.vamp);
+        ((beats as u64 * 60).div_ceil(bpm.max(1) as u64), guess)
+    }
+    // "3:24", marked ≈ (~ without :set unicode) when a vamp makes it a guess
+    fn duration_text(&self, bpm: u32, settings: &Settings) -> String {
+        let (seconds, guess) = self.duration(bpm, settings.vamp_times);
+        let approx = match (guess, settings.unicode) {
+            (false, _) => "",
+            (true, true) => "≈",
+            (true, false) => "~",
+        };
+        format!("{}{}:{:02}", approx, seconds / 60, seconds % 60)
+    }
+    // standard midi file, one track, each chord held for its own cell and
+    // empty cells left silent. % plays whatever it repeats
+    fn to_midi(&self, vamp_times: usize) -> Vec<u8> {
+        const TICKS_PER_BEAT: usize = 480;
+        let mut events = Vec::new(); // (tick, on, note)
+        let mut bar_start = 0;
+        for (section_i, section) in self.played(vamp_times) {
+            for (bar_i, bar) in section.bars.iter().enumerate() {
+                let length = bar.beats * TICKS_PER_BEAT;
+                for (&sub, chord) in &bar.chords {
+                    let pos = CursorPos {
+                        section: section_i,
+                        bar: bar_i,
+                        subdivision: sub,
+                    };
+                    let chord = if chord.repeat {
+                        match self.repeated_chord(pos) {
+                            Some(chord) => chord,
+                            None => continue,
+                        }
                     } else {
-                        false
+                        chord
                     };
+                    let start = bar_start + sub * length / bar.subdivision;
+                    let end = bar_start + (sub + 1) * length / bar.subdivision;
+                    for note in chord.voicing() {
+                        events.push((start, true, note));
+                        events.push((end, false, note));
+                    }
+                }
+                bar_start += length;
+            }
+        }
+        // offs first so a repeated note gets released before it's struck again
+        events.sort_by_key(|&(tick, on, note)| (tick, on, note));
 
-                    let col_width = col_widths[bar_i % section.wrap];
+        let mut track = vec![0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20]; // 500000us a beat
+        let title = self.title.as_bytes();
+        track.extend([0x00, 0xff, 0x03]);
+        write_vlq(&mut track, title.len());
+        track.extend(title);
+        let mut last = 0;
+        for (tick, on, note) in events {
+            write_vlq(&mut track, tick - last);
+            last = tick;
+            if on {
+                track.extend([0x90, note, 80]);
+            } else {
+                track.extend([0x80, note, 0]);
+            }
+        }
+        // end of track after the last bar, so trailing rests still count
+        write_vlq(&mut track, bar_start - last);
+        track.extend([0xff, 0x2f, 0x00]);
 
-                    if let Some(chord) = bar.get_chord(s) {
-                        // print chord
-                        let chord_str = format!("{}", chord);
-                        self.win.addstr(&chord_str);
-                        // fill remaining space
-                        self.win.addstr(" ".repeat(col_width - chord_str.len()));
-                    } else if self.cursor.section == section_i && self.cursor.bar == bar_i {
-                        self.win.addstr(".");
-                        self.win.addstr(" ".repeat(col_width - 1));
-                    } else {
-                        self.win.addstr(" ".repeat(col_width));
+        let mut file = b"MThd".to_vec();
+        file.extend(6u32.to_be_bytes());
+        file.extend(0u16.to_be_bytes()); // single track format
+        file.extend(1u16.to_be_bytes());
+        file.extend((TICKS_PER_BEAT as u16).to_be_bytes());
+        file.extend(b"MTrk");
+        file.extend((track.len() as u32).to_be_bytes());
+        file.extend(track);
+        file
+    }
+    // one part of rests carrying <harmony> frames, a rest per cell so each
+    // chord lands where it was typed. Sections start with a rehearsal mark
+    fn to_musicxml(&self) -> String {
+        // every cell a whole number of divisions, whatever the bar's subdivision
+        let divisions = self
+            .sections
+            .iter()
+            .flat_map(|s| &s.bars)
+            .fold(1, |lcm, bar| {
+                lcm * bar.subdivision / gcd(lcm, bar.subdivision)
+            });
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">\n\
+             <score-partwise version=\"4.0\">\n",
+        );
+        xml.push_str(&format!(
+            "  <work><work-title>{}</work-title></work>\n",
+            escape_html(&self.title)
+        ));
+        xml.push_str("  <part-list><score-part id=\"P1\"><part-name>Chords</part-name></score-part></part-list>\n");
+        xml.push_str("  <part id=\"P1\">\n");
+        let mut number = 0;
+        let mut beats = 0;
+        for (section_i, section) in self.sections.iter().enumerate() {
+            for (bar_i, bar) in section.bars.iter().enumerate() {
+                number += 1;
+                xml.push_str(&format!("    <measure number=\"{}\">\n", number));
+                if number == 1 || bar.beats != beats {
+                    beats = bar.beats;
+                    xml.push_str(&format!(
+                        "      <attributes><divisions>{}</divisions><time><beats>{}</beats><beat-type>4</beat-type></time></attributes>\n",
+                        divisions, beats
+                    ));
+                }
+                if bar_i == 0 {
+                    xml.push_str(&format!(
+                        "      <direction placement=\"above\"><direction-type><rehearsal>{}</rehearsal></direction-type></direction>\n",
+                        escape_html(&section.label)
+                    ));
+                }
+                let duration = bar.beats * divisions / bar.subdivision;
+                for sub in 0..bar.subdivision {
+                    let pos = CursorPos {
+                        section: section_i,
+                        bar: bar_i,
+                        subdivision: sub,
+                    };
+                    let chord = match bar.get_chord(sub) {
+                        Some(chord) if chord.repeat => self.repeated_chord(pos),
+                        chord => chord,
+                    };
+                    if let Some(chord) = chord {
+                        xml.push_str(&chord.to_musicxml());
+                    }
+                    xml.push_str(&format!(
+                        "      <note><rest/><duration>{}</duration></note>\n",
+                        duration
+                    ));
+                }
+                xml.push_str("    </measure>\n");
+            }
+        }
+        xml.push_str("  </part>\n</score-partwise>\n");
+        xml
+    }
+    // the song's own header and footer win over the configured ones
+    fn print_texts<'a>(
+        &'a self,
+        settings: &'a Settings,
+    ) -> (Option<&'a String>, Option<&'a String>) {
+        (
+            self.print_header
+                .as_ref()
+                .or(settings.print_header.as_ref()),
+            self.print_footer
+                .as_ref()
+                .or(settings.print_footer.as_ref()),
+        )
+    }
+    // an export's blocks on numbered pages under the header and over the
+    // footer, each of their lines put in the format's markup by `markup`.
+    // one page without either, there's nothing to number
+    fn print_pages(
+        &self,
+        settings: &Settings,
+        blocks: Vec<Vec<String>>,
+        markup: impl Fn(&str) -> String,
+    ) -> Vec<Vec<String>> {
+        let (header, footer) = self.print_texts(settings);
+        if header.is_none() && footer.is_none() {
+            return vec![blocks.concat()];
+        }
+        // with a blank line between them and the chart
+        let lines = |text: Option<&String>| text.map_or(0, |t| t.lines().count() + 1);
+        let room = settings
+            .page_lines
+            .saturating_sub(lines(header) + lines(footer))
+            .max(1);
+        let pages = paginate(blocks, room);
+        let count = pages.len();
+        let expand = |text: &String, page: usize| {
+            expand_print_text(text, &self.title, page, count)
+                .lines()
+                .map(&markup)
+                .collect::<Vec<String>>()
+        };
+        pages
+            .into_iter()
+            .enumerate()
+            .map(|(i, body)| {
+                let mut page = Vec::new();
+                if let Some(header) = header {
+                    page.extend(expand(header, i + 1));
+                    page.push(String::new());
+                }
+                page.extend(body);
+                if let Some(footer) = footer {
+                    page.push(String::new());
+                    page.extend(expand(footer, i + 1));
+                }
+                page
+            })
+            .collect()
+    }
+    // every chord flagged with ?, in song order
+    fn questions(&self) -> Vec<(CursorPos, &Chord)> {
+        let mut found = Vec::new();
+        for (section_i, section) in self.sections.iter().enumerate() {
+            for (bar_i, bar) in section.bars.iter().enumerate() {
+                for (sub, chord) in &bar.chords {
+                    if chord.question {
+                        let pos = CursorPos {
+                            section: section_i,
+                            bar: bar_i,
+                            subdivision: *sub,
+                        };
+                        found.push((pos, chord));
+                    }
+                }
+            }
+        }
+        found
+    }
+    // human readable "A 3.2", bar and subdivision counted from 1
+    fn location(&self, pos: CursorPos) -> String {
+        format!(
+            "{} {}.{}",
+            self.sections[pos.section].label,
+            pos.bar + 1,
+            pos.subdivision + 1
+        )
+    }
+    // the chord ringing at a position, the last one at or before it even
+    // if that's bars or sections back
+    fn sounding_chord(&self, pos: CursorPos) -> Option<&Chord> {
+        let section = &self.sections[pos.section];
+        if let Some(chord) = section.bars[pos.bar].sounding_at(pos.subdivision) {
+            return Some(chord);
+        }
+        let earlier_bars = section.bars[..pos.bar].iter().rev();
+        let earlier_sections = self.sections[..pos.section]
+            .iter()
+            .rev()
+            .flat_map(|s| s.bars.iter().rev());
+        earlier_bars
+            .chain(earlier_sections)
+            .find_map(|b| b.chords.values().next_back())
+    }
+    // ! on a cell: flips the accent of a chord there, otherwise hits the
+    // sounding chord again here, accented. false with nothing to accent
+    fn accent(&mut self, pos: CursorPos) -> bool {
+        let bar = &self.sections[pos.section].bars[pos.bar];
+        if bar.get_chord(pos.subdivision).is_some() {
+            let chords = &mut self.sections[pos.section].bars[pos.bar].chords;
+            chords.get_mut(&pos.subdivision).unwrap().toggle_special();
+            return true;
+        }
+        let Some(chord) = self.sounding_chord(pos) else {
+            return false;
+        };
+        let accented = Chord {
+            special: true,
+            question: false,
+            question_note: None,
+            ..chord.clone()
+        };
+        self.sections[pos.section].bars[pos.bar]
+            .chords
+            .insert(pos.subdivision, accented);
+        true
+    }
+    // what a % at `pos` stands for, the nearest real chord before it
+    fn repeated_chord(&self, pos: CursorPos) -> Option<&Chord> {
+        let sections = self.sections.iter().enumerate().take(pos.section + 1).rev();
+        for (section_i, section) in sections {
+            for (bar_i, bar) in section.bars.iter().enumerate().rev() {
+                let same_section = section_i == pos.section;
+                if same_section && bar_i > pos.bar {
+                    continue;
+                }
+                for (sub, chord) in bar.chords.iter().rev() {
+                    if same_section && bar_i == pos.bar && *sub >= pos.subdivision {
+                        continue;
                     }
+                    if !chord.repeat && !chord.no_chord {
+                        return Some(chord);
+                    }
+                }
+            }
+        }
+        None
+    }
+    fn transposed(&self, semitones: i32, spelling: Spelling) -> Self {
+        let mut song = self.clone();
+        song.key = self.key.map(|k| k.transposed(semitones));
+        for section in &mut song.sections {
+            section.transpose(semitones, spelling, song.key);
+        }
+        song
+    }
+    // `count` bars from the cursor bar on, cut short at the section's end
+    fn bar_range(&self, cursor: CursorPos, count: usize) -> std::ops::Range<usize> {
+        let end = (cursor.bar + count.max(1)).min(self.sections[cursor.section].bars.len());
+        cursor.bar..end
+    }
+    // deletes bar_range and says where the cursor goes. a section never goes
+    // without bars, so taking them all takes the section, or leaves the only
+    // section one empty bar
+    fn delete_bars(&mut self, cursor: CursorPos, count: usize) -> CursorPos {
+        let range = self.bar_range(cursor, count);
+        let bars = &mut self.sections[cursor.section].bars;
+        if range.len() < bars.len() {
+            bars.drain(range);
+            // previous bar, or whatever moved up into the first slot
+            return CursorPos {
+                bar: cursor.bar.saturating_sub(1),
+                subdivision: 0,
+                ..cursor
+            };
+        }
+        if self.sections.len() > 1 {
+            self.sections.remove(cursor.section);
+            CursorPos {
+                section: cursor.section.min(self.sections.len() - 1),
+                ..CursorPos::default()
+            }
+        } else {
+            let bars = &mut self.sections[0].bars;
+            let last = bars.last().unwrap();
+            *bars = vec![Bar::new(last.beats, last.subdivision)];
+            CursorPos::default()
+        }
+    }
+    // the transposed song and the preview of it, old -> new for each bar
+    // that has chords. `only` limits both to one section
+    fn transpose_preview(&self, semitones: i32, only: Option<usize>) -> (Song, Vec<String>) {
+        // spelled for the key we end up in
+        let spelling = match self.key.and_then(|k| k.transposed(semitones).spelling()) {
+            Some(spelling) => spelling,
+            None => self.spelling(),
+        };
+        let transposed = match only {
+            Some(index) => {
+                let mut song = self.clone();
+                let key = song.key.map(|k| k.transposed(semitones));
+                song.sections[index].transpose(semitones, spelling, key);
+                song
+            }
+            None => self.transposed(semitones, spelling),
+        };
+
+        let mut lines = vec![format!("spelling: {}", spelling), String::new()];
+        for (section_i, (old, new)) in self.sections.iter().zip(&transposed.sections).enumerate() {
+            if only.is_some_and(|index| index != section_i) {
+                continue;
+            }
+            for (bar_i, (old_bar, new_bar)) in old.bars.iter().zip(&new.bars).enumerate() {
+                if old_bar.chords.is_empty() {
+                    continue;
+                }
+                lines.push(format!(
+                    "{}{:<3} {}  ->  {}",
+                    old.label,
+                    bar_i + 1,
+                    old_bar.chords_string(),
+                    new_bar.chords_string()
+                ));
+            }
+        }
+        (transposed, lines)
+    }
+    // the song at `path` in place of this one, which stays as it was if that
+    // can't be read. gives how the file was written and how many bars splitting
+    // crowded ones added
+    fn replace_from(
+        &mut self,
+        path: &Path,
+        settings: &Settings,
+    ) -> Result<(JsonStyle, usize), String> {
+        let (mut song, data) = read_song(path, settings.max_file_bytes())?;
+        let added = song.split_oversized_bars(settings.max_chords_per_bar);
+        *self = song;
+        Ok((JsonStyle::detect(&data), added))
+    }
+    // bars `range` counted across sections, the way :from lists them. each
+    // keeps its own meter, and its chords as chords rather than text
+    fn bars_in(&self, range: std::ops::RangeInclusive<usize>) -> Vec<Bar> {
+        self.sections
+            .iter()
+            .flat_map(|section| &section.bars)
+            .skip(*range.start())
+            .take(range.count())
+            .cloned()
+            .collect()
+    }
+    fn insert_bars(&mut self, section: usize, at: usize, bars: Vec<Bar>) {
+        self.sections[section].bars.splice(at..at, bars);
+    }
+    // the cursor moves along with its section, or to the start of the next
+    fn delete_section(&mut self, index: usize, cursor: &mut CursorPos) -> Result<(), &'static str> {
+        if self.sections.len() == 1 {
+            return Err("can't delete the only section");
+        }
+        self.sections.remove(index);
+        if cursor.section == index {
+            *cursor = CursorPos {
+                section: index.min(self.sections.len() - 1),
+                ..CursorPos::default()
+            };
+        } else if cursor.section > index {
+            cursor.section -= 1;
+        }
+        Ok(())
+    }
+    // swaps with the neighbour; false when there's none that way
+    fn move_section(&mut self, index: usize, up: bool, cursor: &mut CursorPos) -> bool {
+        let other = if up {
+            match index.checked_sub(1) {
+                Some(i) => i,
+                None => return false,
+            }
+        } else if index + 1 < self.sections.len() {
+            index + 1
+        } else {
+            return false;
+        };
+        self.sections.swap(index, other);
+        // the cursor stays on whatever section it was in
+        if cursor.section == index {
+            cursor.section = other;
+        } else if cursor.section == other {
+            cursor.section = index;
+        }
+        true
+    }
+    fn rename_section(&mut self, index: usize, label: &str) -> Result<(), &'static str> {
+        let label = label.trim();
+        if label.is_empty() {
+            return Err("label can't be empty");
+        }
+        self.sections[index].label = label.to_owned();
+        Ok(())
+    }
+    fn split_oversized_bars(&mut self, max: usize) -> usize {
+        self.sections
+            .iter_mut()
+            .map(|s| s.split_oversized_bars(max))
+            .sum()
+    }
+    // past every generated label, and clear of any the user typed
+    fn next_label(&self) -> String {
+        let mut next = self
+            .sections
+            .iter()
+            .filter_map(|s| section_label_index(&s.label))
+            .max()
+            .map_or(self.sections.len(), |n| n + 1);
+        while self.sections.iter().any(|s| s.label == section_label(next)) {
+            next += 1;
+        }
+        section_label(next)
+    }
+    fn capo(&self, fret: i32) -> Notation {
+        Notation::Capo {
+            fret,
+            key: self.key.map(|k| k.transposed(-fret)),
+        }
+    }
+    // whichever accidental the chart already leans on, flats if it's a tie
+    fn spelling(&self) -> Spelling {
+        if let Some(spelling) = self.key.and_then(|k| k.spelling()) {
+            return spelling;
+        }
+        let (mut sharps, mut flats) = (0, 0);
+        for chord in self
+            .sections
+            .iter()
+            .flat_map(|s| &s.bars)
+            .flat_map(|b| b.chords.values())
+        {
+            for accidental in [chord.accidental, chord.over_accidental] {
+                match accidental {
+                    Accidental::Sharp => sharps += 1,
+                    Accidental::Flat => flats += 1,
+                    Accidental::None => {}
+                }
+            }
+        }
+        if sharps > flats {
+            Spelling::Sharps
+        } else {
+            Spelling::Flats
+        }
+    }
+}
+
+// Where everything goes on screen, worked out once and shared by draw(),
+// find_cursor() and the layout export. x and y are terminal columns and rows,
+// y counting from the title line at 0. layout.schema.json describes the export.
+#[derive(Serialize)]
+struct Layout {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<i32>, // rows were broken early to fit this many columns
+    gutter: i32, // columns left of the first barline kept for section labels
+    #[serde(skip)]
+    first: usize, // song index of sections[0], when only some were laid out
+    sections: Vec<SectionLayout>,
+}
+
+// what laying out one section needs from the rest of the song, so a few
+// sections can be placed without walking them all. tops is a running sum of
+// section heights with one entry past the end, where another section would go
+#[derive(Default)]
+struct Outline {
+    width: Option<i32>, // columns to fit rows into, None keeps every wrap
+    gutter: i32,
+    tops: Vec<i32>, // label line of each section, before scrolling
+    first_bars: Vec<usize>,
+    wraps: Vec<usize>,
+    ringing: Vec<bool>, // some chord before the section is still sounding
+    global_widths: Vec<usize>,
+}
+
+impl Outline {
+    // sections before `from` are unchanged, the running sums pick up from there
+    fn refresh(&mut self, song: &Song, settings: &Settings, notation: Notation, from: usize) {
+        self.gutter = song.gutter_width(settings) + song.number_width(settings);
+        self.global_widths = song.global_widths(settings, notation);
+        let from = from.min(self.wraps.len()).min(song.sections.len());
+        self.first_bars = song.first_bars();
+        self.tops.truncate(from);
+        self.wraps.truncate(from);
+        self.ringing.truncate(from);
+        let (mut top, mut ringing) = match from.checked_sub(1) {
+            Some(last) => (
+                self.tops[last] + song.section_height(settings, last, self.wraps[last]),
+                song.sections[last].rings_on(self.ringing[last]),
+            ),
+            None => (song.header_height(), false),
+        };
+        for section_i in from..song.sections.len() {
+            let wrap = song.fit_wrap(settings, notation, section_i, self);
+            self.tops.push(top);
+            self.wraps.push(wrap);
+            self.ringing.push(ringing);
+            top += song.section_height(settings, section_i, wrap);
+            ringing = song.sections[section_i].rings_on(ringing);
+        }
+        self.tops.push(top);
+    }
+    // sections with any line between the two screen rows, before scrolling
+    fn visible(&self, from_y: i32, to_y: i32) -> std::ops::Range<usize> {
+        let count = self.wraps.len();
+        let first = self.tops[1..].partition_point(|&end| end <= from_y);
+        let last = self.tops[..count].partition_point(|&top| top < to_y);
+        first..last.max(first)
+    }
+}
+
+// where a layout gets painted, the terminal or a test's grid of characters
+trait Grid {
+    fn put(&mut self, y: i32, x: i32, text: &str);
+    fn attr(&mut self, attr: chtype, on: bool);
+}
+
+impl Grid for Window {
+    fn put(&mut self, y: i32, x: i32, text: &str) {
+        self.mvaddstr(y, x, text);
+    }
+    fn attr(&mut self, attr: chtype, on: bool) {
+        if on {
+            self.attron(attr);
+        } else {
+            self.attroff(attr);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SectionLayout {
+    label: String,
+    label_y: i32, // same as the first row unless labels get their own line
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    vamp: bool,
+    // |: opens the first row and :| closes the last, every row's first
+    // barline takes two columns so the rows still line up
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    repeats: bool,
+    rows: Vec<RowLayout>,
+}
+
+#[derive(Serialize)]
+struct RowLayout {
+    y: i32, // markers, if any, go on the line above
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<usize>, // of its first bar, counting from 1 across the song
+    end_x: i32, // closing barline
+    bars: Vec<BarLayout>,
+}
+
+#[derive(Serialize)]
+struct BarLayout {
+    bar: usize, // index within the section
+    x: i32,     // opening barline
+    cells: Vec<CellLayout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    marker: Option<Marker>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<String>, // where the beats change, on the marker line
+}
+
+impl BarLayout {
+    // where a marker starts so that jumps end flush with the closing barline
+    // and landing signs clear the time signature
+    fn marker_x(&self, marker: Marker) -> i32 {
+        let start = self.x + 1 + self.time.as_ref().map_or(0, |t| t.len() as i32 + 1);
+        let end = self.cells.last().map_or(self.x + 1, |c| c.x + c.width);
+        if marker.at_end() {
+            (end - marker.to_string().chars().count() as i32).max(start)
+        } else {
+            start
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CellLayout {
+    subdivision: usize,
+    x: i32,
+    width: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symbol: Option<String>,
+    special: bool,
+    question: bool,
+    held: bool,   // empty, with the chord before still ringing
+    simile: bool, // a %, playing the chord before again
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    unresolved: bool, // a % with nothing before it
+}
+
+impl Layout {
+    // (y, x) of a cursor position's cell
+    fn find(&self, pos: CursorPos) -> Option<(i32, i32)> {
+        self.sections
+            .get(pos.section.checked_sub(self.first)?)?
+            .rows
+            .iter()
+            .find_map(|row| {
+                let bar = row.bars.iter().find(|b| b.bar == pos.bar)?;
+                Some((row.y, bar.cells.get(pos.subdivision)?.x))
+            })
+    }
+    // moves everything up by `lines`, for a chart scrolled past its top
+    fn scroll(&mut self, lines: i32) {
+        for section in &mut self.sections {
+            section.label_y -= lines;
+            for row in &mut section.rows {
+                row.y -= lines;
+            }
+        }
+    }
+}
+
+impl Song {
+    // columns reserved left of the barlines for section labels
+    fn gutter_width(&self, settings: &Settings) -> i32 {
+        match settings.section_headers {
+            HeaderStyle::Line => 0,
+            HeaderStyle::Inline => {
+                self.sections
+                    .iter()
+                    .map(|s| s.label.chars().count())
+                    .max()
+                    .unwrap_or(0) as i32
+                    + 3 // brackets and a space
+            }
+            HeaderStyle::Margin => 2,
+        }
+    }
+    // room for the biggest bar number and a space, when numbers are on
+    fn number_width(&self, settings: &Settings) -> i32 {
+        if !settings.bar_numbers {
+            return 0;
+        }
+        self.bar_count().max(1).to_string().len() as i32 + 1
+    }
+    // beats over a quarter, shown where they differ from the bar before (or
+    // from 4/4 at the very start). Every bar counts in quarters
+    fn time_change(&self, section: usize, bar: usize) -> Option<String> {
+        let beats = self.sections[section].bars[bar].beats;
+        let previous = match bar.checked_sub(1) {
+            Some(b) => Some(&self.sections[section].bars[b]),
+            None => self.sections[..section]
+                .iter()
+                .rev()
+                .find_map(|s| s.bars.last()),
+        };
+        (beats != previous.map_or(4, |b| b.beats)).then(|| format!("{}/4", beats))
+    }
+    fn bar_count(&self) -> usize {
+        self.sections.iter().map(|s| s.bars.len()).sum()
+    }
+    // each section's first bar counting from 1 across the song, the one count
+    // behind both the bar numbers drawn and :bar
+    fn first_bars(&self) -> Vec<usize> {
+        self.sections
+            .iter()
+            .scan(1, |next, section| {
+                let first = *next;
+                *next += section.bars.len();
+                Some(first)
+            })
+            .collect()
+    }
+    // the nth bar as bar numbers show it
+    fn bar_position(&self, n: usize) -> Option<CursorPos> {
+        let first_bars = self.first_bars();
+        let section = first_bars
+            .partition_point(|&first| first <= n)
+            .checked_sub(1)?;
+        let bar = n - first_bars[section];
+        (bar < self.sections[section].bars.len()).then_some(CursorPos {
+            section,
+            bar,
+            subdivision: 0,
+        })
+    }
+    // the last subdivision of a section, its start when it has no bars
+    fn section_end(&self, section: usize) -> CursorPos {
+        let bars = &self.sections[section].bars;
+        CursorPos {
+            section,
+            bar: bars.len().saturating_sub(1),
+            subdivision: bars.last().map_or(0, |b| b.subdivision - 1),
+        }
+    }
+    // the bar before in the same column, up is made of these. the first bar
+    // goes to its start, then to the start of the previous section's last
+    fn prev_bar(&self, cursor: CursorPos) -> CursorPos {
+        match (cursor.bar, cursor.subdivision) {
+            (0, 1..) => CursorPos {
+                subdivision: 0,
+                ..cursor
+            },
+            (0, 0) if cursor.section > 0 => CursorPos {
+                subdivision: 0,
+                ..self.section_end(cursor.section - 1)
+            },
+            (0, 0) => cursor,
+            (bar, subdivision) => CursorPos {
+                bar: bar - 1,
+                // a bar in another meter may be shorter
+                subdivision: subdivision
+                    .min(self.sections[cursor.section].bars[bar - 1].subdivision - 1),
+                ..cursor
+            },
+        }
+    }
+    // the bar after in the same column, the last bar of a section goes to
+    // its end instead
+    fn next_bar(&self, cursor: CursorPos) -> CursorPos {
+        let bars = &self.sections[cursor.section].bars;
+        match bars.get(cursor.bar + 1) {
+            Some(next) => CursorPos {
+                bar: cursor.bar + 1,
+                subdivision: cursor.subdivision.min(next.subdivision - 1),
+                ..cursor
+            },
+            None => self.section_end(cursor.section),
+        }
+    }
+    // up moves a row within the section. the top row goes to its start
+    // first, then on to the previous section like prev_bar
+    fn row_above(&self, cursor: CursorPos) -> CursorPos {
+        let wrap = self.sections[cursor.section].effective_wrap();
+        match cursor.bar.checked_sub(wrap) {
+            Some(_) => (0..wrap).fold(cursor, |c, _| self.prev_bar(c)),
+            None if cursor.bar > 0 || cursor.subdivision > 0 => CursorPos {
+                bar: 0,
+                subdivision: 0,
+                ..cursor
+            },
+            None => self.prev_bar(cursor),
+        }
+    }
+    // down moves a row, the bottom row goes to the section's end
+    fn row_below(&self, cursor: CursorPos) -> CursorPos {
+        let wrap = self.sections[cursor.section].effective_wrap();
+        (0..wrap).fold(cursor, |c, _| self.next_bar(c))
+    }
+    // one subdivision back, across bars and sections
+    fn prev_subdivision(&self, cursor: CursorPos) -> CursorPos {
+        match (cursor.bar, cursor.subdivision) {
+            (_, 1..) => CursorPos {
+                subdivision: cursor.subdivision - 1,
+                ..cursor
+            },
+            (0, 0) if cursor.section > 0 => self.section_end(cursor.section - 1),
+            (0, 0) => cursor,
+            (bar, 0) => CursorPos {
+                bar: bar - 1,
+                subdivision: self.sections[cursor.section].bars[bar - 1].subdivision - 1,
+                ..cursor
+            },
+        }
+    }
+    // title lines plus the blank line under them
+    fn header_height(&self) -> i32 {
+        if self.subtitle.is_some() {
+            3
+        } else {
+            2
+        }
+    }
+    fn align_of(&self, settings: &Settings, section: &Section) -> Align {
+        section.align.unwrap_or(settings.align)
+    }
+    // widest cell per column across every globally aligned section
+    fn global_widths(&self, settings: &Settings, notation: Notation) -> Vec<usize> {
+        let mut widths: Vec<usize> = Vec::new();
+        for section in &self.sections {
+            if !matches!(self.align_of(settings, section), Align::Global) {
+                continue;
+            }
+            let other = section.column_widths(settings, notation, section.effective_wrap());
+            if other.len() > widths.len() {
+                widths.resize(other.len(), 0);
+            }
+            for (w, o) in widths.iter_mut().zip(other) {
+                *w = o.max(*w);
+            }
+        }
+        widths
+    }
+    fn calc_widths(
+        &self,
+        settings: &Settings,
+        section: &Section,
+        notation: Notation,
+        global: &[usize],
+        wrap: usize,
+    ) -> Vec<usize> {
+        let mut widths = section.column_widths(settings, notation, wrap);
+        if matches!(self.align_of(settings, section), Align::Global) {
+            // widen to the maxima of every globally aligned section so barlines line up
+            for (w, o) in widths.iter_mut().zip(global) {
+                *w = (*o).max(*w);
+            }
+        }
+        widths
+    }
+    // rows with a marker or a time change over them, each takes a line above
+    fn marker_rows(&self, section_i: usize, wrap: usize) -> i32 {
+        let section = &self.sections[section_i];
+        section
+            .bars
+            .chunks(wrap)
+            .enumerate()
+            .filter(|(row_i, chunk)| {
+                chunk.iter().enumerate().any(|(col, bar)| {
+                    bar.marker.is_some()
+                        || self.time_change(section_i, row_i * wrap + col).is_some()
+                })
+            })
+            .count() as i32
+    }
+    // label, markers, bars and the blank line after
+    fn section_height(&self, settings: &Settings, section_i: usize, wrap: usize) -> i32 {
+        let rows = self.sections[section_i].rows(wrap) as i32;
+        settings.label_rows() + self.marker_rows(section_i, wrap) + rows + 1
+    }
+    // the section's wrap, cut down until its barlines fit in `width` columns
+    fn fit_wrap(
+        &self,
+        settings: &Settings,
+        notation: Notation,
+        section_i: usize,
+        outline: &Outline,
+    ) -> usize {
+        let section = &self.sections[section_i];
+        let mut wrap = section.effective_wrap();
+        let Some(width) = outline.width else {
+            return wrap;
+        };
+        while wrap > 1 {
+            let widths =
+                self.calc_widths(settings, section, notation, &outline.global_widths, wrap);
+            // the |: takes a column, then each bar its barline and cells
+            let widest = section
+                .bars
+                .chunks(wrap)
+                .map(|row| {
+                    row.iter()
+                        .zip(&widths)
+                        .map(|(bar, w)| 1 + bar.subdivision * w)
+                        .sum::<usize>()
+                })
+                .max()
+                .unwrap_or(0) as i32;
+            // where the closing barline goes, which has to be on screen too
+            let end_x = outline.gutter + section.repeats as i32 + widest;
+            if end_x < width {
+                break;
+            }
+            wrap -= 1;
+        }
+        wrap
+    }
+    // the labels in order, "A A B A", cut short with a count of the rest when
+    // they won't fit in `width` columns
+    fn form(&self, width: usize) -> String {
+        let mut form = String::new();
+        for (i, section) in self.sections.iter().enumerate() {
+            let next = match i {
+                0 => section.label.clone(),
+                _ => format!("{} {}", form, section.label),
+            };
+            // room to say how many didn't fit, should the next one not
+            let rest = self.sections.len() - i - 1;
+            let tail = match rest {
+                0 => 0,
+                _ => format!(" ... {} more", rest).len(),
+            };
+            if next.chars().count() + tail > width {
+                let sep = if form.is_empty() { "" } else { " " };
+                return format!("{}{}... {} more", form, sep, self.sections.len() - i);
+            }
+            form = next;
+        }
+        form
+    }
+    // `width` in columns, None to keep every section's own wrap
+    fn outline(&self, settings: &Settings, notation: Notation, width: Option<i32>) -> Outline {
+        let mut outline = Outline {
+            width,
+            ..Outline::default()
+        };
+        outline.refresh(self, settings, notation, 0);
+        outline
+    }
+    fn layout(&self, settings: &Settings, notation: Notation) -> Layout {
+        self.layout_at(settings, notation, None)
+    }
+    fn layout_at(&self, settings: &Settings, notation: Notation, width: Option<i32>) -> Layout {
+        let outline = self.outline(settings, notation, width);
+        self.layout_sections(settings, notation, &outline, 0..self.sections.len())
+    }
+    // just the sections in range, placed where the whole song would put them
+    fn layout_sections(
+        &self,
+        settings: &Settings,
+        notation: Notation,
+        outline: &Outline,
+        range: std::ops::Range<usize>,
+    ) -> Layout {
+        let gutter = outline.gutter;
+        let label_rows = settings.label_rows();
+        let mut sections = Vec::with_capacity(range.len());
+        for section_i in range.clone() {
+            let section = &self.sections[section_i];
+            let top = outline.tops[section_i] + label_rows;
+            let first_bar = outline.first_bars[section_i];
+            let wrap = outline.wraps[section_i];
+            let widths =
+                self.calc_widths(settings, section, notation, &outline.global_widths, wrap);
+            let mut ringing = outline.ringing[section_i];
+            let mut rows = Vec::new();
+            let mut marker_rows = 0;
+            for (row_i, chunk) in section.bars.chunks(wrap).enumerate() {
+                let times: Vec<Option<String>> = (0..chunk.len())
+                    .map(|col| self.time_change(section_i, row_i * wrap + col))
+                    .collect();
+                if chunk.iter().any(|b| b.marker.is_some()) || times.iter().any(Option::is_some) {
+                    marker_rows += 1;
+                }
+                let mut x = gutter;
+                let mut bars = Vec::with_capacity(chunk.len());
+                for (col, bar) in chunk.iter().enumerate() {
+                    let width = widths[col] as i32;
+                    let bar_x = x;
+                    x += 1; // barline
+                    if col == 0 && section.repeats {
+                        x += 1; // the : of |:
+                    }
+                    let mut cells = Vec::with_capacity(bar.subdivision);
+                    for subdivision in 0..bar.subdivision {
+                        let chord = bar.get_chord(subdivision);
+                        let pos = CursorPos {
+                            section: section_i,
+                            bar: row_i * wrap + col,
+                            subdivision,
+                        };
+                        let simile = chord.is_some_and(|c| c.repeat);
+                        let unresolved = simile && self.repeated_chord(pos).is_none();
+                        cells.push(CellLayout {
+                            subdivision,
+                            x,
+                            width,
+                            symbol: chord.map(|c| settings.glyphs(c.symbol(notation))),
+                            special: chord.is_some_and(|c| c.special),
+                            question: chord.is_some_and(|c| c.question),
+                            held: chord.is_none() && ringing,
+                            simile,
+                            unresolved,
+                        });
+                        if let Some(chord) = chord {
+                            ringing = !chord.no_chord;
+                        }
+                        x += width;
+                    }
+                    bars.push(BarLayout {
+                        bar: row_i * wrap + col,
+                        x: bar_x,
+                        cells,
+                        marker: bar.marker,
+                        time: times[col].clone(),
+                    });
+                }
+                rows.push(RowLayout {
+                    number: settings.bar_numbers.then_some(first_bar + row_i * wrap),
+                    y: top + row_i as i32 + marker_rows,
+                    end_x: x,
+                    bars,
+                });
+            }
+            sections.push(SectionLayout {
+                label: section.label.clone(),
+                label_y: top - label_rows,
+                vamp: section.vamp,
+                repeats: section.repeats,
+                rows,
+            });
+        }
+        Layout {
+            width: outline.width,
+            gutter,
+            first: range.start,
+            sections,
+        }
+    }
+    // draws the laid out sections, only the lines in `rows`. colors are
+    // for the chords' functions in the key
+    fn paint(
+        &self,
+        grid: &mut impl Grid,
+        settings: &Settings,
+        layout: &Layout,
+        cursor: CursorPos,
+        colors: bool,
+        rows: std::ops::Range<i32>,
+    ) {
+        let visible = |y: i32| rows.contains(&y);
+        for (section_i, placed) in (layout.first..).zip(&layout.sections) {
+            let section = &self.sections[section_i];
+
+            match settings.section_headers {
+                _ if !visible(placed.label_y) => {}
+                HeaderStyle::Line => {
+                    let label = format!("[{}]{}", section.label, section.label_note());
+                    grid.put(placed.label_y, 0, &label);
+                }
+                HeaderStyle::Inline => {
+                    grid.put(placed.label_y, 0, &format!("[{}]", section.label));
+                }
+                HeaderStyle::Margin => {
+                    // label runs down the gutter, cut short on sections with few rows
+                    for (c, row) in section.label.chars().zip(&placed.rows) {
+                        if visible(row.y) {
+                            grid.put(row.y, 0, &c.to_string());
+                        }
+                    }
+                }
+            }
+            for row in placed.rows.iter().filter(|r| visible(r.y)) {
+                for (bar_i, bar) in row.bars.iter().enumerate() {
+                    if let Some(time) = bar.time.as_ref().filter(|_| visible(row.y - 1)) {
+                        grid.put(row.y - 1, bar.x + 1, time);
+                    }
+                    if let Some(marker) = bar.marker.filter(|_| visible(row.y - 1)) {
+                        grid.put(row.y - 1, bar.marker_x(marker), &marker.to_string());
+                    }
+                    grid.put(row.y, bar.x, "|");
+                    if placed.repeats && bar_i == 0 {
+                        let first_row = row.y == placed.rows[0].y;
+                        grid.put(row.y, bar.x + 1, if first_row { ":" } else { " " });
+                    }
+                    let cursor_bar = cursor.section == section_i && cursor.bar == bar.bar;
+                    for cell in &bar.cells {
+                        let selected = cursor_bar && cursor.subdivision == cell.subdivision;
+                        let color = self
+                            .key
+                            .filter(|_| colors)
+                            .and_then(|key| {
+                                section.bars[bar.bar]
+                                    .get_chord(cell.subdivision)?
+                                    .function(key)
+                            })
+                            .map(|f| chtype::from(ColorPair(f as u8)));
+                        let attrs = [
+                            color,
+                            selected.then_some(chtype::from(Attribute::Reverse)),
+                            cell.unresolved.then_some(chtype::from(Attribute::Dim)),
+                        ];
+                        for attr in attrs.iter().flatten() {
+                            grid.attr(*attr, true);
+                        }
+
+                        let col_width = cell.width as usize;
+                        let text = match &cell.symbol {
+                            Some(chord_str) => chord_str.as_str(),
+                            None if cursor_bar => ".",
+                            None => "",
+                        };
+                        // padded out to the column
+                        grid.put(row.y, cell.x, &format!("{:<1$}", text, col_width));
+
+                        for attr in attrs.iter().rev().flatten() {
+                            grid.attr(*attr, false);
+                        }
+                    }
+                }
+                grid.put(row.y, row.end_x, "|"); // terminating
+                if let (Some(number), Some(first)) = (row.number, row.bars.first()) {
+                    let number = number.to_string();
+                    grid.put(row.y, first.x - number.len() as i32 - 1, &number);
+                }
+            }
+            if let Some(last) = placed.rows.last().filter(|r| visible(r.y)) {
+                if placed.vamp {
+                    grid.put(last.y, last.end_x, ":| until cue");
+                } else if let Some(mark) = section.closing_mark() {
+                    grid.put(last.y, last.end_x, &mark);
+                }
+            }
+        }
+    }
+    // a pipe table per section, a column per bar up to the wrap
+    fn to_markdown(&self, settings: &Settings) -> String {
+        let mut md = format!("# {}\n", self.title);
+        if let Some(subtitle) = &self.subtitle {
+            md.push_str(&format!("\n{}\n", subtitle));
+        }
+        let mut blocks = vec![md.lines().map(String::from).collect()];
+        for section in &self.sections {
+            let mut md = format!("\n## {}{}\n\n", section.label, section.label_note());
+            let wrap = section.effective_wrap();
+            let numbers = (1..=wrap).map(|n| n.to_string()).collect::<Vec<String>>();
+            md.push_str(&format!("| {} |\n", numbers.join(" | ")));
+            md.push_str(&format!("|{}\n", "---|".repeat(wrap)));
+            for chunk in section.bars.chunks(wrap) {
+                let mut cells = chunk
+                    .iter()
+                    .map(|bar| settings.glyphs(bar.chords_string()))
+                    .collect::<Vec<String>>();
+                cells.resize(wrap, String::new());
+                md.push_str(&format!("| {} |\n", cells.join(" | ")));
+            }
+            blocks.push(md.lines().map(String::from).collect());
+        }
+        // most markdown to pdf converters break pages on this
+        let pages = self.print_pages(settings, blocks, |line| line.to_string());
+        let pages = pages.into_iter().map(|page| page.join("\n") + "\n");
+        pages
+            .collect::<Vec<String>>()
+            .join("\n<div style=\"page-break-after: always;\"></div>\n\n")
+    }
+    // a {start_of_grid} per section, cells as chords or . like the editor
+    fn to_chordpro(&self, settings: &Settings) -> String {
+        let mut cho = format!("{{title: {}}}\n", self.title);
+        if let Some(subtitle) = &self.subtitle {
+            cho.push_str(&format!("{{subtitle: {}}}\n", subtitle));
+        }
+        if let Some(key) = self.key {
+            cho.push_str(&format!("{{key: {}}}\n", key));
+        }
+        let mut blocks = vec![cho.lines().map(String::from).collect()];
+        for section in &self.sections {
+            let mut cho = format!(
+                "\n{{start_of_grid label=\"{}{}\"}}\n",
+                section.label,
+                section.label_note()
+            );
+            let rows = section.bars.chunks(section.effective_wrap()).count();
+            for (row_i, chunk) in section.bars.chunks(section.effective_wrap()).enumerate() {
+                let mut line = String::new();
+                for (bar_i, bar) in chunk.iter().enumerate() {
+                    let open = section.repeats && row_i == 0 && bar_i == 0;
+                    line.push_str(if open { "|: " } else { "| " });
+                    for sub in 0..bar.subdivision {
+                        match bar.get_chord(sub) {
+                            Some(chord) => line.push_str(&chord.to_string()),
+                            None => line.push('.'),
+                        }
+                        line.push(' ');
+                    }
+                }
+                let close = (section.repeats || section.vamp) && row_i + 1 == rows;
+                line.push_str(if close { ":|" } else { "|" });
+                cho.push_str(&line);
+                cho.push('\n');
+            }
+            cho.push_str("{end_of_grid}\n");
+            blocks.push(cho.lines().map(String::from).collect());
+        }
+        let pages = self.print_pages(settings, blocks, |line| format!("{{comment: {}}}", line));
+        let pages = pages.into_iter().map(|page| page.join("\n") + "\n");
+        pages.collect::<Vec<String>>().join("{new_page}\n")
+    }
+    // draw() without curses, onto a grid of plain characters
+    fn to_text(&self, settings: &Settings) -> String {
+        let mut lines: Vec<Vec<char>> = Vec::new();
+        let mut put = |y: i32, x: i32, s: &str| {
+            let (y, x) = (y as usize, x as usize);
+            if lines.len() <= y {
+                lines.resize(y + 1, Vec::new());
+            }
+            let line = &mut lines[y];
+            for (i, c) in s.chars().enumerate() {
+                if line.len() <= x + i {
+                    line.resize(x + i + 1, ' ');
+                }
+                line[x + i] = c;
+            }
+        };
+        put(0, 0, &format!("SONG: {}", self.title));
+        if let Some(key) = self.key {
+            put(
+                0,
+                6 + self.title.chars().count() as i32,
+                &format!("  ({})", settings.glyphs(key.to_string())),
+            );
+        }
+        if let Some(subtitle) = &self.subtitle {
+            put(1, 6, subtitle);
+        }
+        let layout = self.layout(settings, Notation::Letters);
+        for (section, placed) in self.sections.iter().zip(&layout.sections) {
+            match settings.section_headers {
+                HeaderStyle::Line => put(
+                    placed.label_y,
+                    0,
+                    &format!("[{}]{}", section.label, section.label_note()),
+                ),
+                HeaderStyle::Inline => put(placed.label_y, 0, &format!("[{}]", section.label)),
+                HeaderStyle::Margin => {
+                    for (c, row) in section.label.chars().zip(&placed.rows) {
+                        put(row.y, 0, &c.to_string());
+                    }
+                }
+            }
+            for row in &placed.rows {
+                for (bar_i, bar) in row.bars.iter().enumerate() {
+                    let open = placed.repeats && bar_i == 0 && row.y == placed.rows[0].y;
+                    put(row.y, bar.x, if open { "|:" } else { "|" });
+                    if let Some(time) = &bar.time {
+                        put(row.y - 1, bar.x + 1, time);
+                    }
+                    if let Some(marker) = bar.marker {
+                        put(row.y - 1, bar.marker_x(marker), &marker.to_string());
+                    }
+                    for cell in &bar.cells {
+                        if let Some(symbol) = &cell.symbol {
+                            put(row.y, cell.x, symbol);
+                        }
+                    }
+                }
+                if let (Some(number), Some(first)) = (row.number, row.bars.first()) {
+                    let number = number.to_string();
+                    put(row.y, first.x - number.len() as i32 - 1, &number);
+                }
+                put(row.y, row.end_x, "|");
+            }
+            if let Some(last) = placed.rows.last() {
+                if placed.vamp {
+                    put(last.y, last.end_x, ":| until cue");
+                } else if let Some(mark) = section.closing_mark() {
+                    put(last.y, last.end_x, &mark);
+                }
+            }
+        }
+        let mut lines = lines
+            .into_iter()
+            .map(|line| line.into_iter().collect::<String>().trim_end().to_string())
+            .collect::<Vec<String>>();
+        // the title, then each section from its label down to the next one
+        let mut blocks = Vec::new();
+        for placed in layout.sections.iter().rev() {
+            let from = (placed.label_y as usize).min(lines.len());
+            blocks.push(lines.split_off(from));
+        }
+        blocks.push(lines);
+        blocks.reverse();
+        let pages = self.print_pages(settings, blocks, |line| line.to_string());
+        let pages = pages.into_iter().map(|page| page.join("\n"));
+        // a form feed starts each new page
+        pages.collect::<Vec<String>>().join("\n\x0c")
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Section {
+    label: String,
+    bars: Vec<Bar>,
+    repeats: bool,
+    // times through when repeats is set, older files only had the flag
+    // and meant twice
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    repeat_count: usize,
+    // repeats until cued, so no fixed count
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    vamp: bool,
+    wrap: usize, // bars
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    align: Option<Align>, // overrides the :set align option
+}
+
+impl Section {
+    // wrap as used for layout and motion, the stored value is left alone
+    fn effective_wrap(&self) -> usize {
+        self.wrap.min(self.bars.len()).max(1)
+    }
+    // `key` is the one the section ends up in, see Chord::transposed
+    fn transpose(&mut self, semitones: i32, spelling: Spelling, key: Option<Key>) {
+        for bar in &mut self.bars {
+            for chord in bar.chords.values_mut() {
+                *chord = chord.transposed(semitones, spelling, key);
+            }
+        }
+    }
+    // whether a chord is still sounding after the section, N.C. stops it
+    fn rings_on(&self, ringing: bool) -> bool {
+        let last = self.bars.iter().flat_map(|b| b.chords.values()).last();
+        last.map_or(ringing, |chord| !chord.no_chord)
+    }
+    fn rows(&self, wrap: usize) -> usize {
+        self.bars.len().saturating_sub(1) / wrap + 1
+    }
+    fn summary(&self) -> String {
+        format!(
+            "[{}] {} bars{}{}",
+            self.label,
+            self.bars.len(),
+            match self.plays() {
+                1 => String::new(),
+                2 => ", repeats".to_string(),
+                n => format!(", x{}", n),
+            },
+            if self.vamp { ", vamp" } else { "" }
+        )
+    }
+    fn plays(&self) -> usize {
+        if self.repeats {
+            self.repeat_count.max(2)
+        } else {
+            1
+        }
+    }
+    // the closing repeat barline, with the count when it's more than twice
+    fn closing_mark(&self) -> Option<String> {
+        match self.plays() {
+            1 => None,
+            2 => Some(":|".to_string()),
+            n => Some(format!(":| x{}", n)),
+        }
+    }
+    // shown after the label where there's room for it
+    fn label_note(&self) -> &'static str {
+        if self.vamp {
+            " (vamp)"
+        } else {
+            ""
+        }
+    }
+    // breaks up bars holding more than `max` chords, keeping their order,
+    // returns how many bars were added
+    fn split_oversized_bars(&mut self, max: usize) -> usize {
+        if max == 0 {
+            return 0;
+        }
+        let mut added = 0;
+        let mut bars = Vec::with_capacity(self.bars.len());
+        for bar in self.bars.drain(..) {
+            if bar.chords.len() <= max {
+                bars.push(bar);
+                continue;
+            }
+            let chords = bar.chords.into_values().collect::<Vec<Chord>>();
+            added += chords.len().div_ceil(max) - 1;
+            let mut marker = bar.marker;
+            for chunk in chords.chunks(max) {
+                // one cell per beat, finer if the chunk doesn't fit that way
+                let mut subdivision = bar.beats.max(1);
+                while subdivision < chunk.len() {
+                    subdivision *= 2;
+                }
+                let mut new = Bar::new(bar.beats, subdivision);
+                let positions = distribute_chords(0, chunk.len(), bar.beats, subdivision)
+                    .unwrap_or_else(|| (0..chunk.len()).collect());
+                new.chords = positions.into_iter().zip(chunk.iter().cloned()).collect();
+                new.marker = marker.take();
+                bars.push(new);
+            }
+        }
+        self.bars = bars;
+        added
+    }
+    // cell width per column, looking at this section only
+    fn column_widths(&self, settings: &Settings, notation: Notation, wrap: usize) -> Vec<usize> {
+        let mut widths = vec![0; wrap];
+
+        for (i, bar) in self.bars.iter().enumerate() {
+            let idx = i % wrap;
+            for subdivision in 0..bar.subdivision {
+                if let Some(chord) = bar.get_chord(subdivision) {
+                    // measured as the layout will draw it
+                    let chord_str = format!("{} ", settings.glyphs(chord.symbol(notation)));
+                    widths[idx] = chord_str.chars().count().max(widths[idx]);
+                } else {
+                    widths[idx] = 2.max(widths[idx]); // minimum width
+                }
+            }
+        }
+        widths
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Bar {
+    beats: usize,
+    subdivision: usize,
+    chords: BTreeMap<usize, Chord>, // position in subdivisions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    marker: Option<Marker>,
+}
+
+// harmonic function, each its own color pair in the editor
+#[derive(Clone, Copy)]
+enum Function {
+    Tonic = 1,
+    Subdominant,
+    Dominant,
+}
+
+impl Function {
+    fn init_colors() {
+        init_pair(Function::Tonic as i16, COLOR_GREEN, -1);
+        init_pair(Function::Subdominant as i16, COLOR_YELLOW, -1);
+        init_pair(Function::Dominant as i16, COLOR_RED, -1);
+    }
+}
+
+// road-map signs written over a bar
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Marker {
+    Segno,
+    Coda,
+    DaCapo,
+    DalSegno,
+    DaCapoAlFine,
+    Fine,
+}
+
+impl Marker {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "segno" => Some(Marker::Segno),
+            "coda" => Some(Marker::Coda),
+            "dc" => Some(Marker::DaCapo),
+            "ds" => Some(Marker::DalSegno),
+            "dcfine" => Some(Marker::DaCapoAlFine),
+            "fine" => Some(Marker::Fine),
+            _ => None,
+        }
+    }
+    // jumps and Fine are read on the way out of a bar, so they sit over its end
+    fn at_end(&self) -> bool {
+        !matches!(self, Marker::Segno | Marker::Coda)
+    }
+}
+
+impl Display for Marker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Marker::Segno => "Segno",
+            Marker::Coda => "Coda",
+            Marker::DaCapo => "D.C.",
+            Marker::DalSegno => "D.S. al Coda",
+            Marker::DaCapoAlFine => "D.C. al Fine",
+            Marker::Fine => "Fine",
+        })
+    }
+}
+
+impl Default for Bar {
+    fn default() -> Self {
+        Bar {
+            beats: 4,
+            subdivision: 4,
+            chords: BTreeMap::new(),
+            marker: None,
+        }
+    }
+}
+
+impl Bar {
+    fn new(beats: usize, subdivision: usize) -> Self {
+        Bar {
+            beats,
+            subdivision,
+            chords: BTreeMap::new(),
+            marker: None,
+        }
+    }
+    // the chord still ringing at a subdivision, i.e. the last one at or before it
+    fn sounding_at(&self, subdivision: usize) -> Option<&Chord> {
+        self.chords
+            .range(..=subdivision)
+            .next_back()
+            .map(|(_, c)| c)
+    }
+    fn chords_string(&self) -> String {
+        self.chords
+            .values()
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+    fn get_chord(&self, subdivision: usize) -> Option<&Chord> {
+        for (i, c) in &self.chords {
+            if *i == subdivision {
+                return Some(c);
+            }
+        }
+        None
+    }
+    // every meter change goes through here so no chord is lost by accident.
+    // Keep leaves positions alone, Rescale moves them proportionally; chords
+    // that fall off the end or land on each other are refused unless
+    // `truncate`, then they're dropped and handed back
+    fn set_meter(
+        &mut self,
+        beats: usize,
+        subdivision: usize,
+        retime: Retime,
+        truncate: bool,
+    ) -> Result<Vec<Chord>, MeterError> {
+        if beats == 0 || !(1..=16).contains(&subdivision) {
+            return Err(MeterError::OutOfRange);
+        }
+        let mut chords = BTreeMap::new();
+        let mut conflicts = Vec::new();
+        let mut dropped = Vec::new();
+        for (&i, chord) in &self.chords {
+            let new_i = match retime {
+                Retime::Keep => i,
+                Retime::Rescale => i * subdivision / self.subdivision,
+            };
+            if new_i >= subdivision || chords.contains_key(&new_i) {
+                conflicts.push(i);
+                dropped.push(chord.clone());
+            } else {
+                chords.insert(new_i, chord.clone());
+            }
+        }
+        if !conflicts.is_empty() && !truncate {
+            return Err(MeterError::Conflicts(conflicts));
+        }
+        self.beats = beats;
+        self.subdivision = subdivision;
+        self.chords = chords;
+        Ok(dropped)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Retime {
+    Keep,
+    Rescale,
+}
+
+enum MeterError {
+    OutOfRange,
+    Conflicts(Vec<usize>), // positions that wouldn't survive
+}
+
+impl Display for MeterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeterError::OutOfRange => write!(f, "bars take 1 to 16 subdivisions"),
+            MeterError::Conflicts(positions) => write!(
+                f,
+                "chords at {} wouldn't fit",
+                positions
+                    .iter()
+                    .map(|p| (p + 1).to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Serialize, Deserialize)]
+struct Chord {
+    note: Note,
+    accidental: Accidental,
+    quality: Quality,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extensions: Vec<Extension>,
+    over: Option<Note>,
+    #[serde(default)]
+    over_accidental: Accidental,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alterations: Vec<Alteration>, // in the order they were typed
+    // written as C7(b9,#11) rather than C7b9#11
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    parenthesized: bool,
+    // N.C., a deliberate gap; note and quality are ignored
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    no_chord: bool,
+    // %, whatever came before; note and quality are ignored
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    repeat: bool,
+    special: bool,
+    question: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    question_note: Option<String>, // why it's flagged
+}
+
+// parentheses and the order of the alterations are only how the chord was
+// written, C7(b9) is C7b9 and C7b9#5 is C7#5b9
+impl PartialEq for Chord {
+    fn eq(&self, other: &Self) -> bool {
+        self.identity() == other.identity()
+    }
+}
+
+impl Hash for Chord {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.identity().hash(state);
+    }
+}
+
+impl Chord {
+    #[allow(clippy::type_complexity)]
+    fn identity(
+        &self,
+    ) -> (
+        (
+            Note,
+            Accidental,
+            Quality,
+            &[Extension],
+            Vec<(u8, Accidental)>,
+        ),
+        (Option<Note>, Accidental, bool, bool),
+        (bool, bool, &Option<String>),
+    ) {
+        let Chord {
+            note,
+            accidental,
+            quality,
+            extensions,
+            over,
+            over_accidental,
+            alterations,
+            parenthesized: _,
+            no_chord,
+            repeat,
+            special,
+            question,
+            question_note,
+        } = self;
+        let mut alterations: Vec<_> = alterations
+            .iter()
+            .map(|a| (a.degree, a.accidental))
+            .collect();
+        alterations.sort_by_key(|&(degree, accidental)| (degree, accidental as u8));
+        (
+            (*note, *accidental, *quality, extensions, alterations),
+            (*over, *over_accidental, *no_chord, *repeat),
+            (*special, *question, question_note),
+        )
+    }
+}
+
+impl Chord {
+    fn parse(s: &str) -> Result<Self, ChordParseError> {
+        if s.is_empty() {
+            return Err(ChordParseError::Empty);
+        }
+        let bare = s.trim_end_matches(['!', '?']);
+        let no_chord = bare.eq_ignore_ascii_case("nc") || bare.eq_ignore_ascii_case("n.c.");
+        if no_chord || bare == "%" {
+            let flags = &s[bare.len()..];
+            return Ok(Chord {
+                note: Note::C,
+                accidental: Accidental::None,
+                quality: Quality::Maj,
+                extensions: Vec::new(),
+                over: None,
+                over_accidental: Accidental::None,
+                alterations: Vec::new(),
+                parenthesized: false,
+                no_chord,
+                repeat: !no_chord,
+                special: flags.contains('!'),
+                question: flags.contains('?'),
+                question_note: None,
+            });
+        }
+        // silly regex i partially stole from some random place (https://regex101.com/r/T5GuGD/1 is my copy)
+        // groups:
+        // 1. note
+        // 2. accidental
+        // 3. combined quality + extensions (we use)
+        // 4. quality alone
+        // 5. extensions alone
+        // 6. alterations stacked after that, b9#5 etc, or added tones like add11
+        // 7. alterations in parentheses, (b9,#11)
+        // 8. over
+        // 9. special (!)
+        // 10. question (?)
+        // compiled once, parse runs for every chord typed, pasted or spread
+        static CHORD: OnceLock<Regex> = OnceLock::new();
+        static ALTERATION: OnceLock<Regex> = OnceLock::new();
+        static ALTERATIONS: OnceLock<Regex> = OnceLock::new();
+        let re = CHORD.get_or_init(|| Regex::new(r"^([CDEFGABcdefgab])([#b])?((maj|min|dom|Δ|M|-|\+|\^|m|o|ø|aug|dim|7sus|sus|add|hd)?(7alt|7b9|7#9|7#11|7b13|6/9|69|6|7|9|11|13|5|4|2)?)((?:[#b](?:5|9|11|13)|add(?:9|11|13))*)(\([^)]*\))?(/[CDEFGABcdefgab][#b]?)?(!)?(\?)?").unwrap());
+        let caps = re
+            .captures(s)
+            .ok_or_else(|| ChordParseError::BadRoot(s.chars().next().unwrap()))?;
+        // the longest chord at the start, work out why the rest didn't fit
+        let rest = &s[caps.get(0).unwrap().end()..];
+        if let Some(bass) = rest.strip_prefix('/') {
+            return Err(ChordParseError::BadBassNote(
+                bass.chars().next().unwrap_or(' '),
+            ));
+        } else if rest.starts_with('(') {
+            return Err(ChordParseError::BadAlteration(rest.to_string()));
+        } else if !rest.is_empty()
+            && caps.get(7).is_none()
+            && caps.get(8).is_none()
+            && caps.get(9).is_none()
+            && caps.get(10).is_none()
+        {
+            let quality = &s[caps.get(3).unwrap().start()..];
+            return Err(ChordParseError::UnknownQuality(quality.to_string()));
+        } else if !rest.is_empty() {
+            return Err(ChordParseError::TrailingGarbage(rest.to_string()));
+        }
+
+        let note_s = caps.get(1).unwrap();
+        let note = Note::try_from(note_s.as_str().chars().nth(0).unwrap()).unwrap();
+        let accidental = if let Some(accidental_s) = caps.get(2) {
+            match accidental_s.as_str() {
+                "#" => Accidental::Sharp,
+                "b" => Accidental::Flat,
+                _ => unreachable!(),
+            }
+        } else {
+            Accidental::None
+        };
+
+        // the 7b9 style extensions only stand alone, after a quality they're
+        // its seventh plus an alteration, -7b9 is a min7 with a flat nine
+        let extension = caps.get(5).map_or("", |e| e.as_str());
+        let (extension, tension) = match extension {
+            "7b9" | "7#9" | "7#11" | "7b13" => ("7", &extension[1..]),
+            _ => (extension, ""),
+        };
+        // the long spellings from other tools, onto the terse ones below
+        let spelled = match caps.get(4).map(|q| q.as_str()) {
+            Some("maj") if extension.is_empty() => Some(String::new()),
+            Some("maj" | "Δ") => Some(format!("^{}", extension)),
+            Some("min") => Some(format!("-{}", extension)),
+            Some("dom") if !extension.is_empty() => Some(extension.to_string()),
+            _ => None,
+        };
+        // the half-diminished and add spellings name an alteration too, it
+        // goes first as if it had been typed right after the quality
+        use Extension::*;
+        let mut implied = None;
+        let (quality, extensions) = if caps.get(3).is_some() {
+            let quality_s = format!("{}{}", caps.get(4).map_or("", |q| q.as_str()), extension);
+            match spelled.unwrap_or(quality_s).as_str() {
+                "" => (Quality::Maj, vec![]),
+                "-" | "m" => (Quality::Min, vec![]),
+                "+" | "aug" => (Quality::Aug, vec![]),
+                "+7" | "aug7" => (Quality::Aug, vec![Seventh]),
+                "7" => (Quality::Dom, vec![]),
+                "-7" | "m7" => (Quality::Min, vec![Seventh]),
+                "^" | "^7" | "M7" => (Quality::Maj, vec![Seventh]),
+                "dim" | "o" => (Quality::Dim, vec![]),
+                "dim7" | "o7" => (Quality::Dim, vec![Seventh]),
+                "hd" | "ø" | "ø7" => {
+                    implied = Some("b5".to_string());
+                    (Quality::Min, vec![Seventh])
+                }
+                "6" => (Quality::Maj, vec![Sixth]),
+                "m6" | "-6" => (Quality::Min, vec![Sixth]),
+                "6/9" | "69" => (Quality::Maj, vec![Sixth, Ninth]),
+                "m6/9" | "-6/9" | "m69" | "-69" => (Quality::Min, vec![Sixth, Ninth]),
+                "9" => (Quality::Dom, vec![Ninth]),
+                "^9" | "M9" => (Quality::Maj, vec![Ninth]),
+                "-9" | "m9" => (Quality::Min, vec![Ninth]),
+                "11" => (Quality::Dom, vec![Eleventh]),
+                "^11" | "M11" => (Quality::Maj, vec![Eleventh]),
+                "-11" | "m11" => (Quality::Min, vec![Eleventh]),
+                "13" => (Quality::Dom, vec![Thirteenth]),
+                "^13" | "M13" => (Quality::Maj, vec![Thirteenth]),
+                "-13" | "m13" => (Quality::Min, vec![Thirteenth]),
+                "7alt" => (Quality::Dom, vec![Alt]),
+                "sus" | "sus4" => (Quality::Sus, vec![]),
+                "7sus" | "7sus4" => (Quality::Sus, vec![Seventh]),
+                "sus2" => (Quality::Sus, vec![Second]),
+                added @ ("add9" | "add11" | "add13") => {
+                    implied = Some(added.to_string());
+                    (Quality::Maj, vec![])
+                }
+
+                // TODO
+                other => return Err(ChordParseError::UnknownQuality(other.to_string())),
+            }
+        } else {
+            (Quality::Maj, vec![])
+        };
+        let parenthesized = caps.get(7).map(|p| p.as_str().trim_matches(['(', ')']));
+        let mut listed = String::new();
+        if let Some(list) = parenthesized {
+            if list.trim().is_empty() {
+                return Err(ChordParseError::BadAlteration(String::new()));
+            }
+            let one = ALTERATION
+                .get_or_init(|| Regex::new(r"^(?:[#b](?:5|9|11|13)|add(?:9|11|13))$").unwrap());
+            for item in list.split(',').map(str::trim) {
+                if !one.is_match(item) {
+                    return Err(ChordParseError::BadAlteration(item.to_string()));
+                }
+                listed.push_str(item);
+            }
+        }
+        let alterations: Vec<Alteration> = ALTERATIONS
+            .get_or_init(|| Regex::new(r"([#b]|add)(5|9|11|13)").unwrap())
+            .captures_iter(&format!(
+                "{}{}{}{}",
+                implied.as_deref().unwrap_or(""),
+                tension,
+                caps.get(6).map_or("", |a| a.as_str()),
+                listed
+            ))
+            .map(|a| Alteration {
+                accidental: match &a[1] {
+                    "#" => Accidental::Sharp,
+                    "b" => Accidental::Flat,
+                    _ => Accidental::None,
+                },
+                degree: a[2].parse().unwrap(),
+            })
+            .collect();
+        let over = caps
+            .get(8)
+            .and_then(|over_s| over_s.as_str().chars().nth(1))
+            .map(|c| Note::try_from(c).map_err(|_| ChordParseError::BadBassNote(c)))
+            .transpose()?;
+        let over_accidental = match caps
+            .get(8)
+            .and_then(|over_s| over_s.as_str().chars().nth(2))
+        {
+            Some('#') => Accidental::Sharp,
+            Some('b') => Accidental::Flat,
+            _ => Accidental::None,
+        };
+
+        Ok(Chord {
+            note,
+            accidental,
+            quality,
+            extensions,
+            over,
+            over_accidental,
+            alterations,
+            parenthesized: parenthesized.is_some(),
+            no_chord: false,
+            repeat: false,
+            special: caps.get(9).is_some(),
+            question: caps.get(10).is_some(),
+            question_note: None,
+        })
+    }
+    // the chord with only what sounds left, flags and notes dropped, so two
+    // normalized chords are equal when they're the same harmony. enharmonic
+    // also counts C#/Db (and Cb/B, E#/F) as the same root and bass
+    fn normalized(&self, enharmonic: bool) -> Self {
+        let mut chord = Chord {
+            special: false,
+            question: false,
+            question_note: None,
+            parenthesized: false,
+            ..self.clone()
+        };
+        if chord.no_chord || chord.repeat {
+            chord.note = Note::C;
+            chord.accidental = Accidental::None;
+            chord.quality = Quality::Maj;
+            chord.extensions.clear();
+            chord.over = None;
+            chord.over_accidental = Accidental::None;
+            chord.alterations.clear();
+        } else if enharmonic {
+            (chord.note, chord.accidental) =
+                Spelling::Sharps.spell(self.note.pitch_class(self.accidental));
+            if let Some(over) = self.over {
+                let (note, accidental) =
+                    Spelling::Sharps.spell(over.pitch_class(self.over_accidental));
+                chord.over = Some(note);
+                chord.over_accidental = accidental;
+            }
+        }
+        chord
+    }
+    fn toggle_question(&mut self) {
+        self.question = !self.question;
+        if !self.question {
+            self.question_note = None;
+        }
+    }
+    fn toggle_special(&mut self) {
+        self.special = !self.special;
+    }
+    // notes in the target key take its letters, Ab and Bb in Eb, Cb in Gb.
+    // the rest fall back to `spelling`
+    fn transposed(&self, semitones: i32, spelling: Spelling, key: Option<Key>) -> Self {
+        if self.no_chord || self.repeat {
+            return self.clone();
+        }
+        let spell = |pc: i32| {
+            key.and_then(|k| k.diatonic(pc))
+                .unwrap_or_else(|| spelling.spell(pc))
+        };
+        let (note, accidental) = spell(self.note.pitch_class(self.accidental) + semitones);
+        let (over, over_accidental) = match self.over {
+            Some(over) => {
+                let (n, a) = spell(over.pitch_class(self.over_accidental) + semitones);
+                (Some(n), a)
+            }
+            None => (None, Accidental::None),
+        };
+        Chord {
+            note,
+            accidental,
+            over,
+            over_accidental,
+            ..self.clone()
+        }
+    }
+    // `s` was typed with a lowercase root and no quality of its own, so the
+    // chord becomes the minor one. the root stays an uppercase Note
+    fn lowercase_minor(mut self, s: &str) -> Self {
+        let mut chars = s.chars();
+        if !chars.next().is_some_and(|c| c.is_ascii_lowercase()) {
+            return self;
+        }
+        let after_root = chars.as_str();
+        let after_root = after_root
+            .strip_prefix(['#', 'b'])
+            .filter(|_| self.accidental != Accidental::None)
+            .unwrap_or(after_root);
+        let explicit = [
+            "M", "-", "+", "^", "m", "o", "ø", "Δ", "aug", "dim", "dom", "sus", "add", "hd",
+        ]
+        .iter()
+        .any(|q| after_root.starts_with(q));
+        if !explicit {
+            match (self.quality, self.extensions.as_slice()) {
+                (Quality::Maj, _) => self.quality = Quality::Min,
+                (Quality::Dom, []) => {
+                    self.quality = Quality::Min;
+                    self.extensions = vec![Extension::Seventh];
+                }
+                (
+                    Quality::Dom,
+                    [Extension::Ninth | Extension::Eleventh | Extension::Thirteenth],
+                ) => self.quality = Quality::Min,
+                _ => {}
+            }
+        }
+        self
+    }
+    // a <harmony> frame, kind text keeps our own symbol for the quality
+    fn to_musicxml(&self) -> String {
+        let step_alter = |note: Note, accidental: Accidental, tag: &str| {
+            let alter = match accidental {
+                Accidental::None => String::new(),
+                Accidental::Sharp => format!("<{}-alter>1</{}-alter>", tag, tag),
+                Accidental::Flat => format!("<{}-alter>-1</{}-alter>", tag, tag),
+            };
+            format!("<{}-step>{}</{}-step>{}", tag, note, tag, alter)
+        };
+        let mut xml = String::from("      <harmony>");
+        if self.no_chord {
+            xml.push_str("<root><root-step>C</root-step></root><kind text=\"N.C.\">none</kind>");
+        } else {
+            let (kind, degrees) = self.musicxml_kind();
+            xml.push_str(&format!(
+                "<root>{}</root><kind text=\"{}\">{}</kind>",
+                step_alter(self.note, self.accidental, "root"),
+                self.quality_symbol(),
+                kind
+            ));
+            if let Some(over) = self.over {
+                xml.push_str(&format!(
+                    "<bass>{}</bass>",
+                    step_alter(over, self.over_accidental, "bass")
+                ));
+            }
+            let alterations = self.alterations.iter().map(|a| {
+                let alter = match a.accidental {
+                    Accidental::Sharp => 1,
+                    Accidental::Flat => -1,
+                    Accidental::None => 0,
+                };
+                (a.degree, alter, if a.degree == 5 { "alter" } else { "add" })
+            });
+            for (value, alter, kind) in degrees.iter().copied().chain(alterations) {
+                xml.push_str(&format!(
+                    "<degree><degree-value>{}</degree-value><degree-alter>{}</degree-alter><degree-type>{}</degree-type></degree>",
+                    value, alter, kind
+                ));
+            }
+        }
+        xml.push_str("</harmony>\n");
+        xml
+    }
+    // midi notes, close voicing from the root above middle C, the slash bass
+    // an octave below. N.C. is silent
+    fn voicing(&self) -> Vec<u8> {
+        if self.no_chord || self.repeat {
+            return Vec::new();
+        }
+        let root = 60 + self.note.pitch_class(self.accidental);
+        let mut intervals: Vec<i32> = std::iter::once(0)
+            .chain(self.tones().into_iter().map(|(_, semitones)| semitones))
+            .chain(self.extension_tone())
+            .collect();
+        for alteration in &self.alterations {
+            let natural = match alteration.degree {
+                5 => 7,
+                9 => 14,
+                11 => 17,
+                _ => 21,
+            };
+            if alteration.degree == 5 {
+                intervals.retain(|&i| i != 7);
+            }
+            intervals.push(match alteration.accidental {
+                Accidental::Sharp => natural + 1,
+                Accidental::Flat => natural - 1,
+                Accidental::None => natural,
+            });
+        }
+        let mut notes: Vec<u8> = intervals.iter().map(|i| (root + i) as u8).collect();
+        if let Some(over) = self.over {
+            notes.push((48 + over.pitch_class(self.over_accidental)) as u8);
+        }
+        notes.sort();
+        notes.dedup();
+        notes
+    }
+    // next bass in third, fifth, seventh, none; no bass, a root bass or one
+    // that isn't a chord tone goes to the third, since C/C is just C
+    fn inverted(&self, spelling: Spelling) -> Self {
+        if self.no_chord || self.repeat {
+            return self.clone();
+        }
+        let root = self.note.pitch_class(self.accidental);
+        let mut basses = vec![(self.note, self.accidental)];
+        for (steps, semitones) in self.tones() {
+            let letter = self.note.step(steps);
+            let wanted = (root + semitones).rem_euclid(12);
+            // keep the letter so a third of Eb is G, not F##
+            let bass = match (wanted - letter.pitch_class(Accidental::None)).rem_euclid(12) {
+                0 => (letter, Accidental::None),
+                1 => (letter, Accidental::Sharp),
+                11 => (letter, Accidental::Flat),
+                _ => spelling.spell(wanted),
+            };
+            basses.push(bass);
+        }
+        let next = match self.over {
+            None => basses.get(1),
+            Some(over) => {
+                let current = over.pitch_class(self.over_accidental);
+                match basses
+                    .iter()
+                    .position(|(n, a)| n.pitch_class(*a) == current)
+                {
+                    Some(i) => basses.get(i + 1),
+                    None => basses.get(1),
+                }
+            }
+        };
+        Chord {
+            over: next.map(|(n, _)| *n),
+            over_accidental: next.map(|(_, a)| *a).unwrap_or_default(),
+            ..self.clone()
+        }
+    }
+    // G/B becomes B/G, flagged since the quality was picked for the old root
+    fn swapped_bass(&self) -> Option<Self> {
+        let over = self.over?;
+        Some(Chord {
+            note: over,
+            accidental: self.over_accidental,
+            over: Some(self.note),
+            over_accidental: self.accidental,
+            question: true,
+            ..self.clone()
+        })
+    }
+}
+
+impl Display for Chord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        //dbg!(&self);
+        self.write_with(f, &|note, accidental| format!("{}{}", note, accidental))
+    }
+}
+
+impl Chord {
+    // the symbol as drawn, roots and basses become scale degrees in number mode
+    fn symbol(&self, notation: Notation) -> String {
+        match notation {
+            Notation::Letters => self.to_string(),
+            Notation::Numbers(key) => {
+                let mut symbol = String::new();
+                self.write_with(&mut symbol, &|note, accidental| {
+                    key.degree(note, accidental).to_string()
+                })
+                .unwrap();
+                symbol
+            }
+            Notation::Roman { key, unicode } => {
+                self.roman(key, unicode).unwrap_or_else(|| self.to_string())
+            }
+            Notation::Capo { fret, key } => {
+                let spelling = key.and_then(|k| k.spelling()).unwrap_or(Spelling::Sharps);
+                self.transposed(-fret, spelling, key).to_string()
+            }
+        }
+    }
+    // I iii vi rest, ii IV move away, V viio pull home; None off the scale
+    fn function(&self, key: Key) -> Option<Function> {
+        if self.no_chord || self.repeat {
+            return None;
+        }
+        match key.degree(self.note, self.accidental) {
+            "1" | "3" | "6" => Some(Function::Tonic),
+            "2" | "4" => Some(Function::Subdominant),
+            "5" | "7" => Some(Function::Dominant),
+            _ => None,
+        }
+    }
+    // IV, ii7, vii°7, iiø7, III+; None when the root isn't in the key. o and
+    // 7b5 stand in for ° and ø unless `unicode`, for terminals without UTF-8
+    fn roman(&self, key: Key, unicode: bool) -> Option<String> {
+        const NUMERALS: [&str; 7] = ["I", "II", "III", "IV", "V", "VI", "VII"];
+        if self.no_chord || self.repeat {
+            return None;
+        }
+        let degree: usize = key.degree(self.note, self.accidental).parse().ok()?;
+        let quality = self.quality_symbol();
+        let flat_five = Alteration {
+            accidental: Accidental::Flat,
+            degree: 5,
+        };
+        let half_diminished = unicode
+            && self.quality == Quality::Min
+            && self.extensions == [Extension::Seventh]
+            && self.alterations.contains(&flat_five);
+        let (minor, suffix) = match (self.quality, self.extensions.as_slice()) {
+            _ if half_diminished => (true, "ø7".to_string()),
+            (Quality::Min, _) => (true, quality.trim_start_matches(['-', 'm']).to_string()),
+            (Quality::Dim, _) if unicode => (true, quality.replace('o', "°")),
+            (Quality::Dim, _) => (true, quality.to_string()),
+            (Quality::Maj, [Extension::Seventh]) => (false, "maj7".to_string()),
+            (Quality::Maj, [Extension::Ninth | Extension::Eleventh | Extension::Thirteenth]) => {
+                (false, format!("maj{}", &quality[1..]))
+            }
+            _ => (false, quality.to_string()),
+        };
+        let numeral = NUMERALS[degree - 1];
+        let mut symbol = if minor {
+            numeral.to_lowercase()
+        } else {
+            numeral.to_string()
+        };
+        symbol.push_str(&suffix);
+        if half_diminished {
+            // the b5 is in the ø
+            let mut rest = self.clone();
+            rest.alterations.retain(|a| *a != flat_five);
+            symbol.push_str(&rest.alterations_text());
+        } else {
+            symbol.push_str(&self.alterations_text());
+        }
+        if let Some(over) = self.over {
+            symbol.push_str(&format!("/{}", key.degree(over, self.over_accidental)));
+        }
+        if self.special {
+            symbol.push('!');
+        }
+        if self.question {
+            symbol.push('?');
+        }
+        Some(symbol)
+    }
+    fn alterations_text(&self) -> String {
+        let each = self.alterations.iter().map(|a| a.to_string());
+        // straight after a bare root the #11 in C#11 would be the root's sharp
+        let bare = self.quality_symbol().is_empty()
+            && self
+                .alterations
+                .first()
+                .is_some_and(|a| a.accidental != Accidental::None);
+        if (self.parenthesized || bare) && !self.alterations.is_empty() {
+            format!("({})", each.collect::<Vec<String>>().join(","))
+        } else {
+            each.collect()
+        }
+    }
+    fn write_with(
+        &self,
+        f: &mut dyn std::fmt::Write,
+        name: &dyn Fn(Note, Accidental) -> String,
+    ) -> std::fmt::Result {
+        if self.repeat {
+            write!(f, "%")?;
+        } else if self.no_chord {
+            write!(f, "N.C.")?;
+        } else {
+            write!(
+                f,
+                "{}{}",
+                name(self.note, self.accidental),
+                self.quality_symbol()
+            )?;
+            write!(f, "{}", self.alterations_text())?;
+            if let Some(n) = self.over {
+                write!(f, "/{}", name(n, self.over_accidental))?;
+            }
+        }
+        if self.special {
+            write!(f, "!")?;
+        }
+        if self.question {
+            write!(f, "?")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ChordParseError {
+    Empty,
+    BadRoot(char),
+    UnknownQuality(String),
+    BadBassNote(char),
+    TrailingGarbage(String),
+    BadAlteration(String), // inside parentheses, empty for ()
+}
+
+impl Display for ChordParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChordParseError::Empty => write!(f, "no chord"),
+            ChordParseError::BadRoot(c) => write!(f, "'{}' isn't a note", c),
+            ChordParseError::UnknownQuality(q) => write!(f, "unknown quality '{}'", q),
+            ChordParseError::BadBassNote(c) => write!(f, "'{}' isn't a bass note", c),
+            ChordParseError::TrailingGarbage(rest) => write!(f, "'{}' after the chord", rest),
+            ChordParseError::BadAlteration(a) if a.is_empty() => write!(f, "empty parentheses"),
+            ChordParseError::BadAlteration(a) => write!(f, "unknown alteration '{}'", a),
+        }
+    }
+}
+
+// how chords are spelled on screen, numbers are relative to the song's key
+#[derive(Clone, Copy)]
+enum Notation {
+    Letters,
+    Numbers(Key),
+    // ° and ø for diminished and half-diminished with `unicode`, o and 7b5 without
+    Roman { key: Key, unicode: bool },
+    // shapes played with a capo on `fret`, spelled for the shape key if known
+    Capo { fret: i32, key: Option<Key> },
+}
+
+// the choice behind Notation, kept without a key so :key can change under it
+#[derive(Clone, Copy, PartialEq)]
+enum ChordDisplay {
+    Letters,
+    Numbers,
+    Roman,
+}
+
+// a raised or lowered tension stacked on the quality, the b9 in C7#5b9.
+// no accidental is an added tone, the add11 in Cm7add11
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Alteration {
+    accidental: Accidental,
+    degree: u8,
+}
+
+impl Display for Alteration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.accidental {
+            Accidental::None => write!(f, "add{}", self.degree),
+            accidental => write!(f, "{}{}", accidental, self.degree),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Note {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+}
+
+impl Note {
+    fn pitch_class(&self, accidental: Accidental) -> i32 {
+        let natural: i32 = match self {
+            Note::C => 0,
+            Note::D => 2,
+            Note::E => 4,
+            Note::F => 5,
+            Note::G => 7,
+            Note::A => 9,
+            Note::B => 11,
+        };
+        let offset = match accidental {
+            Accidental::None => 0,
+            Accidental::Sharp => 1,
+            Accidental::Flat => -1,
+        };
+        (natural + offset).rem_euclid(12)
+    }
+    // letter a number of scale steps up, D stepped 2 is F
+    fn step(&self, steps: usize) -> Note {
+        const LETTERS: [Note; 7] = [
+            Note::C,
+            Note::D,
+            Note::E,
+            Note::F,
+            Note::G,
+            Note::A,
+            Note::B,
+        ];
+        let i = LETTERS.iter().position(|n| n == self).unwrap();
+        LETTERS[(i + steps) % 7]
+    }
+}
+
+impl TryFrom<char> for Note {
+    type Error = ();
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value.to_ascii_uppercase() {
+            'A' => Ok(Self::A),
+            'B' => Ok(Self::B),
+            'C' => Ok(Self::C),
+            'D' => Ok(Self::D),
+            'E' => Ok(Self::E),
+            'F' => Ok(Self::F),
+            'G' => Ok(Self::G),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Note {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Note::A => 'A',
+                Note::B => 'B',
+                Note::C => 'C',
+                Note::D => 'D',
+                Note::E => 'E',
+                Note::F => 'F',
+                Note::G => 'G',
+            }
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Accidental {
+    #[default]
+    None,
+    Sharp,
+    Flat,
+}
+
+impl Display for Accidental {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if matches!(self, Accidental::None) {
+            return Ok(());
+        }
+        write!(
+            f,
+            "{}",
+            match self {
+                Accidental::None => unreachable!(),
+                Accidental::Sharp => '#',
+                Accidental::Flat => 'b',
+            }
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Spelling {
+    Sharps,
+    Flats,
+}
+
+impl Spelling {
+    fn spell(&self, pitch_class: i32) -> (Note, Accidental) {
+        use Accidental::*;
+        match (pitch_class.rem_euclid(12), self) {
+            (0, _) => (Note::C, None),
+            (1, Spelling::Sharps) => (Note::C, Sharp),
+            (1, Spelling::Flats) => (Note::D, Flat),
+            (2, _) => (Note::D, None),
+            (3, Spelling::Sharps) => (Note::D, Sharp),
+            (3, Spelling::Flats) => (Note::E, Flat),
+            (4, _) => (Note::E, None),
+            (5, _) => (Note::F, None),
+            (6, Spelling::Sharps) => (Note::F, Sharp),
+            (6, Spelling::Flats) => (Note::G, Flat),
+            (7, _) => (Note::G, None),
+            (8, Spelling::Sharps) => (Note::G, Sharp),
+            (8, Spelling::Flats) => (Note::A, Flat),
+            (9, _) => (Note::A, None),
+            (10, Spelling::Sharps) => (Note::A, Sharp),
+            (10, Spelling::Flats) => (Note::B, Flat),
+            (11, _) => (Note::B, None),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Key {
+    note: Note,
+    #[serde(default)]
+    accidental: Accidental,
+    #[serde(default)]
+    minor: bool,
+}
+
+impl Key {
+    fn parse(s: &str) -> Option<Self> {
+        static KEY: OnceLock<Regex> = OnceLock::new();
+        let caps = KEY
+            .get_or_init(|| Regex::new(r"^([A-Ga-g])([#b])?(m|-|min)?$").unwrap())
+            .captures(s)?;
+        Some(Key {
+            note: Note::try_from(caps[1].chars().next()?).ok()?,
+            accidental: match caps.get(2).map(|a| a.as_str()) {
+                Some("#") => Accidental::Sharp,
+                Some("b") => Accidental::Flat,
+                _ => Accidental::None,
+            },
+            minor: caps.get(3).is_some(),
+        })
+    }
+    // None for C major and A minor, which don't lean either way
+    fn spelling(&self) -> Option<Spelling> {
+        let pc = self.note.pitch_class(self.accidental);
+        let major = if self.minor { pc + 3 } else { pc }.rem_euclid(12);
+        match major {
+            0 => None,
+            // Gb or F#, Ebm or D#m, whichever way it was written
+            6 if self.accidental == Accidental::Sharp => Some(Spelling::Sharps),
+            6 => Some(Spelling::Flats),
+            1 | 3 | 5 | 8 | 10 => Some(Spelling::Flats),
+            _ => Some(Spelling::Sharps),
+        }
+    }
+    // a pitch class spelled with the scale's own letter, None outside the
+    // (natural minor) scale or where that would take a double accidental
+    fn diatonic(&self, pitch_class: i32) -> Option<(Note, Accidental)> {
+        const MAJOR: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+        const MINOR: [i32; 7] = [0, 2, 3, 5, 7, 8, 10];
+        let scale = if self.minor { MINOR } else { MAJOR };
+        let tonic = self.note.pitch_class(self.accidental);
+        let step = scale
+            .iter()
+            .position(|i| (tonic + i - pitch_class).rem_euclid(12) == 0)?;
+        let note = self.note.step(step);
+        let accidental = match (pitch_class - note.pitch_class(Accidental::None)).rem_euclid(12) {
+            0 => Accidental::None,
+            1 => Accidental::Sharp,
+            11 => Accidental::Flat,
+            _ => return None,
+        };
+        Some((note, accidental))
+    }
+    // Nashville number of a root, outside the scale gets an accidental
+    fn degree(&self, note: Note, accidental: Accidental) -> &'static str {
+        const MAJOR: [&str; 12] = [
+            "1", "b2", "2", "b3", "3", "4", "#4", "5", "b6", "6", "b7", "7",
+        ];
+        const MINOR: [&str; 12] = [
+            "1", "b2", "2", "3", "#3", "4", "b5", "5", "6", "#6", "7", "#7",
+        ];
+        let interval = (note.pitch_class(accidental) - self.note.pitch_class(self.accidental))
+            .rem_euclid(12) as usize;
+        if self.minor {
+            MINOR[interval]
+        } else {
+            MAJOR[interval]
+        }
+    }
+    // spelled the way the key is usually written, Bb not A#, F#m not Gbm
+    fn transposed(&self, semitones: i32) -> Self {
+        let pc = (self.note.pitch_class(self.accidental) + semitones).rem_euclid(12);
+        let spelling = match (pc, self.minor) {
+            (6, false) | (3, true) => self.spelling().unwrap_or(Spelling::Flats),
+            (1 | 3 | 8 | 10, false) | (10, true) => Spelling::Flats,
+            _ => Spelling::Sharps,
+        };
+        let (note, accidental) = spelling.spell(pc);
+        Key {
+            note,
+            accidental,
+            minor: self.minor,
+        }
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.note,
+            self.accidental,
+            if self.minor { "m" } else { "" }
+        )
+    }
+}
+
+impl Display for Spelling {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Spelling::Sharps => "sharps",
+                Spelling::Flats => "flats",
+            }
+        )
+    }
+}
+
+// the chord underneath, extensions and alterations stack on top of it. Dom
+// is the dominant seventh, the 7 comes with it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Quality {
+    Maj,
+    Min,
+    Dim,
+    Aug,
+    Sus,
+    Dom,
+}
+
+// tones named on top of the quality, kept in this order. a 9, 11 or 13
+// brings the 7 under it along, sixth and ninth together is 6/9
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+enum Extension {
+    Second, // sus2
+    Sixth,
+    Seventh,
+    Ninth,
+    Eleventh,
+    Thirteenth,
+    Alt,
+}
+
+impl Quality {
+    // the old flat names from format 1 files, as a quality and extensions
+    // plus the alteration the name stood for
+    fn migrate(name: &str) -> Option<(Quality, Vec<Extension>, Option<Alteration>)> {
+        use Extension::*;
+        let flat = |degree| Alteration {
+            accidental: Accidental::Flat,
+            degree,
+        };
+        let sharp = |degree| Alteration {
+            accidental: Accidental::Sharp,
+            degree,
+        };
+        let add = |degree| Alteration {
+            accidental: Accidental::None,
+            degree,
+        };
+        Some(match name {
+            "Maj" => (Quality::Maj, vec![], None),
+            "Min" => (Quality::Min, vec![], None),
+            "Dom7" => (Quality::Dom, vec![], None),
+            "Maj7" => (Quality::Maj, vec![Seventh], None),
+            "Min7" => (Quality::Min, vec![Seventh], None),
+            "Dim" => (Quality::Dim, vec![], None),
+            "Dim7" => (Quality::Dim, vec![Seventh], None),
+            "HalfDim" => (Quality::Min, vec![Seventh], Some(flat(5))),
+            "Aug" => (Quality::Aug, vec![], None),
+            "Aug7" => (Quality::Aug, vec![Seventh], None),
+            "Dom9" => (Quality::Dom, vec![Ninth], None),
+            "Maj9" => (Quality::Maj, vec![Ninth], None),
+            "Min9" => (Quality::Min, vec![Ninth], None),
+            "Flat9" => (Quality::Dom, vec![], Some(flat(9))),
+            "Sharp9" => (Quality::Dom, vec![], Some(sharp(9))),
+            "Dom11" => (Quality::Dom, vec![Eleventh], None),
+            "Maj11" => (Quality::Maj, vec![Eleventh], None),
+            "Min11" => (Quality::Min, vec![Eleventh], None),
+            "Sharp11" => (Quality::Dom, vec![], Some(sharp(11))),
+            "Dom13" => (Quality::Dom, vec![Thirteenth], None),
+            "Maj13" => (Quality::Maj, vec![Thirteenth], None),
+            "Flat13" => (Quality::Dom, vec![], Some(flat(13))),
+            "Alt" => (Quality::Dom, vec![Alt], None),
+            "Sus" | "Sus4" => (Quality::Sus, vec![], None),
+            "Sus2" => (Quality::Sus, vec![Second], None),
+            "Sus7" => (Quality::Sus, vec![Seventh], None),
+            "Add9" => (Quality::Maj, vec![], Some(add(9))),
+            "Add11" => (Quality::Maj, vec![], Some(add(11))),
+            "Add13" => (Quality::Maj, vec![], Some(add(13))),
+            "Maj6" => (Quality::Maj, vec![Sixth], None),
+            "Min6" => (Quality::Min, vec![Sixth], None),
+            "Maj69" => (Quality::Maj, vec![Sixth, Ninth], None),
+            "Min69" => (Quality::Min, vec![Sixth, Ninth], None),
+            _ => return None,
+        })
+    }
+    // every quality with the extensions it can carry
+    #[cfg(test)]
+    fn shapes() -> Vec<(Quality, Vec<Extension>)> {
+        use Extension::*;
+        let mut shapes = Vec::new();
+        for quality in [Quality::Maj, Quality::Min] {
+            for extensions in [
+                vec![],
+                vec![Sixth],
+                vec![Seventh],
+                vec![Ninth],
+                vec![Eleventh],
+                vec![Thirteenth],
+                vec![Sixth, Ninth],
+            ] {
+                shapes.push((quality, extensions));
+            }
+        }
+        for extensions in [
+            vec![],
+            vec![Ninth],
+            vec![Eleventh],
+            vec![Thirteenth],
+            vec![Alt],
+        ] {
+            shapes.push((Quality::Dom, extensions));
+        }
+        for quality in [Quality::Dim, Quality::Aug] {
+            shapes.push((quality, vec![]));
+            shapes.push((quality, vec![Seventh]));
+        }
+        for extensions in [vec![], vec![Second], vec![Seventh]] {
+            shapes.push((Quality::Sus, extensions));
+        }
+        shapes
+    }
+}
+
+impl Chord {
+    // the symbol between the root and the alterations
+    fn quality_symbol(&self) -> &'static str {
+        use Extension::*;
+        match (self.quality, self.extensions.as_slice()) {
+            (Quality::Maj, []) => "", // idk why but that's what it does
+            (Quality::Maj, [Sixth]) => "6",
+            (Quality::Maj, [Seventh]) => "^",
+            (Quality::Maj, [Ninth]) => "^9",
+            (Quality::Maj, [Eleventh]) => "^11",
+            (Quality::Maj, [Thirteenth]) => "^13",
+            (Quality::Maj, [Sixth, Ninth]) => "6/9",
+            (Quality::Min, []) => "-",
+            (Quality::Min, [Sixth]) => "m6",
+            (Quality::Min, [Seventh]) => "-7",
+            (Quality::Min, [Ninth]) => "-9",
+            (Quality::Min, [Eleventh]) => "-11",
+            (Quality::Min, [Thirteenth]) => "-13",
+            (Quality::Min, [Sixth, Ninth]) => "m6/9",
+            (Quality::Dom, [Ninth]) => "9",
+            (Quality::Dom, [Eleventh]) => "11",
+            (Quality::Dom, [Thirteenth]) => "13",
+            (Quality::Dom, [Alt]) => "7alt",
+            (Quality::Dom, _) => "7",
+            (Quality::Dim, [Seventh]) => "o7",
+            (Quality::Dim, _) => "o",
+            (Quality::Aug, [Seventh]) => "+7",
+            (Quality::Aug, _) => "+",
+            (Quality::Sus, [Second]) => "sus2",
+            (Quality::Sus, [Seventh]) => "7sus4",
+            (Quality::Sus, _) => "sus",
+            // shapes() has everything parse can build
+            (Quality::Maj, _) => "",
+            (Quality::Min, _) => "-",
+        }
+    }
+    // (letter steps, semitones) above the root for the third, fifth and
+    // seventh, sus chords and sixths use their own tones in those slots
+    fn tones(&self) -> Vec<(usize, i32)> {
+        use Extension::*;
+        let has = |e| self.extensions.contains(&e);
+        let mut tones = match self.quality {
+            Quality::Maj => vec![(2, 4), (4, 7)],
+            Quality::Min => vec![(2, 3), (4, 7)],
+            Quality::Dim => vec![(2, 3), (4, 6)],
+            Quality::Aug => vec![(2, 4), (4, 8)],
+            Quality::Sus if has(Second) => vec![(1, 2), (4, 7)],
+            Quality::Sus => vec![(3, 5), (4, 7)],
+            Quality::Dom if has(Alt) => vec![(2, 4), (6, 10)],
+            Quality::Dom => vec![(2, 4), (4, 7), (6, 10)],
+        };
+        let stacked = [Seventh, Ninth, Eleventh, Thirteenth].into_iter().any(has);
+        match self.quality {
+            Quality::Maj if stacked => tones.push((6, 11)),
+            Quality::Min | Quality::Aug | Quality::Sus if stacked => tones.push((6, 10)),
+            Quality::Dim if stacked => tones.push((6, 9)),
+            _ => {}
+        }
+        if has(Sixth) {
+            tones.push((5, 9));
+            if has(Ninth) {
+                tones.push((1, 2));
+            }
+        }
+        tones
+    }
+    // semitones of the tension named in the symbol, on top of tones()
+    fn extension_tone(&self) -> Option<i32> {
+        use Extension::*;
+        match self.extensions.last()? {
+            Ninth if !self.extensions.contains(&Sixth) => Some(14),
+            Eleventh => Some(17),
+            Thirteenth => Some(21),
+            _ => None,
+        }
+    }
+    // musicxml <kind> plus the <degree>s (value, alter, type) it doesn't cover
+    fn musicxml_kind(&self) -> (&'static str, &'static [(u8, i32, &'static str)]) {
+        use Extension::*;
+        match (self.quality, self.extensions.as_slice()) {
+            (Quality::Maj, [Sixth]) => ("major-sixth", &[]),
+            (Quality::Maj, [Seventh]) => ("major-seventh", &[]),
+            (Quality::Maj, [Ninth]) => ("major-ninth", &[]),
+            (Quality::Maj, [Eleventh]) => ("major-11th", &[]),
+            (Quality::Maj, [Thirteenth]) => ("major-13th", &[]),
+            (Quality::Maj, [Sixth, Ninth]) => ("major-sixth", &[(9, 0, "add")]),
+            (Quality::Maj, _) => ("major", &[]),
+            (Quality::Min, [Sixth]) => ("minor-sixth", &[]),
+            (Quality::Min, [Seventh]) => ("minor-seventh", &[]),
+            (Quality::Min, [Ninth]) => ("minor-ninth", &[]),
+            (Quality::Min, [Eleventh]) => ("minor-11th", &[]),
+            (Quality::Min, [Thirteenth]) => ("minor-13th", &[]),
+            (Quality::Min, [Sixth, Ninth]) => ("minor-sixth", &[(9, 0, "add")]),
+            (Quality::Min, _) => ("minor", &[]),
+            (Quality::Dom, [Ninth]) => ("dominant-ninth", &[]),
+            (Quality::Dom, [Eleventh]) => ("dominant-11th", &[]),
+            (Quality::Dom, [Thirteenth]) => ("dominant-13th", &[]),
+            (Quality::Dom, _) => ("dominant", &[]),
+            (Quality::Dim, [Seventh]) => ("diminished-seventh", &[]),
+            (Quality::Dim, _) => ("diminished", &[]),
+            (Quality::Aug, [Seventh]) => ("augmented-seventh", &[]),
+            (Quality::Aug, _) => ("augmented", &[]),
+            (Quality::Sus, [Second]) => ("suspended-second", &[]),
+            (Quality::Sus, [Seventh]) => ("suspended-fourth", &[(7, -1, "add")]),
+            (Quality::Sus, _) => ("suspended-fourth", &[]),
+        }
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+struct CursorPos {
+    section: usize,
+    bar: usize,
+    subdivision: usize,
+}
+
+#[derive(Default)]
+struct Toast {
+    message: Option<String>,
+    ticks: u32,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+enum Align {
+    #[default]
+    Section,
+    Global,
+}
+
+impl Align {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "section" => Some(Align::Section),
+            "global" => Some(Align::Global),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JsonStyle {
+    Pretty,
+    Compact,
+}
+
+impl JsonStyle {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pretty" => Some(JsonStyle::Pretty),
+            "compact" => Some(JsonStyle::Compact),
+            _ => None,
+        }
+    }
+    // anything spread over several lines counts as pretty
+    fn detect(data: &str) -> Self {
+        if data.trim().contains('\n') {
+            JsonStyle::Pretty
+        } else {
+            JsonStyle::Compact
+        }
+    }
+    // :set save_format wins, then whatever the file looked like, then `ask`
+    // once; anything but c there is pretty
+    fn for_save(
+        setting: Option<Self>,
+        file: Option<Self>,
+        ask: impl FnOnce() -> Option<char>,
+    ) -> Self {
+        match setting.or(file) {
+            Some(style) => style,
+            None => match ask() {
+                Some('c') => JsonStyle::Compact,
+                _ => JsonStyle::Pretty,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+enum HeaderStyle {
+    #[default]
+    Line,
+    Inline,
+    Margin,
+}
+
+impl HeaderStyle {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "line" => Some(HeaderStyle::Line),
+            "inline" => Some(HeaderStyle::Inline),
+            "margin" => Some(HeaderStyle::Margin),
+            _ => None,
+        }
+    }
+}
+
+// session options, changed with :set
+#[derive(Clone)]
+struct Settings {
+    section_headers: HeaderStyle,
+    warn_duplicate: bool,
+    align: Align,
+    confirm_bar_delete: bool,
+    practice: bool,                 // practice summary on the title line
+    save_format: Option<JsonStyle>, // None keeps the style the file had
+    max_chords_per_bar: usize,      // split bars with more on load, 0 is off
+    max_file_mb: u64,               // refuse to read anything bigger
+    max_wrap: usize,                // wraps past this are almost certainly a typo or a broken file
+    print_header: Option<String>,   // for songs without their own :header
+    print_footer: Option<String>,
+    lowercase_minor: bool, // typed `a` means Am, `c7` Cm7
+    unicode: bool,         // ♭ and ♯ in printed and exported charts, ° and ø in roman numerals
+    bar_numbers: bool,     // running bar count left of each row
+    vamp_times: usize,     // how often a vamp goes round in MIDI and :duration
+    layout_width: usize,   // columns the layout export fits rows into, 0 is off
+    page_lines: usize,     // a printed page of text, markdown or chordpro
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            section_headers: HeaderStyle::default(),
+            warn_duplicate: false,
+            align: Align::default(),
+            confirm_bar_delete: true,
+            practice: false,
+            save_format: None,
+            max_chords_per_bar: 0,
+            max_file_mb: 4,
+            max_wrap: 16,
+            print_header: None,
+            print_footer: None,
+            lowercase_minor: false,
+            unicode: false,
+            bar_numbers: false,
+            vamp_times: 4,
+            layout_width: 0,
+            page_lines: 60,
+        }
+    }
+}
+
+impl Settings {
+    // rows taken by a section's own label line
+    fn label_rows(&self) -> i32 {
+        match self.section_headers {
+            HeaderStyle::Line => 1,
+            HeaderStyle::Inline | HeaderStyle::Margin => 0,
+        }
+    }
+    // a chord symbol as it's drawn or goes out to a file. b and # only ever
+    // stand for accidentals in symbols, numbers and numerals alike, so they
+    // can be swapped wholesale
+    fn glyphs(&self, symbol: String) -> String {
+        if self.unicode {
+            symbol.replace('b', "♭").replace('#', "♯")
+        } else {
+            symbol
+        }
+    }
+    fn max_file_bytes(&self) -> u64 {
+        self.max_file_mb * 1024 * 1024
+    }
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "section_headers" => {
+                self.section_headers =
+                    HeaderStyle::parse(value).ok_or("section_headers is line, inline or margin")?;
+            }
+            "warn_duplicate" => self.warn_duplicate = parse_switch(value)?,
+            "confirm_bar_delete" => self.confirm_bar_delete = parse_switch(value)?,
+            "practice" => self.practice = parse_switch(value)?,
+            "lowercase_minor" => self.lowercase_minor = parse_switch(value)?,
+            "unicode" => self.unicode = parse_switch(value)?,
+            "bar_numbers" => self.bar_numbers = parse_switch(value)?,
+            "max_chords_per_bar" => {
+                self.max_chords_per_bar = value
+                    .parse()
+                    .map_err(|_| "max_chords_per_bar is a number, 0 for off")?;
+            }
+            "vamp_times" => {
+                self.vamp_times = value
+                    .parse()
+                    .ok()
+                    .filter(|&times| times > 0)
+                    .ok_or("vamp_times is a number of times round")?;
+            }
+            "page_lines" => {
+                self.page_lines = value
+                    .parse()
+                    .ok()
+                    .filter(|&lines| lines > 0)
+                    .ok_or("page_lines is a number of lines")?;
+            }
+            "layout_width" => {
+                self.layout_width = value
+                    .parse()
+                    .map_err(|_| "layout_width is a number of columns, 0 for off")?;
+            }
+            "max_wrap" => {
+                self.max_wrap = value
+                    .parse()
+                    .ok()
+                    .filter(|&wrap| wrap > 0)
+                    .ok_or("max_wrap is a number of bars")?;
+            }
+            "max_file_mb" => {
+                self.max_file_mb = value
+                    .parse()
+                    .ok()
+                    .filter(|&mb| mb > 0)
+                    .ok_or("max_file_mb is a number of megabytes")?;
+            }
+            "print_header" => self.print_header = Some(value.to_string()).filter(|v| !v.is_empty()),
+            "print_footer" => self.print_footer = Some(value.to_string()).filter(|v| !v.is_empty()),
+            "save_format" => {
+                self.save_format = match value {
+                    "auto" => None,
+                    _ => Some(
+                        JsonStyle::parse(value).ok_or("save_format is pretty, compact or auto")?,
+                    ),
+                }
+            }
+            "align" => {
+                self.align = Align::parse(value).ok_or("align is section or global")?;
+            }
+            _ => return Err(format!("unknown option '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+fn one() -> usize {
+    1
+}
+
+fn is_one(n: &usize) -> bool {
+    *n == 1
+}
+
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("expected on or off, got '{}'", value)),
+    }
+}
+
+// :transpose's arguments, in any order: semitones with or without a sign,
+// then whether it's just the section and whether it's a dry run
+fn parse_transpose(args: &[&str]) -> Option<(i32, bool, bool)> {
+    let semitones = args
+        .iter()
+        .find(|c| !c.starts_with("--") && **c != "section")?
+        .parse()
+        .ok()?;
+    Some((
+        semitones,
+        args.contains(&"section"),
+        args.contains(&"--dry-run"),
+    ))
+}
+
+// whether committing `chord` at the cursor waits for a second commit: it's the
+// chord already sounding there, and not the one `pending` was just warned about.
+// a %, the first subdivision and a re-hit after an N.C. go straight in
+fn confirm_duplicate(
+    bar: &Bar,
+    cursor: CursorPos,
+    chord: &Chord,
+    pending: Option<&(CursorPos, Chord)>,
+) -> bool {
+    let Some(before) = cursor.subdivision.checked_sub(1) else {
+        return false;
+    };
+    !chord.repeat
+        && bar
+            .sounding_at(before)
+            .is_some_and(|c| !c.no_chord && c.normalized(true) == chord.normalized(true))
+        && pending != Some(&(cursor, chord.clone()))
+}
+
+#[derive(Debug, PartialEq)]
+enum TriageStep {
+    Next,
+    Edit,
+    Stop,
+}
+
+// one :triage answer about the flagged chord at `pos`. c unflags it, dropping
+// the note too, s leaves it be and e wants a new chord typed over it
+fn triage_step(song: &mut Song, pos: CursorPos, answer: Option<char>) -> TriageStep {
+    match answer {
+        Some('c') => {
+            if let Some(chord) = song.sections[pos.section].bars[pos.bar]
+                .chords
+                .get_mut(&pos.subdivision)
+            {
+                chord.toggle_question();
+            }
+            TriageStep::Next
+        }
+        Some('e') => TriageStep::Edit,
+        Some('s') => TriageStep::Next,
+        _ => TriageStep::Stop,
+    }
+}
+
+// even split of the rest of the bar starting at `start`, nudged onto beats where
+// that doesn't make two chords collide
+fn distribute_chords(
+    start: usize,
+    count: usize,
+    beats: usize,
+    subdivision: usize,
+) -> Option<Vec<usize>> {
+    let remaining = subdivision.checked_sub(start)?;
+    if count == 0 || count > remaining {
+        return None;
+    }
+    let per_beat = if beats > 0 && subdivision.is_multiple_of(beats) {
+        subdivision / beats
+    } else {
+        1
+    };
+    let mut positions: Vec<usize> = Vec::with_capacity(count);
+    for i in 0..count {
+        let even = start + i * remaining / count;
+        let snapped = (even + per_beat / 2) / per_beat * per_beat;
+        let after_previous = positions.last().map_or(start, |p| p + 1);
+        // snapping must leave a subdivision for each chord still to come
+        let latest = subdivision - (count - i);
+        // the first chord goes where the cursor is
+        let pos = if i == 0 {
+            start
+        } else if snapped >= after_previous && snapped <= latest {
+            snapped
+        } else {
+            even.max(after_previous)
+        };
+        if pos >= subdivision {
+            return None;
+        }
+        positions.push(pos);
+    }
+    Some(positions)
+}
+
+// ~/.config/chordchart/keys, rebinds written by :doctor, one "Delete = X" a line
+fn keys_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("chordchart").join("keys"))
+}
+
+#[derive(Default)]
+struct KeyConfig {
+    path: Option<PathBuf>,
+    rebinds: Vec<(char, Input)>,
+    status: String, // for the doctor report
+}
+
+impl KeyConfig {
+    fn load() -> Self {
+        let path = keys_path();
+        let mut config = KeyConfig {
+            path: path.clone(),
+            ..KeyConfig::default()
+        };
+        config.status = match path.map(fs::read_to_string) {
+            None => "no home directory".to_string(),
+            Some(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => "not found".to_string(),
+            Some(Err(e)) => e.to_string(),
+            Some(Ok(text)) => match Self::parse(&text) {
+                Ok(rebinds) => {
+                    config.rebinds = rebinds;
+                    format!("ok, {} rebinds", config.rebinds.len())
+                }
+                // a half-read file would bind keys nobody asked for
+                Err(e) => format!("{}, ignored", e),
+            },
+        };
+        config
+    }
+    fn parse(text: &str) -> Result<Vec<(char, Input)>, String> {
+        let mut rebinds: Vec<(char, Input)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fail = |e: String| format!("line {}: {}", i + 1, e);
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| fail("expected <key> = <char>".to_string()))?;
+            let (name, value) = (name.trim(), value.trim());
+            let key = KEY_FALLBACKS
+                .iter()
+                .find(|(n, _, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, key, _)| *key)
+                .ok_or_else(|| fail(format!("unknown key '{}'", name)))?;
+            let mut chars = value.chars();
+            let c = match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_whitespace() => c,
+                _ => return Err(fail(format!("'{}' isn't one character", value))),
+            };
+            if rebinds.iter().any(|(bound, _)| *bound == c) {
+                return Err(fail(format!("{} is already bound", c)));
+            }
+            rebinds.push((c, key));
+        }
+        Ok(rebinds)
+    }
+    fn translate(&self, input: Option<Input>) -> Option<Input> {
+        match input {
+            Some(Input::Character(c)) => self
+                .rebinds
+                .iter()
+                .find(|(bound, _)| *bound == c)
+                .map(|(_, key)| *key)
+                .or(input),
+            _ => input,
+        }
+    }
+    fn bound_to(&self, key: Input) -> Option<char> {
+        self.rebinds
+            .iter()
+            .find(|(_, k)| *k == key)
+            .map(|(c, _)| *c)
+    }
+    // adds the rebinds to the file, keeping whatever it already says
+    fn append(&mut self, rebinds: &[(&str, char)]) -> Result<(), String> {
+        let path = self.path.clone().ok_or("no home directory")?;
+        let mut text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.to_string()),
+        };
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        for (name, c) in rebinds {
+            text += &format!("{} = {}\n", name, c);
+        }
+        self.rebinds = Self::parse(&text)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(&path, text).map_err(|e| e.to_string())?;
+        self.status = format!("ok, {} rebinds", self.rebinds.len());
+        Ok(())
+    }
+}
+
+// what the doctor asks of the terminal, a trait so tests can answer instead
+trait Terminfo {
+    fn size(&self) -> (i32, i32);
+    fn colors(&self) -> Option<(i32, i32)>; // colors and pairs
+    fn locale(&self) -> Option<String>;
+    fn receives(&mut self, name: &str, key: Input) -> bool;
+}
+
+struct Curses<'a>(&'a Window);
+
+impl Terminfo for Curses<'_> {
+    fn size(&self) -> (i32, i32) {
+        (self.0.get_max_x(), self.0.get_max_y())
+    }
+    fn colors(&self) -> Option<(i32, i32)> {
+        if !has_colors() {
+            return None;
+        }
+        start_color();
+        Some((COLORS(), COLOR_PAIRS()))
+    }
+    fn locale(&self) -> Option<String> {
+        ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|v| !v.is_empty())
+    }
+    // asks for the key and waits a while for it
+    fn receives(&mut self, name: &str, key: Input) -> bool {
+        let win = self.0;
+        win.attron(Attribute::Reverse);
+        win.mvaddstr(
+            win.get_max_y() - 1,
+            0,
+            format!("press {} (Esc to skip)", name),
+        );
+        win.attroff(Attribute::Reverse);
+        win.clrtoeol();
+        win.timeout(10_000);
+        let input = win.getch();
+        win.timeout(-1);
+        input == Some(key)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum KeyAdvice {
+    Works,
+    Redundant(char), // works, and a rebind that isn't needed
+    Rebound(char),
+    Suggest(char),
+    Taken(char), // the usual fallback is already bound to something else
+}
+
+impl KeyAdvice {
+    fn decide(received: bool, key: Input, fallback: char, config: &KeyConfig) -> Self {
+        let taken = config.rebinds.iter().any(|(c, _)| *c == fallback);
+        match (received, config.bound_to(key)) {
+            (true, None) => KeyAdvice::Works,
+            (true, Some(c)) => KeyAdvice::Redundant(c),
+            (false, Some(c)) => KeyAdvice::Rebound(c),
+            (false, None) if taken => KeyAdvice::Taken(fallback),
+            (false, None) => KeyAdvice::Suggest(fallback),
+        }
+    }
+}
+
+// the report, and the rebinds worth writing for keys that never arrived
+fn doctor(
+    term: &mut impl Terminfo,
+    config: &KeyConfig,
+) -> (Vec<String>, Vec<(&'static str, char)>) {
+    let (width, height) = term.size();
+    let mut lines = vec![format!("terminal size: {}x{}", width, height)];
+    lines.push(match term.colors() {
+        Some((colors, pairs)) => format!("colors: {} ({} pairs)", colors, pairs),
+        None => "colors: none".to_string(),
+    });
+    lines.push("keypad mode: on".to_string());
+    let locale = term.locale().unwrap_or_else(|| "unset".to_string());
+    let utf8 = locale
+        .to_ascii_lowercase()
+        .replace('-', "")
+        .contains("utf8");
+    lines.push(format!(
+        "locale: {} ({})",
+        locale,
+        if utf8 { "UTF-8" } else { "not UTF-8" }
+    ));
+    lines.push(format!(
+        "keys file: {} ({})",
+        config
+            .path
+            .as_ref()
+            .map_or("none".to_string(), |p| p.display().to_string()),
+        config.status
+    ));
+    lines.push(String::new());
+
+    let mut suggested = Vec::new();
+    for (name, key, fallback) in KEY_FALLBACKS {
+        let received = term.receives(name, key);
+        lines.push(match KeyAdvice::decide(received, key, fallback, config) {
+            KeyAdvice::Works => format!("{}: ok", name),
+            KeyAdvice::Redundant(c) => format!("{}: ok, the rebind to {} isn't needed", name, c),
+            KeyAdvice::Rebound(c) => format!("{}: not received, rebound to {}", name, c),
+            KeyAdvice::Suggest(c) => {
+                suggested.push((name, c));
+                format!("{}: not received, suggest {}", name, c)
+            }
+            KeyAdvice::Taken(c) => format!(
+                "{}: not received, {} is taken, pick a key in the keys file",
+                name, c
+            ),
+        });
+    }
+    (lines, suggested)
+}
+
+// status for loops over several items, Escape between items cancels the rest
+struct Progress<'a> {
+    win: &'a Window,
+    what: &'a str,
+    total: usize,
+}
+
+impl Progress<'_> {
+    fn tick(&self, done: usize) -> bool {
+        let win = self.win;
+        win.attron(Attribute::Reverse);
+        win.mvaddstr(
+            win.get_max_y() - 1,
+            0,
+            format!(
+                "{} {}/{}... (esc to cancel)",
+                self.what,
+                done + 1,
+                self.total
+            ),
+        );
+        win.attroff(Attribute::Reverse);
+        win.clrtoeol();
+        win.refresh();
+
+        win.nodelay(true);
+        let input = win.getch();
+        win.nodelay(false);
+        match input {
+            Some(Input::Character('\u{1b}')) => false,
+            Some(other) => {
+                // not ours, leave it for the main loop
+                win.ungetch(&other);
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// midi's variable length numbers, 7 bits a byte with the high bit on all but the last
+fn write_vlq(out: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// the page of `per_page` items holding `selected`, with its number and the
+// page count, both from 1
+fn page_of(selected: usize, len: usize, per_page: usize) -> (std::ops::Range<usize>, usize, usize) {
+    let per_page = per_page.max(1);
+    let start = selected / per_page * per_page;
+    (
+        start..(start + per_page).min(len),
+        start / per_page + 1,
+        len.div_ceil(per_page).max(1),
+    )
+}
+
+// a key in the structure overlay, with what its prompt got back: r comes with
+// the new label and d only once it's been confirmed
+#[derive(Clone, Debug)]
+enum StructureKey {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Go,
+    MoveUp,
+    MoveDown,
+    Rename(String),
+    Delete,
+    Close,
+}
+
+// the overlay without the terminal: false once it's closed
+fn structure_key(
+    song: &mut Song,
+    cursor: &mut CursorPos,
+    selected: &mut usize,
+    per_page: usize,
+    key: StructureKey,
+) -> Result<bool, &'static str> {
+    let last = song.sections.len() - 1;
+    match key {
+        StructureKey::Up => *selected = selected.saturating_sub(1),
+        StructureKey::Down => *selected = (*selected + 1).min(last),
+        StructureKey::PageUp => *selected = selected.saturating_sub(per_page),
+        StructureKey::PageDown => *selected = (*selected + per_page).min(last),
+        StructureKey::Go => {
+            *cursor = CursorPos {
+                section: *selected,
+                ..CursorPos::default()
+            };
+            return Ok(false);
+        }
+        StructureKey::MoveUp if song.move_section(*selected, true, cursor) => *selected -= 1,
+        StructureKey::MoveDown if song.move_section(*selected, false, cursor) => *selected += 1,
+        StructureKey::MoveUp | StructureKey::MoveDown => {}
+        StructureKey::Rename(label) => song.rename_section(*selected, &label)?,
+        StructureKey::Delete => {
+            song.delete_section(*selected, cursor)?;
+            *selected = (*selected).min(song.sections.len() - 1);
+        }
+        StructureKey::Close => return Ok(false),
+    }
+    Ok(true)
+}
+
+// index of the first section that differs between two saved songs, 0 when
+// anything outside the sections did
+fn first_changed_section(old: &serde_json::Value, new: &serde_json::Value) -> usize {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return 0;
+    };
+    if old.len() != new.len()
+        || old
+            .iter()
+            .any(|(k, v)| k != "sections" && new.get(k) != Some(v))
+    {
+        return 0;
+    }
+    let empty = Vec::new();
+    let old = old
+        .get("sections")
+        .and_then(|s| s.as_array())
+        .unwrap_or(&empty);
+    let new = new
+        .get("sections")
+        .and_then(|s| s.as_array())
+        .unwrap_or(&empty);
+    old.iter()
+        .zip(new)
+        .position(|(a, b)| a != b)
+        .unwrap_or(old.len().min(new.len()))
+}
+
+// keys that only move the cursor or change the view, with nothing for undo to
+// keep. undo and redo look after their own stacks. space and right aren't here
+// since stepping off the last bar adds one
+fn leaves_song_alone(input: Option<Input>) -> bool {
+    matches!(
+        input,
+        None | Some(
+            Input::Character('0'..='9' | 'y' | 'u' | 'U' | '\u{12}')
+                | Input::KeyLeft
+                | Input::KeyUp
+                | Input::KeyDown
+                | Input::KeyF1
+                | Input::KeyF3
+                | Input::KeyF5
+                | Input::KeyResize
+        )
+    )
+}
+
+// song.json recovers from song.autosave.json, an unnamed song from the temp dir
+fn autosave_path(filename: Option<&Path>) -> PathBuf {
+    match filename {
+        Some(path) => path.with_extension("autosave.json"),
+        None => std::env::temp_dir().join("chordchart.autosave.json"),
+    }
+}
+
+// on startup, a recovery file newer than the song means we went down mid-edit.
+// with no song file, or none yet, any recovery file counts
+fn needs_recovery(recovery: &Path, song: Option<&Path>) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let Some(saved) = modified(recovery) else {
+        return false;
+    };
+    song.and_then(modified).is_none_or(|song| song < saved)
+}
+
+// every loader goes through here so a binary or a huge log fails with a message
+// instead of a panic or a long read
+fn read_song(path: &Path, max_bytes: u64) -> Result<(Song, String), String> {
+    let fail = |e: String| format!("{}: {}", path.display(), e);
+    let mut file = fs::File::open(path).map_err(|e| fail(e.to_string()))?;
+    let size = file.metadata().map_err(|e| fail(e.to_string()))?.len();
+    if size > max_bytes {
+        return Err(fail(format!(
+            "{} MB is over the {} MB limit (:set max_file_mb)",
+            size.div_ceil(1024 * 1024),
+            max_bytes / (1024 * 1024)
+        )));
+    }
+    // sniff a small prefix before reading the rest
+    let mut prefix = [0u8; 64];
+    let n = file.read(&mut prefix).map_err(|e| fail(e.to_string()))?;
+    if prefix[..n]
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|&b| b != b'{')
+    {
+        return Err(fail("not a chordchart song".to_string()));
+    }
+    let mut bytes = prefix[..n].to_vec();
+    file.read_to_end(&mut bytes)
+        .map_err(|e| fail(e.to_string()))?;
+    let data = String::from_utf8(bytes).map_err(|e| {
+        fail(format!(
+            "not UTF-8 text (bad byte at {})",
+            e.utf8_error().valid_up_to()
+        ))
+    })?;
+    let mut json: serde_json::Value =
+        serde_json::from_str(&data).map_err(|e| fail(e.to_string()))?;
+    // a current file goes to serde as text, so errors keep their line
+    let current = json.get("version") == Some(&FORMAT_VERSION.into());
+    Song::migrate(&mut json).map_err(fail)?;
+    let song = if current {
+        serde_json::from_str(&data)
+    } else {
+        serde_json::from_value(json)
+    }
+    .map_err(|e| fail(e.to_string()))?;
+    Ok((song, data))
+}
+
+struct State {
+    win: Window,
+    settings: Settings,
+    song: Song,
+    cursor: CursorPos,
+    should_clear: bool,
+    should_quit: bool,
+    toast: Toast,
+    filename: Option<PathBuf>,
+    pending_duplicate: Option<(CursorPos, Chord)>, // waiting for a second commit
+    count: Option<usize>,                          // typed digits before a command key
+    json_style: Option<JsonStyle>,                 // how the open file was written
+    chord_display: ChordDisplay,                   // just for this session
+    function_colors: bool,                         // :color, needs a key
+    capo: i32,                                     // shapes shown for this fret, 0 is off
+    undo_stack: Vec<(Song, CursorPos)>,            // oldest first
+    redo_stack: Vec<(Song, CursorPos)>,
+    register: Option<Bar>,             // y yanks here, p pastes from it
+    section_register: Option<Section>, // :copysection and :pastesection
+    unsaved_edits: usize,              // since the last save or autosave
+    scroll: i32,                       // chart lines hidden above the screen
+    keys: KeyConfig,                   // rebinds from the keys file
+    outline: Outline,                  // section tops for draw(), see outline_from
+    outline_from: Option<usize>,       // first section changed since it was worked out
+}
+
+impl State {
+    fn schedule_clear(&mut self) {
+        self.should_clear = true;
+        // whatever called for a full redraw may have moved sections too
+        self.outline_from = Some(0);
+    }
+    fn quit(&mut self) {
+        self.should_quit = true;
+    }
+    fn notation(&self) -> Notation {
+        match (self.chord_display, self.song.key) {
+            (ChordDisplay::Numbers, Some(key)) => Notation::Numbers(key),
+            (ChordDisplay::Roman, Some(key)) => Notation::Roman {
+                key,
+                unicode: self.settings.unicode,
+            },
+            _ if self.capo > 0 => self.song.capo(self.capo),
+            _ => Notation::Letters,
+        }
+    }
+    fn set_chord_display(&mut self, display: ChordDisplay) {
+        if display != ChordDisplay::Letters && self.song.key.is_none() {
+            self.refuse("numbers need a key, set one with :key");
+            return;
+        }
+        self.chord_display = display;
+        self.schedule_clear();
+        self.toast(match display {
+            ChordDisplay::Letters => "Letters",
+            ChordDisplay::Numbers => "Numbers",
+            ChordDisplay::Roman => "Roman numerals",
+        });
+    }
+    // redoes the running section heights from the first changed section
+    fn refresh_outline(&mut self) {
+        // a song replaced outright can have fewer sections than the outline
+        let from = match self.outline_from.take() {
+            None if self.outline.wraps.len() == self.song.sections.len() => return,
+            from => from.unwrap_or(0),
+        };
+        let notation = self.notation();
+        self.outline
+            .refresh(&self.song, &self.settings, notation, from);
+    }
+    // the sections in range as they sit on screen, scrolled
+    fn layout(&mut self, range: std::ops::Range<usize>) -> Layout {
+        self.refresh_outline();
+        let mut layout =
+            self.song
+                .layout_sections(&self.settings, self.notation(), &self.outline, range);
+        layout.scroll(self.scroll);
+        layout
+    }
+    fn find_cursor(&mut self) -> (i32, i32) {
+        let section = self.cursor.section;
+        self.layout(section..section + 1)
+            .find(self.cursor)
+            .unwrap_or((0, 0))
+    }
+    // keeps the cursor row between the title and the toast line, with the
+    // line above it (label, marker or the previous row) in view when possible
+    fn follow_cursor(&mut self) {
+        let top = self.song.header_height();
+        let bottom = self.win.get_max_y() - 2;
+        self.refresh_outline();
+        let section = self.cursor.section;
+        let Some((y, _)) = self
+            .song
+            .layout_sections(
+                &self.settings,
+                self.notation(),
+                &self.outline,
+                section..section + 1,
+            )
+            .find(self.cursor)
+        else {
+            return;
+        };
+        let scroll = if y - 1 - self.scroll < top {
+            (y - 1 - top).max(0)
+        } else if y - self.scroll > bottom {
+            y - bottom
+        } else {
+            self.scroll
+        };
+        if scroll != self.scroll {
+            self.scroll = scroll;
+            // nothing moved in the chart, so the outline stands
+            self.should_clear = true;
+        }
+    }
+    fn draw(&mut self) {
+        self.follow_cursor();
+        if self.should_clear {
+            self.win.clear();
+        }
+
+        // Header
+        self.win.mvprintw(0, 0, "SONG: ");
+        self.win.printw(&self.song.title);
+        if let Some(key) = self.song.key {
+            self.win
+                .printw(format!("  ({})", self.settings.glyphs(key.to_string())));
+        }
+        if self.capo > 0 {
+            self.win.printw(format!("  capo {}", self.capo));
+        }
+        if let Some(subtitle) = &self.song.subtitle {
+            self.win.mvaddstr(1, 6, subtitle);
+        }
+        if let Some(practice) = self
+            .song
+            .practice
+            .as_ref()
+            .filter(|_| self.settings.practice)
+        {
+            let summary = practice.to_string();
+            let x = self.win.get_max_x() - summary.chars().count() as i32 - 1;
+            self.win.mvaddstr(0, x.max(0), summary);
+        }
+
+        // below the title, above the toast line
+        let (top, max_y) = (self.song.header_height(), self.win.get_max_y());
+        // only the sections on screen get laid out, the outline says which
+        self.refresh_outline();
+        let shown = self
+            .outline
+            .visible(top + self.scroll, max_y - 1 + self.scroll);
+        let layout = self.layout(shown.clone());
+        self.song.paint(
+            &mut self.win,
+            &self.settings,
+            &layout,
+            self.cursor,
+            self.function_colors,
+            top..max_y - 1,
+        );
+        self.draw_toast();
+        self.win.refresh();
+    }
+    fn current_section(&self) -> &Section {
+        &self.song.sections[self.cursor.section]
+    }
+    fn current_section_mut(&mut self) -> &mut Section {
+        &mut self.song.sections[self.cursor.section]
+    }
+    fn current_chord_mut(&mut self) -> Option<&mut Chord> {
+        let cursor = self.cursor;
+        self.current_section_mut().bars[cursor.bar]
+            .chords
+            .get_mut(&cursor.subdivision)
+    }
+    fn next_or_create_bar(&mut self) {
+        let cursor = self.cursor;
+        let section = self.current_section();
+        if section.bars.is_empty() {
+            self.current_section_mut().bars.push(Bar::default());
+            self.cursor.bar = 0;
+            self.cursor.subdivision = 0;
+            return;
+        }
+
+        let previous = section.bars.last().unwrap();
+        let new = Bar::new(previous.beats, previous.subdivision);
+        if section.bars.len() == cursor.bar + 1 && self.song.sections.len() == cursor.section + 1 {
+            // last bar last section
+            self.current_section_mut().bars.push(new);
+            self.cursor.bar += 1;
+            self.cursor.subdivision = 0;
+            self.win.clear();
+        } else if section.bars.len() == cursor.bar + 1 {
+            // last bar not last section
+            self.next_or_create_section();
+        } else {
+            self.cursor.bar += 1;
+            self.cursor.subdivision = 0;
+        }
+    }
+    // an empty bar in the cursor bar's meter, wherever the cursor is
+    fn insert_bar_after(&mut self) {
+        let cursor = self.cursor;
+        let section = self.current_section();
+        let (at, new) = match section.bars.get(cursor.bar) {
+            Some(bar) => (cursor.bar + 1, Bar::new(bar.beats, bar.subdivision)),
+            None => (0, Bar::default()),
+        };
+        self.current_section_mut().bars.insert(at, new);
+        self.cursor.bar = at;
+        self.cursor.subdivision = 0;
+        // everything after it rewraps
+        self.schedule_clear();
+    }
+    fn prev_bar(&mut self) {
+        self.cursor = self.song.prev_bar(self.cursor);
+    }
+    fn next_subdivision(&mut self) {
+        let current_bar = self.cursor.bar;
+        let current_sub = self.cursor.subdivision;
+        let section = self.current_section();
+
+        if current_sub + 1 == section.bars[current_bar].subdivision {
+            self.next_or_create_bar();
+        } else {
+            self.cursor.subdivision += 1;
+        }
+    }
+    fn prev_subdivision(&mut self) {
+        self.cursor = self.song.prev_subdivision(self.cursor);
+    }
+    fn chord_input(&mut self, initial: &str) -> Result<String, ()> {
+        let mut buf = String::with_capacity(8);
+        buf.push_str(initial);
+        let mut finished = false;
+        // find current cursor position
+        let (y, x) = self.find_cursor();
+
+        self.win.attron(Attribute::Reverse);
+        while !finished {
+            self.win.mvaddstr(y, x, &buf);
+            let ch = self.win.getch();
+            if let Some(Input::Character(c)) = ch {
+                if c.is_ascii_alphanumeric() || c.is_ascii_punctuation() || c == 'ø' || c == 'Δ' {
+                    buf.push(c);
+                } else if c.is_whitespace() {
+                    if c == ' ' {
+                        // a chord letter right after the space starts another chord in this bar
+                        let parses = buf
+                            .rsplit(' ')
+                            .next()
+                            .is_some_and(|last| Chord::parse(last).is_ok());
+                        if parses {
+                            self.win.mvaddstr(y, x, format!("{} ", buf));
+                            match self.win.getch() {
+                                Some(Input::Character(n)) if Note::try_from(n).is_ok() => {
+                                    buf.push(' ');
+                                    buf.push(n);
+                                    continue;
+                                }
+                                Some(other) => {
+                                    self.win.ungetch(&other);
+                                }
+                                None => {}
+                            }
+                        }
+                        if buf.contains(' ') {
+                            self.next_or_create_bar();
+                        } else {
+                            self.next_subdivision();
+                        }
+                    } else if c == '\t' {
+                        self.next_or_create_bar();
+                    }
+                    finished = true;
+                } else if c == '\u{8}' {
+                    buf.pop();
+                    self.win.mvaddstr(y, x, &buf);
+                    self.win.addch(' ');
+                } else {
+                    finished = true;
+                }
+            } else {
+                finished = true;
+            }
+        }
+        self.win.attroff(Attribute::Reverse);
+        Ok(buf)
+    }
+    // typed chords, read with the input settings
+    fn parse_chord(&self, s: &str) -> Result<Chord, ChordParseError> {
+        let chord = Chord::parse(s)?;
+        Ok(if self.settings.lowercase_minor {
+            chord.lowercase_minor(s)
+        } else {
+            chord
+        })
+    }
+    fn input_or_edit_in_place_chord(&mut self, first: char) {
+        // n starts an N.C., % repeats the chord before
+        if Note::try_from(first).is_err() && !matches!(first, 'n' | 'N' | '%') {
+            return;
+        }
+
+        let cursor = self.cursor;
+        // if let Some(prev_chord) = self.current_section_mut().bars[cursor.bar].get_chord_mut(cursor.subdivision) {
+        //     // already a chord there
+        //     // just change the root
+        //     prev_chord.note = note;
+        //     return;
+        // }
+
+        let Some(new) = self.parsed_chord_input(cursor, &first.to_string()) else {
+            return;
+        };
+        if new.contains(' ') {
+            self.insert_spread_chords(cursor, &new);
+            return;
+        }
+        if let Ok(chord) = self.parse_chord(&new) {
+            // chord_input may already have moved the cursor on, even into the next section
+            let bar = &self.song.sections[cursor.section].bars[cursor.bar];
+            let pending = self.pending_duplicate.take();
+            if self.settings.warn_duplicate
+                && confirm_duplicate(bar, cursor, &chord, pending.as_ref())
+            {
+                // send the cursor back so committing again means "yes, keep it"
+                self.cursor = cursor;
+                self.pending_duplicate = Some((cursor, chord));
+                self.toast("same as previous - press again to keep");
+                return;
+            }
+            self.song.sections[cursor.section].bars[cursor.bar]
+                .chords
+                .insert(cursor.subdivision, chord);
+        }
+    }
+    // chord_input until every word parses. a typo reopens the cell with the text
+    // kept, committing it unchanged gives up
+    fn parsed_chord_input(&mut self, cursor: CursorPos, initial: &str) -> Option<String> {
+        let mut new = self.chord_input(initial).unwrap();
+        while let Some((word, e)) = new
+            .split_ascii_whitespace()
+            .find_map(|word| self.parse_chord(word).err().map(|e| (word, e)))
+        {
+            self.cursor = cursor;
+            self.refuse(&format!("couldn't parse '{}': {}", word, e));
+            self.draw();
+            let retry = self.chord_input(&new).unwrap();
+            if retry == new {
+                self.cursor = cursor;
+                return None;
+            }
+            new = retry;
+        }
+        Some(new)
+    }
+    // several chords typed in one go, spread over the rest of the bar
+    fn insert_spread_chords(&mut self, cursor: CursorPos, input: &str) {
+        let mut chords = Vec::new();
+        for word in input.split_ascii_whitespace() {
+            match self.parse_chord(word) {
+                Ok(chord) => chords.push(chord),
+                Err(e) => {
+                    self.refuse(&format!("couldn't parse '{}': {}", word, e));
+                    return;
+                }
+            }
+        }
+        let bar = &mut self.song.sections[cursor.section].bars[cursor.bar];
+        match distribute_chords(cursor.subdivision, chords.len(), bar.beats, bar.subdivision) {
+            Some(positions) => {
+                for (pos, chord) in positions.into_iter().zip(chords) {
+                    bar.chords.insert(pos, chord);
+                }
+            }
+            None => self.toast(&format!("{} chords don't fit in this bar", chords.len())),
+        }
+    }
+    fn do_command_line(&mut self) {
+        self.toast.ticks = 0;
+        let mut buf = String::new();
+
+        let mut finished = false;
+        let y = self.win.get_max_y() - 1;
+        let x = 1;
+        self.win.attron(Attribute::Reverse);
+        self.win.mvaddch(y, 0, ':');
+        curs_set(1);
+
+        while !finished {
+            self.win.mvaddstr(y, x, &buf);
+            self.win
+                .hline(' ', self.win.get_max_x() - buf.chars().count() as i32);
+            let ch = self.win.getch();
+            if let Some(Input::Character(c)) = ch {
+                if c.is_ascii_alphanumeric() || c.is_ascii_punctuation() {
+                    buf.push(c);
+                } else if c == '\u{8}' {
+                    buf.pop();
+                    self.win.mvaddstr(y, x, &buf);
+                    self.win.addch(' ');
+                } else if c == ' ' {
+                    // autoexpand stuff
+                    if buf == "t" {
+                        buf = "title ".to_string();
+                    } else if buf == "q" {
+                        buf = "quit".to_string();
+                    } else if buf == "s" {
+                        buf = "save ".to_string();
+                    } else if buf == "e" {
+                        buf = "edit ".to_string();
+                    } else if buf == "p" {
+                        buf = "print ".to_string();
+                    } else if buf == "n" {
+                        buf = "new".to_string();
+                    } else {
+                        if buf.is_empty() {
+                            continue;
+                        }
+                        buf.push(' ');
+                    }
+                } else if c == '\t' {
+                    continue;
+                } else {
+                    finished = true;
+                }
+            } else {
+                finished = true;
+            }
+        }
+        self.win.attroff(Attribute::Reverse);
+        curs_set(0);
+        // now parse
+        if buf.is_empty() {
+            return;
+        }
+        let components = buf.split_ascii_whitespace().collect::<Vec<&str>>();
+        if components.first() == Some(&"title") && components.get(1).is_some() {
+            // set title
+            let title = components.get(1..).unwrap().join(" ");
+            self.song.title = title;
+            self.schedule_clear();
+            self.toast(&format!("Set title to '{}'.", self.song.title));
+        } else if components.first() == Some(&"subtitle") {
+            // no text clears it
+            let subtitle = components[1..].join(" ");
+            if subtitle.is_empty() {
+                self.song.subtitle = None;
+                self.toast("Cleared subtitle.");
+            } else {
+                self.toast(&format!("Set subtitle to '{}'.", subtitle));
+                self.song.subtitle = Some(subtitle);
+            }
+            self.schedule_clear();
+        } else if components.first() == Some(&"header") || components.first() == Some(&"footer") {
+            // no text clears it, {title} {date} {page} {pages} expand on export
+            let which = components[0];
+            let text = components[1..].join(" ");
+            let slot = if which == "header" {
+                &mut self.song.print_header
+            } else {
+                &mut self.song.print_footer
+            };
+            let message = if text.is_empty() {
+                *slot = None;
+                format!("Cleared print {}.", which)
+            } else {
+                let message = format!("Set print {} to '{}'.", which, text);
+                *slot = Some(text);
+                message
+            };
+            self.toast(&message);
+        } else if components.first() == Some(&"quit") || components.first() == Some(&"q") {
+            self.quit();
+        } else if components.first() == Some(&"save") || components.first() == Some(&"s") {
+            let path = match (components.get(1), self.filename.clone()) {
+                (Some(name), _) => Some(PathBuf::from(name)),
+                (None, Some(path)) => Some(path),
+                (None, None) => match self.prompt_line("filename? ") {
+                    Some(name) if name.is_empty() => {
+                        self.toast("need a file name to save");
+                        None
+                    }
+                    name => name.map(PathBuf::from),
+                },
+            };
+            if let Some(path) = path {
+                if let Err(e) = self.save_to_disk(&path) {
+                    self.refuse(&e);
+                }
+            }
+        } else if components.first() == Some(&"set") {
+            // rejoined so values like print_footer can have spaces
+            let assignment = components[1..].join(" ");
+            match assignment.split_once('=') {
+                Some((key, value)) => match self.settings.set(key, value) {
+                    Ok(()) => {
+                        self.schedule_clear();
+                        self.toast(&format!("{}={}", key, value));
+                    }
+                    Err(e) => self.toast(&e),
+                },
+                None => self.toast("usage: set <option>=<value>"),
+            }
+        } else if components.first() == Some(&"meter") {
+            // meter <beats>/<subdivisions> [--rescale] [--truncate]
+            let meter = components
+                .get(1)
+                .and_then(|m| m.split_once('/'))
+                .and_then(|(b, s)| Some((b.parse::<usize>().ok()?, s.parse::<usize>().ok()?)));
+            let retime = if components.contains(&"--rescale") {
+                Retime::Rescale
+            } else {
+                Retime::Keep
+            };
+            let truncate = components.contains(&"--truncate");
+            match meter {
+                Some((beats, subdivision)) => self.set_meter(beats, subdivision, retime, truncate),
+                None => self.toast("usage: meter <beats>/<subdivisions> [--rescale] [--truncate]"),
+            }
+        } else if components.first() == Some(&"time") {
+            // just the beats, cells stay the same length where they divide evenly
+            match components.get(1).map(|n| n.parse::<usize>()) {
+                Some(Ok(beats)) => {
+                    let bar = &self.current_section().bars[self.cursor.bar];
+                    let subdivision = match bar.subdivision * beats {
+                        n if n % bar.beats.max(1) == 0 && n > 0 => n / bar.beats.max(1),
+                        _ => bar.subdivision,
+                    };
+                    self.set_meter(beats, subdivision, Retime::Keep, false);
+                }
+                _ => self.toast("usage: time <beats>"),
+            }
+        } else if components.first() == Some(&"capo") {
+            match components.get(1).copied() {
+                Some("off" | "0") => {
+                    self.capo = 0;
+                    self.schedule_clear();
+                    self.toast("capo off");
+                }
+                Some(fret) => match fret.parse::<i32>() {
+                    Ok(fret @ 1..=11) => {
+                        self.capo = fret;
+                        self.schedule_clear();
+                        self.toast(&format!("capo {}, showing shapes", fret));
+                    }
+                    _ => self.toast("capo is a fret from 1 to 11"),
+                },
+                None => self.toast("usage: capo <fret>|off"),
+            }
+        } else if components.first() == Some(&"repeat") {
+            match components.get(1).map(|n| n.parse::<usize>()) {
+                Some(Ok(count)) if count > 0 => {
+                    let section = self.current_section_mut();
+                    section.repeats = count > 1;
+                    section.repeat_count = count;
+                    let message = format!("[{}] plays {}x", section.label, count);
+                    self.schedule_clear();
+                    self.toast(&message);
+                }
+                _ => self.toast("usage: repeat <times>"),
+            }
+        } else if components.first() == Some(&"wrap") {
+            match components.get(1).map(|n| n.parse::<usize>()) {
+                Some(Ok(wrap)) if wrap > self.settings.max_wrap => {
+                    let message = format!(
+                        "wrap is at most {} bars (:set max_wrap)",
+                        self.settings.max_wrap
+                    );
+                    self.refuse(&message)
+                }
+                Some(Ok(0)) => self.refuse("wrap is at least 1 bar"),
+                Some(Ok(wrap)) if components.get(2) == Some(&"all") => {
+                    for section in &mut self.song.sections {
+                        section.wrap = wrap;
+                    }
+                    self.schedule_clear();
+                    self.toast(&format!("{} bars per row everywhere", wrap));
+                }
+                Some(Ok(wrap)) if components.len() == 2 => {
+                    self.current_section_mut().wrap = wrap;
+                    self.schedule_clear();
+                    self.toast(&format!("{} bars per row", wrap));
+                }
+                _ => self.toast("usage: wrap <bars> [all]"),
+            }
+        } else if components.first() == Some(&"label") {
+            let label = components[1..].join(" ");
+            self.rename_section(self.cursor.section, &label);
+        } else if let Some(marker) = components
+            .first()
+            .filter(|c| ["segno", "coda", "dc", "ds"].contains(c))
+            .and_then(|c| Marker::parse(c))
+        {
+            self.toggle_marker(marker);
+        } else if components.first() == Some(&"mark") {
+            match components.get(1).map(|name| (*name, Marker::parse(name))) {
+                Some(("none", _)) => self.clear_marker(),
+                Some((_, Some(marker))) => self.toggle_marker(marker),
+                _ => self.toast("usage: mark segno|coda|ds|dc|dcfine|fine|none"),
+            }
+        } else if components.first() == Some(&"bar") {
+            match components.get(1).map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) => match self.song.bar_position(n) {
+                    Some(pos) => {
+                        self.cursor = pos;
+                        self.schedule_clear();
+                    }
+                    None => {
+                        let message = format!("bars go from 1 to {}", self.song.bar_count());
+                        self.refuse(&message);
+                    }
+                },
+                _ => self.toast("usage: bar <number>"),
+            }
+        } else if components.first() == Some(&"go") || components.first() == Some(&"goto") {
+            let label = components[1..].join(" ");
+            self.go_to_section(&label);
+        } else if components.first() == Some(&"dup") {
+            self.duplicate_section();
+        } else if components.first() == Some(&"copysection") {
+            self.copy_section();
+        } else if components.first() == Some(&"pastesection") {
+            self.paste_section();
+        } else if components.first() == Some(&"vamp") {
+            let section = self.current_section_mut();
+            section.vamp = !section.vamp;
+            let message = format!(
+                "[{}] {}",
+                section.label,
+                if section.vamp {
+                    "vamps until cue"
+                } else {
+                    "no longer vamps"
+                }
+            );
+            self.schedule_clear();
+            self.toast(&message);
+        } else if components.first() == Some(&"duration") {
+            // the tempo being practiced, then the 120 the MIDI export plays at
+            let bpm = match components.get(1) {
+                Some(bpm) => bpm.parse().ok().filter(|&bpm| bpm > 0),
+                None => Some(
+                    self.song
+                        .practice
+                        .as_ref()
+                        .and_then(|p| p.current_tempo)
+                        .unwrap_or(120),
+                ),
+            };
+            match bpm {
+                Some(bpm) => {
+                    let vamps = match self.song.sections.iter().any(|s| s.vamp) {
+                        true => format!(", vamps x{} (:set vamp_times)", self.settings.vamp_times),
+                        false => String::new(),
+                    };
+                    self.toast(&format!(
+                        "{} at {} bpm{}",
+                        self.song.duration_text(bpm, &self.settings),
+                        bpm,
+                        vamps
+                    ));
+                }
+                None => self.toast("usage: duration [bpm]"),
+            }
+        } else if components.first() == Some(&"align") {
+            // per-section override, "inherit" goes back to the :set align value
+            match components.get(1).copied() {
+                Some("inherit") => {
+                    self.current_section_mut().align = None;
+                    self.schedule_clear();
+                    self.toast("Section follows the global align setting");
+                }
+                Some(value) => match Align::parse(value) {
+                    Some(align) => {
+                        self.current_section_mut().align = Some(align);
+                        self.schedule_clear();
+                        self.toast(&format!("Section align set to {}", value));
+                    }
+                    None => self.toast("usage: align section|global|inherit"),
+                },
+                None => self.toast("usage: align section|global|inherit"),
+            }
+        } else if components.first() == Some(&"doctor") {
+            let (lines, suggested) = doctor(&mut Curses(&self.win), &self.keys);
+            self.schedule_clear();
+            self.show_overlay(" doctor ", &lines, None);
+            if suggested.is_empty() {
+                self.win.getch();
+            } else if self.prompt_char(
+                &format!("Write {} rebinds to the keys file? (y/n)", suggested.len()),
+                vec!['y', 'n'],
+            ) == Some('y')
+            {
+                match self.keys.append(&suggested) {
+                    Ok(()) => self.toast(&format!("Wrote {} rebinds", suggested.len())),
+                    Err(e) => self.refuse(&format!("couldn't write the keys file: {}", e)),
+                }
+            }
+        } else if components.first() == Some(&"from") {
+            match components.get(1) {
+                Some(path) => self.pick_from(&PathBuf::from(path)),
+                None => self.toast("usage: from <file>"),
+            }
+        } else if components.first() == Some(&"why") {
+            let note = components[1..].join(" ");
+            match self.current_chord_mut() {
+                Some(chord) if !note.is_empty() => {
+                    chord.question = true;
+                    chord.question_note = Some(note);
+                }
+                Some(_) => self.toast("usage: why <reason>"),
+                None => self.toast("no chord here"),
+            }
+        } else if components.first() == Some(&"invert") || components.first() == Some(&"swapbass") {
+            let spelling = self.song.spelling();
+            let invert = components[0] == "invert";
+            let message = match self.current_chord_mut() {
+                Some(chord) => {
+                    let changed = if invert {
+                        Some(chord.inverted(spelling))
+                    } else {
+                        chord.swapped_bass()
+                    };
+                    match changed {
+                        Some(changed) => {
+                            let message = format!("{} -> {}", chord, changed);
+                            *chord = changed;
+                            message
+                        }
+                        None => "no slash bass to swap".to_string(),
+                    }
+                }
+                None => "no chord here".to_string(),
+            };
+            self.toast(&message);
+        } else if components.first() == Some(&"questions") {
+            let lines = self
+                .song
+                .questions()
+                .into_iter()
+                .map(|(pos, chord)| {
+                    format!(
+                        "{:<8} {:<8} {}",
+                        self.song.location(pos),
+                        chord.to_string(),
+                        chord.question_note.as_deref().unwrap_or("")
+                    )
+                })
+                .collect::<Vec<String>>();
+            if lines.is_empty() {
+                self.toast("no questions");
+            } else {
+                self.schedule_clear();
+                self.show_overlay(" questions ", &lines, None);
+                self.win.getch();
+            }
+        } else if components.first() == Some(&"triage") {
+            self.triage();
+        } else if components.first() == Some(&"bdelete") || components.first() == Some(&"delbar") {
+            match components.get(1).map(|n| n.parse::<usize>()) {
+                None => self.delete_bars(1),
+                Some(Ok(n)) => self.delete_bars(n),
+                Some(Err(_)) => self.toast("usage: bdelete [count]"),
+            }
+        } else if components.first() == Some(&"practice") {
+            self.practice_command(&components[1..]);
+        } else if components.first() == Some(&"export") {
+            match components[1..] {
+                [format, path] => match ExportFormat::parse(format) {
+                    Some(format) => {
+                        match fs::write(path, format.render(&self.song, &self.settings)) {
+                            Ok(()) => self.toast(&format!("Exported {} to {}", format, path)),
+                            Err(e) => self.toast(&format!("{}: {}", path, e)),
+                        }
+                    }
+                    None => self.toast(&format!("unknown export format '{}'", format)),
+                },
+                _ => self.toast("usage: export <format> <path>"),
+            }
+        } else if components.first() == Some(&"autoexport") {
+            self.autoexport_command(&components[1..]);
+        } else if components.first() == Some(&"edit") {
+            if let Some(path) = components.get(1) {
+                if let Err(e) = self.load_from_disk(&PathBuf::from(path)) {
+                    self.refuse(&e);
+                }
+            }
+        } else if components.first() == Some(&"transpose") {
+            match parse_transpose(&components[1..]) {
+                Some((semitones, section, dry_run)) => {
+                    let only = section.then_some(self.cursor.section);
+                    self.transpose(semitones, dry_run, only)
+                }
+                None => self.toast("usage: transpose <semitones> [section] [--dry-run]"),
+            }
+        } else if components.first() == Some(&"color") {
+            if !has_colors() {
+                self.refuse("no colors on this terminal");
+            } else if !self.function_colors && self.song.key.is_none() {
+                self.refuse("coloring needs a key, set one with :key");
+            } else {
+                self.function_colors = !self.function_colors;
+                self.schedule_clear();
+                self.toast(if self.function_colors {
+                    "Colored by function"
+                } else {
+                    "Colors off"
+                });
+            }
+        } else if components.first() == Some(&"mode") {
+            match components.get(1).copied() {
+                Some("letters") => self.set_chord_display(ChordDisplay::Letters),
+                Some("numbers") => self.set_chord_display(ChordDisplay::Numbers),
+                Some("roman") => self.set_chord_display(ChordDisplay::Roman),
+                _ => self.toast("usage: mode letters|numbers|roman"),
+            }
+        } else if components.first() == Some(&"key") {
+            // no key shows it, "none" clears it
+            match components.get(1).copied() {
+                None => {
+                    let message = match self.song.key {
+                        Some(key) => format!("key: {}", key),
+                        None => "no key set".to_string(),
+                    };
+                    self.toast(&message);
+                }
+                Some("none") => {
+                    self.song.key = None;
+                    // the title line and roman numerals change with it
+                    self.schedule_clear();
+                    self.toast("Cleared key.");
+                }
+                Some(name) => match Key::parse(name) {
+                    Some(key) => {
+                        self.song.key = Some(key);
+                        self.schedule_clear();
+                        self.toast(&format!("Key set to {}.", key));
+                    }
+                    None => self.toast("usage: key <root>[m] | none"),
+                },
+            }
+        } else if components.first() == Some(&"help") {
+            self.show_help();
+        } else if components.first() == Some(&"structure") {
+            self.structure();
+        } else if components.first() == Some(&"print") || components.first() == Some(&"p") {
+            self.print(components.get(1).copied());
+        } else if (components.first() == Some(&"new") || components.first() == Some(&"n"))
+            && self.prompt_bool("Are you sure you want to clear your song?")
+        {
+            self.song = Song::new();
+            self.cursor = CursorPos::default();
+            self.filename = None;
+            self.json_style = None;
+        }
+    }
+
+    // the whole song, or with `only` just that section and the key left be
+    fn transpose(&mut self, semitones: i32, dry_run: bool, only: Option<usize>) {
+        let (transposed, lines) = self.song.transpose_preview(semitones, only);
+        self.schedule_clear();
+        let title = match only {
+            Some(index) => format!(
+                " transpose [{}] {:+} ",
+                self.song.sections[index].label, semitones
+            ),
+            None => format!(" transpose {:+} ", semitones),
+        };
+        self.show_overlay(&title, &lines, None);
+        if dry_run {
+            self.win.getch();
+            return;
+        }
+        if self.prompt_bool("Apply?") {
+            self.song = transposed;
+            match only {
+                Some(index) => self.toast(&format!(
+                    "Transposed [{}] {:+} semitones",
+                    self.song.sections[index].label, semitones
+                )),
+                None => self.toast(&format!("Transposed {:+} semitones", semitones)),
+            }
+        }
+    }
+
+    // bar picker over another song, the pick gets pasted after the cursor
+    fn pick_from(&mut self, path: &Path) {
+        let other = match read_song(path, self.settings.max_file_bytes()) {
+            Ok((mut song, _)) => {
+                song.split_oversized_bars(self.settings.max_chords_per_bar);
+                song
+            }
+            Err(e) => {
+                self.toast(&e);
+                return;
+            }
+        };
+        // section summaries are headings, only bar lines can be selected
+        let mut lines = Vec::new();
+        let mut bars: Vec<usize> = Vec::new(); // line index of each bar
+        for section in &other.sections {
+            lines.push(section.summary());
+            for (bar_i, bar) in section.bars.iter().enumerate() {
+                bars.push(lines.len());
+                lines.push(format!("  {:<3} {}", bar_i + 1, bar.chords_string()));
+            }
+        }
+        if bars.is_empty() {
+            self.toast("nothing to pick from");
+            return;
+        }
+
+        let mut selected = 0;
+        let mut anchor: Option<usize> = None;
+        loop {
+            let range = match anchor {
+                Some(a) => a.min(selected)..=a.max(selected),
+                None => selected..=selected,
+            };
+            let marked = lines
+                .iter()
+                .enumerate()
+                .map(|(i, line)| {
+                    let picked = bars
+                        .iter()
+                        .position(|&l| l == i)
+                        .is_some_and(|b| range.contains(&b));
+                    format!("{}{}", if picked { "* " } else { "  " }, line)
+                })
+                .collect::<Vec<String>>();
+            self.draw();
+            self.show_overlay(
+                " from (v: select range, enter: paste, esc: cancel) ",
+                &marked,
+                Some(bars[selected]),
+            );
+            match self.win.getch() {
+                Some(Input::KeyUp) => selected = selected.saturating_sub(1),
+                Some(Input::KeyDown) => selected = (selected + 1).min(bars.len() - 1),
+                Some(Input::Character('v')) => {
+                    anchor = if anchor.is_some() {
+                        None
+                    } else {
+                        Some(selected)
+                    }
+                }
+                Some(Input::Character('\n')) => {
+                    let picked = other.bars_in(range);
+                    self.paste_bars(self.cursor.bar + 1, picked);
+                    break;
+                }
+                Some(Input::Character('\u{1b}' | 'q')) | None => break,
+                _ => {}
+            }
+        }
+        self.schedule_clear();
+    }
+
+    // walk the flagged chords one at a time
+    fn triage(&mut self) {
+        let positions = self
+            .song
+            .questions()
+            .into_iter()
+            .map(|(pos, _)| pos)
+            .collect::<Vec<CursorPos>>();
+        if positions.is_empty() {
+            self.toast("no questions");
+            return;
+        }
+        for (i, pos) in positions.iter().enumerate() {
+            self.cursor = *pos;
+            self.draw();
+            let chord =
+                self.song.sections[pos.section].bars[pos.bar].chords[&pos.subdivision].clone();
+            let message = format!(
+                "{}/{} {} {}  c: clear, e: edit, s: skip, q: stop",
+                i + 1,
+                positions.len(),
+                chord,
+                chord.question_note.as_deref().unwrap_or("")
+            );
+            let answer = self.prompt_char(&message, vec!['c', 'e', 's', 'q']);
+            match triage_step(&mut self.song, *pos, answer) {
+                TriageStep::Next => {}
+                TriageStep::Edit => {
+                    // a freshly parsed chord comes without the flag
+                    let input = self.parsed_chord_input(*pos, "");
+                    self.cursor = *pos;
+                    match input.as_deref().map(str::trim) {
+                        None | Some("") => {}
+                        Some(input) if input.contains(' ') => {
+                            self.insert_spread_chords(*pos, input)
+                        }
+                        Some(input) => {
+                            if let Ok(new) = self.parse_chord(input) {
+                                self.song.sections[pos.section].bars[pos.bar]
+                                    .chords
+                                    .insert(pos.subdivision, new);
+                            }
+                        }
+                    }
+                }
+                TriageStep::Stop => break,
+            }
+        }
+        self.schedule_clear();
+    }
+
+    // section list overlay, doubles as a tiny arranger. the form goes on top
+    // and long songs are shown a page at a time
+    fn structure(&mut self) {
+        let mut selected = self.cursor.section;
+        loop {
+            let (max_y, max_x) = (self.win.get_max_y(), self.win.get_max_x());
+            // the overlay's borders, the form line and the prompt line
+            let per_page = (max_y - 4).max(1) as usize;
+            let count = self.song.sections.len();
+            let (range, page, pages) = page_of(selected, count, per_page);
+            let mut lines = vec![self.song.form((max_x - 4).max(1) as usize)];
+            lines.extend(
+                self.song.sections[range.clone()]
+                    .iter()
+                    .map(Section::summary),
+            );
+            let title = match pages {
+                1 => " structure (enter: go, J/K: move, r: rename, d: delete) ".to_string(),
+                _ => format!(
+                    " structure {}/{} (enter: go, J/K: move, r: rename, d: delete, PgUp/PgDn) ",
+                    page, pages
+                ),
+            };
+            self.draw();
+            self.show_overlay(&title, &lines, Some(selected - range.start + 1));
+            let key = match self.keys.translate(self.win.getch()) {
+                Some(Input::KeyUp) => StructureKey::Up,
+                Some(Input::KeyDown) => StructureKey::Down,
+                Some(Input::KeyPPage) => StructureKey::PageUp,
+                Some(Input::KeyNPage) => StructureKey::PageDown,
+                Some(Input::Character('\n')) => StructureKey::Go,
+                Some(Input::Character('J')) => StructureKey::MoveDown,
+                Some(Input::Character('K')) => StructureKey::MoveUp,
+                Some(Input::Character('r')) => match self.prompt_line("label? ") {
+                    Some(label) => StructureKey::Rename(label),
+                    None => continue,
+                },
+                Some(Input::Character('d' | 'D')) => {
+                    let label = self.song.sections[selected].label.clone();
+                    if !self.prompt_bool(&format!("Delete section {}?", label)) {
+                        continue;
+                    }
+                    StructureKey::Delete
+                }
+                Some(Input::Character('\u{1b}' | 'q')) | None => StructureKey::Close,
+                _ => continue,
+            };
+            match structure_key(
+                &mut self.song,
+                &mut self.cursor,
+                &mut selected,
+                per_page,
+                key,
+            ) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => self.toast(e),
+            }
+        }
+        self.schedule_clear();
+    }
+
+    // boxed window centered over the chart, stays up until the next full redraw
+    fn show_overlay(&mut self, title: &str, lines: &[String], selected: Option<usize>) {
+        let max_y = self.win.get_max_y();
+        let max_x = self.win.get_max_x();
+        let widest = lines
+            .iter()
+            .map(|l| l.chars().count())
+            .chain([title.chars().count()])
+            .max()
+            .unwrap_or(0) as i32;
+        // leave the bottom line free for prompts
+        let height = (lines.len() as i32 + 2).min(max_y - 1).max(3);
+        let width = (widest + 4).min(max_x);
+        let overlay = newwin(height, width, (max_y - 1 - height) / 2, (max_x - width) / 2);
+        overlay.draw_box(0, 0);
+        overlay.mvaddstr(0, 2, title);
+        let visible = (height - 2) as usize;
+        // scroll just enough to keep the selection on screen
+        let offset = selected.map_or(0, |sel| (sel + 1).saturating_sub(visible));
+        for (i, line) in lines.iter().skip(offset).take(visible).enumerate() {
+            if selected.is_none() && i + 1 == visible && lines.len() > visible {
+                overlay.mvaddstr(i as i32 + 1, 2, format!("... {} more", lines.len() - i));
+                break;
+            }
+            if selected == Some(i + offset) {
+                overlay.attron(Attribute::Reverse);
+            }
+            overlay.mvaddnstr(i as i32 + 1, 2, line, width - 4);
+            overlay.attroff(Attribute::Reverse);
+        }
+        overlay.refresh();
+    }
+
+    fn show_help(&mut self) {
+        let lines = [
+            "A-G       enter a chord",
+            "space     next subdivision",
+            "tab       next bar",
+            "o         new bar after this one",
+            "=         same bar again (count first: 3=)",
+            "s         next section",
+            "L         rename section (or :label)",
+            "arrows    move",
+            "S-Up/S-Dn move section up/down",
+            "S-Lt/S-Rt move bar left/right in its section (or < >)",
+            "PgUp/PgDn more/fewer subdivisions",
+            "Del       delete chord or empty bar",
+            "Shift+Del delete bar (count first: 3 Shift+Del)",
+            "u/U       undo/redo (Ctrl+R redoes too)",
+            "y/p/P     yank bar/paste it after/before this one",
+            "r         repeat section",
+            "+/-       chord up/down a semitone",
+            "?         flag chord (help on an empty cell)",
+            "!         accent chord (repeat sounding chord)",
+            "F5        letters/numbers/roman numerals",
+            "F1        this help, any key for the commands",
+            ":         command line",
+        ]
+        .map(String::from);
+        self.schedule_clear();
+        self.show_overlay(" help ", &lines, None);
+        if matches!(
+            self.win.getch(),
+            Some(Input::Character('\u{1b}' | 'q')) | None
+        ) {
+            return;
+        }
+        let commands = [
+            ":title/:subtitle <text>    name the song",
+            ":key <root>[m]|none        set the key",
+            ":save [file]  :edit <file> write and open songs",
+            ":new                       start over",
+            ":print [file]              html chart",
+            ":export <format> <file>    musicxml, txt, md, chordpro, ...",
+            ":autoexport add|list|remove exports on save",
+            ":header/:footer <text>     printed above and below",
+            ":set <option>=<value>      session options",
+            ":meter <beats>/<subs>      beats and cells of the bar",
+            ":time <beats>              beats of the bar, cells kept even",
+            ":transpose <n> [section]   move chords by semitones",
+            ":capo <fret>|off           show shapes for a capo",
+            ":mode letters|numbers|roman",
+            ":color                     color chords by function",
+            ":label <name>  :dup        rename, copy the section",
+            ":copysection :pastesection",
+            ":repeat <times>  :vamp     repeats and vamps",
+            ":duration [bpm]            how long it plays",
+            ":wrap <bars> [all] :align  row length and alignment",
+            ":mark segno|coda|ds|dc|dcfine|fine|none",
+            ":segno :coda :dc :ds       road-map sign shortcuts",
+            ":bdelete [count]           delete bars",
+            ":invert  :swapbass         slash chord bass",
+            ":why <text>  :questions    flag chords, list them",
+            ":triage                    walk the flagged chords",
+            ":go <label>  :bar <n>      jump to a section or bar",
+            ":structure                 section list and arranger",
+            ":from <file>               paste bars from another song",
+            ":practice tempo|note|done  practice log",
+            ":doctor                    terminal check",
+            ":help  :quit",
+        ]
+        .map(String::from);
+        self.show_overlay(" commands ", &commands, None);
+        self.win.getch();
+    }
+
+    // PgUp/PgDn, chords keep their place in the bar
+    fn rescale_bar(&mut self, change: impl Fn(usize) -> usize) {
+        let cursor = self.cursor;
+        let bar = &mut self.song.sections[cursor.section].bars[cursor.bar];
+        let (beats, subdivision) = (bar.beats, bar.subdivision);
+        match bar.set_meter(beats, change(subdivision), Retime::Rescale, false) {
+            Ok(_) => {
+                self.cursor.subdivision = cursor.subdivision * bar.subdivision / subdivision;
+                let message = format!("{} subdivisions", bar.subdivision);
+                self.toast(&message);
+            }
+            Err(e) => self.refuse(&e.to_string()),
+        }
+    }
+    // called after every key with the song as it was before it. anything
+    // that changed the song is an edit, unless a different file got opened
+    fn record_edit(&mut self, before: Song, cursor: CursorPos, filename: Option<PathBuf>) {
+        let (old, new) = (
+            serde_json::to_value(&before).unwrap_or_default(),
+            serde_json::to_value(&self.song).unwrap_or_default(),
+        );
+        if old == new {
+            return;
+        }
+        let from = first_changed_section(&old, &new);
+        self.outline_from = Some(self.outline_from.map_or(from, |f| f.min(from)));
+        if filename != self.filename {
+            // a different file, nothing of it is unsaved yet
+            self.undo_stack.clear();
+            self.unsaved_edits = 0;
+        } else {
+            self.unsaved_edits += 1;
+            self.undo_stack.push((before, cursor));
+            if self.undo_stack.len() > UNDO_LIMIT {
+                self.undo_stack.remove(0);
+            }
+        }
+        self.redo_stack.clear();
+    }
+    // quietly, a failed autosave is no reason to interrupt editing
+    fn autosave(&mut self) {
+        if let Ok(encoded) = serde_json::to_string(&self.song) {
+            let _ = fs::write(autosave_path(self.filename.as_deref()), encoded);
+        }
+        self.unsaved_edits = 0;
+    }
+    fn offer_recovery(&mut self) {
+        let recovery = autosave_path(self.filename.as_deref());
+        if !needs_recovery(&recovery, self.filename.as_deref()) {
+            return;
+        }
+        self.draw();
+        if !self.prompt_bool(&format!(
+            "Recover unsaved changes from {}?",
+            recovery.display()
+        )) {
+            // turned down once, not asked again
+            let _ = fs::remove_file(&recovery);
+            return;
+        }
+        match read_song(&recovery, self.settings.max_file_bytes()) {
+            Ok((song, _)) => {
+                self.song = song;
+                self.cursor = CursorPos::default();
+                self.schedule_clear();
+                self.toast("Recovered, :save to keep it");
+            }
+            Err(e) => self.refuse(&e),
+        }
+    }
+    // just the cursor chord, sharps going up and flats going down unless
+    // the song's key has a letter for the new note
+    fn nudge_chord(&mut self, semitones: i32) {
+        let spelling = if semitones > 0 {
+            Spelling::Sharps
+        } else {
+            Spelling::Flats
+        };
+        let key = self.song.key;
+        let Some(chord) = self.current_chord_mut() else {
+            self.refuse("no chord here");
+            return;
+        };
+        *chord = chord.transposed(semitones, spelling, key);
+        let message = chord.to_string();
+        self.toast(&message);
+    }
+    fn toggle_repeats(&mut self) {
+        let section = self.current_section_mut();
+        section.repeats = !section.repeats;
+        let message = format!(
+            "[{}] {}",
+            section.label,
+            if section.repeats {
+                "repeats"
+            } else {
+                "plays once"
+            }
+        );
+        self.schedule_clear();
+        self.toast(&message);
+    }
+    // the same sign again takes it off
+    fn toggle_marker(&mut self, marker: Marker) {
+        let cursor = self.cursor;
+        let Some(bar) = self.current_section_mut().bars.get_mut(cursor.bar) else {
+            self.refuse("no bar here");
+            return;
+        };
+        bar.marker = if bar.marker == Some(marker) {
+            None
+        } else {
+            Some(marker)
+        };
+        let message = match bar.marker {
+            Some(marker) => format!("{} over bar {}", marker, cursor.bar + 1),
+            None => format!("cleared bar {}", cursor.bar + 1),
+        };
+        self.schedule_clear();
+        self.toast(&message);
+    }
+    // first bar of the first section with that label, any case
+    fn go_to_section(&mut self, label: &str) {
+        match self
+            .song
+            .sections
+            .iter()
+            .position(|s| s.label.eq_ignore_ascii_case(label))
+        {
+            Some(section) => {
+                self.cursor = CursorPos {
+                    section,
+                    ..CursorPos::default()
+                };
+                self.schedule_clear();
+            }
+            None => self.refuse(&format!("no section '{}'", label)),
+        }
+    }
+    fn set_meter(&mut self, beats: usize, subdivision: usize, retime: Retime, truncate: bool) {
+        let cursor = self.cursor;
+        let bar = &mut self.song.sections[cursor.section].bars[cursor.bar];
+        match bar.set_meter(beats, subdivision, retime, truncate) {
+            Ok(dropped) => {
+                self.cursor.subdivision = cursor.subdivision.min(subdivision - 1);
+                self.schedule_clear();
+                if dropped.is_empty() {
+                    self.toast(&format!("{}/{}", beats, subdivision));
+                } else {
+                    let dropped: Vec<String> = dropped.iter().map(|c| c.to_string()).collect();
+                    self.toast(&format!("Dropped {}", dropped.join(" ")));
+                }
+            }
+            Err(e @ MeterError::Conflicts(_)) => {
+                self.refuse(&format!("{} (--truncate drops them)", e))
+            }
+            Err(e) => self.refuse(&e.to_string()),
+        }
+    }
+    fn clear_marker(&mut self) {
+        let cursor = self.cursor;
+        if let Some(bar) = self.current_section_mut().bars.get_mut(cursor.bar) {
+            bar.marker = None;
+        }
+        self.schedule_clear();
+        self.toast(&format!("cleared bar {}", cursor.bar + 1));
+    }
+    fn yank_bar(&mut self) {
+        let cursor = self.cursor;
+        self.register = Some(self.current_section().bars[cursor.bar].clone());
+        self.toast("yanked bar");
+    }
+    // like y then p, without touching the register. Cursor goes to the last copy
+    fn duplicate_bar(&mut self, count: usize) {
+        let cursor = self.cursor;
+        let Some(bar) = self.current_section().bars.get(cursor.bar).cloned() else {
+            self.refuse("no bar here");
+            return;
+        };
+        let at = cursor.bar + 1;
+        self.current_section_mut()
+            .bars
+            .splice(at..at, vec![bar; count]);
+        self.cursor.bar = at + count - 1;
+        self.cursor.subdivision = 0;
+        self.schedule_clear();
+    }
+    // `count` copies after the cursor bar, or before it
+    fn paste_bar(&mut self, count: usize, before: bool) {
+        let Some(bar) = self.register.clone() else {
+            self.refuse("nothing yanked");
+            return;
+        };
+        let at = if before {
+            self.cursor.bar
+        } else {
+            self.cursor.bar + 1
+        };
+        self.paste_bars(at, vec![bar; count]);
+    }
+    fn undo(&mut self) {
+        let Some((song, cursor)) = self.undo_stack.pop() else {
+            self.refuse("nothing to undo");
+            return;
+        };
+        let current = std::mem::replace(&mut self.song, song);
+        self.redo_stack.push((current, self.cursor));
+        self.unsaved_edits += 1;
+        self.cursor = cursor;
+        self.schedule_clear();
+    }
+    fn redo(&mut self) {
+        let Some((song, cursor)) = self.redo_stack.pop() else {
+            self.refuse("nothing to redo");
+            return;
+        };
+        let current = std::mem::replace(&mut self.song, song);
+        self.undo_stack.push((current, self.cursor));
+        self.unsaved_edits += 1;
+        self.cursor = cursor;
+        self.schedule_clear();
+    }
+    // the key did nothing, say so instead of staying silent
+    fn refuse(&mut self, message: &str) {
+        beep();
+        self.toast(message);
+    }
+
+    fn draw_toast(&mut self) {
+        if let Some(message) = &self.toast.message {
+            if self.toast.ticks == 0 {
+                self.draw_question_note();
+                return;
+            }
+            self.win.attron(Attribute::Reverse);
+            self.win.mvaddstr(self.win.get_max_y() - 1, 0, message);
+            self.win.attroff(Attribute::Reverse);
+            self.toast.ticks -= 1;
+        } else {
+            self.draw_question_note();
+        }
+    }
+
+    // status line for a flagged chord under the cursor, when no toast is showing
+    fn draw_question_note(&self) {
+        let cursor = self.cursor;
+        let note = self.song.sections[cursor.section].bars[cursor.bar]
+            .get_chord(cursor.subdivision)
+            .and_then(|c| c.question_note.as_ref());
+        if let Some(note) = note {
+            self.win
+                .mvaddstr(self.win.get_max_y() - 1, 0, format!("? {}", note));
+        }
+    }
+
+    fn toast(&mut self, message: &str) {
+        self.toast.message = Some(message.to_owned());
+        self.toast.ticks = 2;
+    }
+
+    fn prompt_bool(&mut self, message: &str) -> bool {
+        self.prompt_char(&format!("{} (y/n)", message), vec!['y', 'n'])
+            .is_some_and(|c| c == 'y')
+    }
+
+    fn prompt_char(&mut self, message: &str, allowable: Vec<char>) -> Option<char> {
+        self.win.attron(Attribute::Reverse);
+        curs_set(1);
+        self.win.mvaddstr(self.win.get_max_y() - 1, 0, message);
+        self.win.attroff(Attribute::Reverse);
+        let mut response: Option<Option<char>> = None;
+        while response.is_none() {
+            let ch = self.win.getch();
+            if let Some(Input::Character(c)) = ch {
+                if allowable.contains(&c) {
+                    response = Some(Some(c));
+                }
+            } else {
+                response = Some(None);
+            }
+        }
+        curs_set(0);
+        response.unwrap()
+    }
+
+    fn prompt_line(&mut self, message: &str) -> Option<String> {
+        let mut buf = String::new();
+        let mut finished = false;
+        let y = self.win.get_max_y() - 1;
+        let x = message.chars().count() as i32;
+        self.win.attron(Attribute::Reverse);
+        curs_set(1);
+        self.win.mvaddstr(y, 0, message);
+
+        let mut cancelled = false;
+
+        while !finished {
+            self.win.mvaddstr(y, x, &buf);
+            self.win
+                .hline(' ', self.win.get_max_x() - buf.chars().count() as i32);
+            let ch = self.win.getch();
+            if let Some(Input::Character(c)) = ch {
+                if c.is_ascii_alphanumeric() || c.is_ascii_punctuation() || c == ' ' {
+                    buf.push(c);
+                } else if c == '\u{8}' {
+                    buf.pop();
+                    self.win.mvaddstr(y, x, &buf);
+                    self.win.addch(' ');
+                } else if c == '\u{1b}' {
+                    finished = true;
+                    cancelled = true;
+                } else if c == '\t' {
+                    continue;
+                } else {
+                    finished = true;
+                }
+            } else {
+                finished = true;
+                cancelled = true;
+            }
+        }
+        self.win.attroff(Attribute::Reverse);
+        curs_set(0);
+        if cancelled {
+            None
+        } else {
+            Some(buf)
+        }
+    }
+
+    fn delete_chord_or_empty_bar(&mut self) {
+        let cursor = self.cursor;
+        // maybe even remove empty section
+        if self.current_section().bars.len() == 1
+            && self.current_section().bars[0].chords.is_empty()
+            && self.song.sections.len() > 1
+        {
+            self.song.sections.remove(self.cursor.section);
+            self.cursor.section -= 1;
+            self.cursor.bar = self.current_section().bars.len() - 1;
+            self.cursor.subdivision = self.current_section().bars[self.cursor.bar].beats - 1;
+            self.schedule_clear();
+            return;
+        }
+        let section = self.current_section_mut();
+        let current_bar = &section.bars[cursor.bar];
+
+        if current_bar.chords.is_empty() && section.bars.len() > 1 {
+            section.bars.remove(cursor.bar);
+            // put the cursor somewhere nice
+            if cursor.bar >= section.bars.len() {
+                self.cursor.bar -= 1;
+            }
+            self.schedule_clear();
+        } else {
+            section.bars[cursor.bar].chords.remove(&cursor.subdivision);
+        }
+    }
+    // removes `count` bars from the cursor on, chords and all
+    fn delete_bars(&mut self, count: usize) {
+        let range = self.song.bar_range(self.cursor, count);
+        let doomed = &self.current_section().bars[range.clone()];
+        let question = match doomed {
+            [bar] => format!("Delete bar with {} chords?", bar.chords.len()),
+            _ => format!("Delete {} bars and their chords?", doomed.len()),
+        };
+        if self.settings.confirm_bar_delete
+            && doomed.iter().any(|b| !b.chords.is_empty())
+            && !self.prompt_bool(&question)
+        {
+            return;
+        }
+
+        self.cursor = self.song.delete_bars(self.cursor, count);
+        self.schedule_clear();
+        self.toast(&format!("Deleted {} bars", range.len()));
+    }
+    fn next_or_create_section(&mut self) {
+        if self.cursor.section + 1 < self.song.sections.len() {
+            // next
+            self.cursor.section += 1;
+            self.cursor.bar = 0;
+            self.cursor.subdivision = 0;
+            return;
+        }
+        // create
+        let label = self.song.next_label();
+        let previous = self.song.sections.last().unwrap();
+        let new = Section {
+            label,
+            bars: vec![Bar::new(
+                previous.bars.last().unwrap().beats,
+                previous.bars.last().unwrap().subdivision,
+            )],
+            repeats: false,
+            repeat_count: 1,
+            vamp: false,
+            wrap: previous.wrap,
+            align: previous.align,
+        };
+        self.song.sections.push(new);
+        self.cursor.section += 1;
+        self.cursor.bar = 0;
+        self.cursor.subdivision = 0;
+    }
+    // a copy of the cursor section right after it, labelled past every
+    // generated label so it can't clash with one further on
+    fn duplicate_section(&mut self) {
+        let section = self.current_section().clone();
+        self.insert_section_copy(section);
+    }
+    fn copy_section(&mut self) {
+        let section = self.current_section().clone();
+        let message = format!("copied [{}]", section.label);
+        self.section_register = Some(section);
+        self.toast(&message);
+    }
+    fn paste_section(&mut self) {
+        let Some(section) = self.section_register.clone() else {
+            self.refuse("no section copied");
+            return;
+        };
+        self.insert_section_copy(section);
+    }
+    // goes in after the cursor section under the next free label
+    fn insert_section_copy(&mut self, section: Section) {
+        let index = self.cursor.section;
+        let copy = Section {
+            label: self.song.next_label(),
+            ..section
+        };
+        let message = format!("[{}] copied to [{}]", section.label, copy.label);
+        self.song.sections.insert(index + 1, copy);
+        self.cursor = CursorPos {
+            section: index + 1,
+            bar: 0,
+            subdivision: 0,
+        };
+        self.schedule_clear();
+        self.toast(&message);
+    }
+    // swaps the cursor bar with its neighbour, never across sections
+    fn move_bar(&mut self, left: bool) -> bool {
+        let index = self.cursor.bar;
+        let other = if left {
+            match index.checked_sub(1) {
+                Some(i) => i,
+                None => return false,
+            }
+        } else if index + 1 < self.current_section().bars.len() {
+            index + 1
+        } else {
+            return false;
+        };
+        self.current_section_mut().bars.swap(index, other);
+        self.cursor.bar = other;
+        // column widths follow the bars
+        self.schedule_clear();
+        true
+    }
+    fn move_section(&mut self, index: usize, up: bool) -> bool {
+        if !self.song.move_section(index, up, &mut self.cursor) {
+            return false;
+        }
+        self.schedule_clear();
+        true
+    }
+    fn rename_section(&mut self, index: usize, label: &str) -> bool {
+        if let Err(e) = self.song.rename_section(index, label) {
+            self.toast(e);
+            return false;
+        }
+        self.schedule_clear();
+        true
+    }
+    // inserted before bar `at` of the cursor section, cursor lands on the
+    // first pasted bar
+    fn paste_bars(&mut self, at: usize, bars: Vec<Bar>) {
+        let count = bars.len();
+        self.song.insert_bars(self.cursor.section, at, bars);
+        self.cursor.bar = at;
+        self.cursor.subdivision = 0;
+        self.schedule_clear();
+        self.toast(&format!("Pasted {} bars", count));
+    }
+    // "Title - from Somewhere" reads better as a title and a subtitle
+    fn offer_title_split(&mut self) {
+        if self.song.subtitle.is_some() {
+            return;
+        }
+        let Some((title, subtitle)) = [" \u{2014} ", " - "]
+            .iter()
+            .find_map(|sep| self.song.title.split_once(sep))
+            .map(|(t, s)| (t.trim().to_owned(), s.trim().to_owned()))
+        else {
+            return;
+        };
+        self.draw();
+        if self.prompt_bool(&format!(
+            "Split into '{}' and subtitle '{}'?",
+            title, subtitle
+        )) {
+            self.song.title = title;
+            self.song.subtitle = Some(subtitle);
+            self.schedule_clear();
+        }
+    }
+    fn save_to_disk(&mut self, path: &Path) -> Result<(), String> {
+        let style = JsonStyle::for_save(self.settings.save_format, self.json_style, || {
+            self.prompt_char("Save as (p)retty or (c)ompact JSON?", vec!['p', 'c'])
+        });
+        self.json_style = Some(style);
+        self.song.version = FORMAT_VERSION;
+        let encoded = match style {
+            JsonStyle::Pretty => serde_json::to_string_pretty(&self.song),
+            JsonStyle::Compact => serde_json::to_string(&self.song),
+        };
+        // the song stays in memory either way, so a bad path is just retyped
+        encoded
+            .map_err(|e| e.to_string())
+            .and_then(|encoded| fs::write(path, encoded).map_err(|e| e.to_string()))
+            .map_err(|e| format!("couldn't save {}: {}", path.display(), e))?;
+        // saved for real, nothing left to recover. that includes the temp dir
+        // one from before the song had a name, or one under its old name
+        let _ = fs::remove_file(autosave_path(Some(path)));
+        let _ = fs::remove_file(autosave_path(self.filename.as_deref()));
+        self.filename = Some(path.to_path_buf());
+        self.unsaved_edits = 0;
+
+        let progress = Progress {
+            win: &self.win,
+            what: "exporting",
+            total: self.song.auto_exports.len(),
+        };
+        let run = self
+            .song
+            .write_auto_exports(path, &self.settings, |i| progress.tick(i));
+        self.toast(&run.summary(path));
+        Ok(())
+    }
+    fn practice_command(&mut self, args: &[&str]) {
+        match PracticeCommand::parse(args) {
+            Ok(PracticeCommand::Tempo(tempo)) => {
+                self.song
+                    .practice
+                    .get_or_insert_with(Practice::default)
+                    .target_tempo = Some(tempo);
+                self.toast(&format!("Tempo goal {}", tempo));
+            }
+            Ok(PracticeCommand::Note(note)) => {
+                self.song
+                    .practice
+                    .get_or_insert_with(Practice::default)
+                    .notes = Some(note);
+                self.toast("Practice note saved");
+            }
+            Ok(PracticeCommand::Done) => {
+                let today = today();
+                let bump = self.prompt_line("tempo today (enter to skip)? ");
+                let practice = self.song.practice.get_or_insert_with(Practice::default);
+                practice.last_practiced = Some(today.clone());
+                if let Some(Ok(tempo)) = bump.filter(|b| !b.is_empty()).map(|b| b.trim().parse()) {
+                    practice.current_tempo = Some(tempo);
+                }
+                self.toast(&format!("Practiced {}", today));
+            }
+            Err(usage) => self.toast(usage),
+        }
+        self.schedule_clear();
+    }
+    fn autoexport_command(&mut self, args: &[&str]) {
+        match args {
+            ["add", format, path] => match ExportFormat::parse(format) {
+                Some(format) => {
+                    self.song.auto_exports.push(AutoExport {
+                        format,
+                        path: PathBuf::from(path),
+                    });
+                    self.toast(&format!("Will export {} to {} on save", format, path));
+                }
+                None => self.toast(&format!("unknown export format '{}'", format)),
+            },
+            ["list"] => {
+                let lines = self
+                    .song
+                    .auto_exports
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| format!("{}. {} -> {}", i + 1, e.format, e.path.display()))
+                    .collect::<Vec<String>>();
+                if lines.is_empty() {
+                    self.toast("no auto exports");
+                    return;
+                }
+                self.schedule_clear();
+                self.show_overlay(" auto exports ", &lines, None);
+                self.win.getch();
+            }
+            ["remove", n] => match n.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= self.song.auto_exports.len() => {
+                    let removed = self.song.auto_exports.remove(n - 1);
+                    self.toast(&format!("Removed export to {}", removed.path.display()));
+                }
+                _ => self.toast(&format!("no auto export number {}", n)),
+            },
+            _ => self.toast("usage: autoexport add <format> <path> | list | remove <n>"),
+        }
+    }
+    fn load_from_disk(&mut self, path: &Path) -> Result<(), String> {
+        let (json_style, added) = self
+            .song
+            .replace_from(path, &self.settings)
+            .map_err(|e| format!("couldn't open {}", e))?;
+        self.json_style = Some(json_style);
+        self.filename = Some(path.to_path_buf());
+        if added > 0 {
+            self.toast(&format!("Split crowded bars into {} extra bars", added));
+        }
+        self.offer_title_split();
+        let max_wrap = self.settings.max_wrap;
+        if let Some(section) = self.song.sections.iter().find(|s| s.wrap > max_wrap) {
+            self.toast(&format!(
+                "warning: section {} wraps at {} bars",
+                section.label, section.wrap
+            ));
+        }
+        Ok(())
+    }
+    // html next to where we were started, <title>.html unless told otherwise
+    fn print(&mut self, path: Option<&str>) {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let name: String = self
+                    .song
+                    .title
+                    .chars()
+                    .map(|c| {
+                        if c.is_alphanumeric() || c == '-' {
+                            c
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect();
+                PathBuf::from(format!("{}.html", name))
+            }
+        };
+        match fs::write(&path, self.song.to_html(&self.settings, self.capo)) {
+            Ok(()) => self.toast(&format!("Printed to {}", path.display())),
+            Err(e) => self.refuse(&format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+// never practiced sorts before everything, ISO dates sort as strings. the
+// sort is stable so ties keep the order they were given in
+fn stalest_first<T>(songs: &mut [(T, Song)]) {
+    songs.sort_by_key(|(_, song)| {
+        song.practice
+            .as_ref()
+            .and_then(|p| p.last_practiced.clone())
+    });
+}
+
+// one line per song file, stalest first with --sort last_practiced
+fn inventory(args: &[String], max_bytes: u64) {
+    let mut paths = Vec::new();
+    let mut sort = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--inventory" => {}
+            "--sort" => sort = iter.next().map(String::as_str),
+            _ => paths.push(arg),
+        }
+    }
+    let mut songs = paths
+        .into_iter()
+        .filter_map(|path| match read_song(Path::new(path), max_bytes) {
+            Ok((song, _)) => Some((path, song)),
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        })
+        .collect::<Vec<(&String, Song)>>();
+    if sort == Some("last_practiced") {
+        stalest_first(&mut songs);
+    }
+    for (path, song) in songs {
+        let practice = song
+            .practice
+            .as_ref()
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        println!("{}\t{}\t{}", path, song.title, practice);
+    }
+}
+
+// chordchart --export <format> <song> [out], stdout when there's no out
+fn export(format: &str, song: &Path, out: Option<&Path>, settings: &Settings) {
+    let Some(format) = ExportFormat::parse(format) else {
+        eprintln!("unknown export format '{}'", format);
+        std::process::exit(1);
+    };
+    let (mut song, _) = read_song(song, settings.max_file_bytes()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let added = song.split_oversized_bars(settings.max_chords_per_bar);
+    if added > 0 {
+        eprintln!("split crowded bars into {} extra bars", added);
+    }
+    let mut rendered = format.render(&song, settings);
+    match out {
+        Some(path) => {
+            if let Err(e) = fs::write(path, rendered) {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        None => {
+            if format.is_text() {
+                rendered.push(b'\n');
+            }
+            std::io::stdout().write_all(&rendered).unwrap();
+        }
+    }
+}
+
+const USAGE: &str = "usage: chordchart [song.json]
+       chordchart --export <html|layout|midi|musicxml|txt|md|chordpro> <song.json> [out]
+       chordchart --inventory <song.json>... [--sort last_practiced]
+       chordchart --doctor
+options:
+       --max-chords-per-bar <n>  split crowded bars on load
+       --vamp-times <n>          times round a vamp in MIDI, 4 by default
+       --width <n>               columns to fit the layout export into";
+
+fn main() {
+    let mut args = std::env::args().collect::<Vec<String>>();
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let mut settings = Settings::default();
+    if let Some(i) = args.iter().position(|a| a == "--max-chords-per-bar") {
+        let value = args.get(i + 1).cloned().unwrap_or_default();
+        if let Err(e) = settings.set("max_chords_per_bar", &value) {
+            eprintln!("{}", e);
+            return;
+        }
+        args.drain(i..(i + 2).min(args.len()));
+    }
+    if let Some(i) = args.iter().position(|a| a == "--vamp-times") {
+        let value = args.get(i + 1).cloned().unwrap_or_default();
+        if let Err(e) = settings.set("vamp_times", &value) {
+            eprintln!("{}", e);
+            return;
+        }
+        args.drain(i..(i + 2).min(args.len()));
+    }
+    if let Some(i) = args.iter().position(|a| a == "--width") {
+        let value = args.get(i + 1).cloned().unwrap_or_default();
+        if let Err(e) = settings.set("layout_width", &value) {
+            eprintln!("{}", e);
+            return;
+        }
+        args.drain(i..(i + 2).min(args.len()));
+    }
+    if args.iter().any(|a| a == "--inventory") {
+        inventory(&args[1..], settings.max_file_bytes());
+        return;
+    }
+    if let [_, flag, format, song, rest @ ..] = &args[..] {
+        if flag == "--export" {
+            export(
+                format,
+                Path::new(song),
+                rest.first().map(Path::new),
+                &settings,
+            );
+            return;
+        }
+    }
+
+    let window = initscr();
+    window.keypad(true);
+    noecho();
+    curs_set(0);
+
+    if args.iter().any(|a| a == "--doctor") {
+        let keys = KeyConfig::load();
+        let (report, suggested) = doctor(&mut Curses(&window), &keys);
+        endwin();
+        println!("{}", report.join("\n"));
+        if let (false, Some(path)) = (suggested.is_empty(), &keys.path) {
+            println!(
+                "\nadd to {} (or run :doctor in the editor):",
+                path.display()
+            );
+            for (name, c) in suggested {
+                println!("{} = {}", name, c);
+            }
+        }
+        return;
+    }
+    if has_colors() {
+        start_color();
+        use_default_colors();
+        Function::init_colors();
+    }
+
+    let mut state = State {
+        win: window,
+        settings,
+        song: Song::new(),
+        cursor: CursorPos::default(),
+        should_clear: true,
+        should_quit: false,
+        toast: Toast::default(),
+        filename: None,
+        pending_duplicate: None,
+        count: None,
+        json_style: None,
+        chord_display: ChordDisplay::Letters,
+        function_colors: false,
+        capo: 0,
+        undo_stack: Vec::new(),
+        redo_stack: Vec::new(),
+        register: None,
+        section_register: None,
+        unsaved_edits: 0,
+        scroll: 0,
+        keys: KeyConfig::load(),
+        outline: Outline::default(),
+        outline_from: Some(0),
+    };
+    // chordchart song.json, a path that isn't there yet is where :save will go
+    if let Some(path) = args.get(1).filter(|a| !a.starts_with("--")) {
+        let path = PathBuf::from(path);
+        if path.exists() {
+            if let Err(e) = state.load_from_disk(&path) {
+                state.refuse(&e);
+            }
+        } else {
+            state.toast(&format!("new file {}", path.display()));
+            state.filename = Some(path);
+        }
+    }
+    state.offer_recovery();
+
+    loop {
+        // draw
+        state.draw();
+        // get input
+        let input = state.keys.translate(state.win.getch());
+        let count = state.count.take();
+        // a warned duplicate only goes in if the very next thing is committing it again
+        let pending = state.pending_duplicate.take();
+        // cloning the song for undo on every arrow key adds up on long charts
+        let before = (!leaves_song_alone(input))
+            .then(|| (state.song.clone(), state.cursor, state.filename.clone()));
+        match input {
+            Some(Input::Character(c)) => match c {
+                '0'..='9' => {
+                    let digit = c.to_digit(10).unwrap() as usize;
+                    state.count = Some(count.unwrap_or(0) * 10 + digit);
+                }
+                '\t' => state.next_or_create_bar(),
+                'o' => state.insert_bar_after(),
+                'L' => {
+                    if let Some(label) = state.prompt_line("label? ") {
+                        let index = state.cursor.section;
+                        state.rename_section(index, &label);
+                    }
+                }
+                '=' => state.duplicate_bar(count.unwrap_or(1).max(1)),
+                ' ' => state.next_subdivision(),
+                's' => state.next_or_create_section(),
+                ':' => state.do_command_line(),
+                'u' => state.undo(),
+                'y' => state.yank_bar(),
+                'r' => state.toggle_repeats(),
+                '+' => state.nudge_chord(count.unwrap_or(1) as i32),
+                '-' => state.nudge_chord(-(count.unwrap_or(1) as i32)),
+                'p' => state.paste_bar(count.unwrap_or(1), false),
+                'P' => state.paste_bar(count.unwrap_or(1), true),
+                '\u{12}' | 'U' => state.redo(), // ctrl-r
+                '?' => match state.current_chord_mut() {
+                    Some(chord) => chord.toggle_question(),
+                    None => state.show_help(),
+                },
+                // "hit it again here, accented" on an empty cell
+                '!' => {
+                    if !state.song.accent(state.cursor) {
+                        state.refuse("no chord to accent");
+                    }
+                }
+                _ => {
+                    state.pending_duplicate = pending;
+                    state.input_or_edit_in_place_chord(c)
+                }
+            },
+            Some(Input::KeyDC) => {
+                // DEL
+                state.delete_chord_or_empty_bar();
+            }
+            Some(Input::KeySDC) => {
+                // shift+DEL, the whole bar
+                state.delete_bars(count.unwrap_or(1));
+            }
+            Some(Input::KeyNPage) => {
+                state.win.touch();
+                state.rescale_bar(|subdivision| subdivision / 2);
+            }
+            Some(Input::KeyPPage) => {
+                state.rescale_bar(|subdivision| subdivision * 2);
+            }
+
+            Some(Input::KeyF4) => {
+                state.next_or_create_bar();
+            }
+            Some(Input::KeyF3) => {
+                state.prev_bar();
+            }
+            Some(Input::KeyF1) => state.show_help(),
+            Some(Input::KeyResize) => {
+                // pick up the new size, then redraw everything at it
+                resize_term(0, 0);
+                state.schedule_clear();
+            }
+            Some(Input::KeyF5) => {
+                // letters, numbers, roman and round again
+                state.set_chord_display(match state.chord_display {
+                    ChordDisplay::Letters => ChordDisplay::Numbers,
+                    ChordDisplay::Numbers => ChordDisplay::Roman,
+                    ChordDisplay::Roman => ChordDisplay::Letters,
+                });
+            }
+            // shift+up/down, the section swaps with its neighbour
+            Some(Input::KeySR) if !state.move_section(state.cursor.section, true) => {
+                state.refuse("already the first section");
+            }
+            Some(Input::KeySF) if !state.move_section(state.cursor.section, false) => {
+                state.refuse("already the last section");
+            }
+            // shift+left/right, the bar swaps with its neighbour
+            Some(Input::KeySLeft) if !state.move_bar(true) => {
+                state.refuse("already the first bar of the section");
+            }
+            Some(Input::KeySRight) if !state.move_bar(false) => {
+                state.refuse("already the last bar of the section");
+            }
+            Some(Input::KeySLeft | Input::KeySRight) => {}
+            Some(Input::KeyRight) => {
+                state.next_subdivision();
+            }
+            Some(Input::KeyLeft) => {
+                state.prev_subdivision();
+            }
+            Some(Input::KeyUp) => {
+                state.cursor = state.song.row_above(state.cursor);
+            }
+            Some(Input::KeyDown) => {
+                state.cursor = state.song.row_below(state.cursor);
+            }
+            Some(_) => {}
+            None => (),
+        }
+        if let Some((song, cursor, filename)) = before {
+            state.record_edit(song, cursor, filename);
+        }
+        if state.unsaved_edits >= AUTOSAVE_EVERY {
+            state.autosave();
+        }
+        if state.should_quit {
+            break;
+        }
+    }
+    endwin();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(s: &str) -> Chord {
+        Chord::parse(s).unwrap()
+    }
+
+    // a song with a section per list, each bar given by its subdivisions
+    fn song(sections: &[&[usize]]) -> Song {
+        let mut song = Song::new();
+        let template = song.sections.remove(0);
+        for (i, bars) in sections.iter().enumerate() {
+            song.sections.push(Section {
+                label: section_label(i),
+                bars: bars.iter().map(|&s| Bar::new(4, s)).collect(),
+                ..template.clone()
+            });
+        }
+        song
+    }
+
+    // chords by subdivision, spelled the way they're typed
+    fn put(song: &mut Song, section: usize, bar: usize, chords: &[(usize, &str)]) {
+        for &(subdivision, symbol) in chords {
+            song.sections[section].bars[bar]
+                .chords
+                .insert(subdivision, chord(symbol));
+        }
+    }
+
+    fn at(section: usize, bar: usize, subdivision: usize) -> CursorPos {
+        CursorPos {
+            section,
+            bar,
+            subdivision,
+        }
+    }
+
+    #[test]
+    fn every_shape_parses_back_from_its_symbol() {
+        for (quality, extensions) in Quality::shapes() {
+            let parsed = chord(&format!("D{}", shape_symbol(quality, &extensions)));
+            assert_eq!((parsed.quality, parsed.extensions), (quality, extensions));
+        }
+    }
+
+    fn shape_symbol(quality: Quality, extensions: &[Extension]) -> &'static str {
+        Chord {
+            quality,
+            extensions: extensions.to_vec(),
+            ..chord("C")
+        }
+        .quality_symbol()
+    }
+
+    #[test]
+    fn old_quality_names_migrate() {
+        let old = [
+            ("Maj", "C"),
+            ("Min", "C-"),
+            ("Dom7", "C7"),
+            ("Maj7", "C^"),
+            ("Min7", "C-7"),
+            ("Dim", "Co"),
+            ("Dim7", "Co7"),
+            ("HalfDim", "C-7b5"),
+            ("Aug", "C+"),
+            ("Aug7", "C+7"),
+            ("Dom9", "C9"),
+            ("Maj9", "C^9"),
+            ("Min9", "C-9"),
+            ("Flat9", "C7b9"),
+            ("Sharp9", "C7#9"),
+            ("Dom11", "C11"),
+            ("Maj11", "C^11"),
+            ("Min11", "C-11"),
+            ("Sharp11", "C7#11"),
+            ("Dom13", "C13"),
+            ("Maj13", "C^13"),
+            ("Flat13", "C7b13"),
+            ("Alt", "C7alt"),
+            ("Sus", "Csus"),
+            ("Sus4", "Csus"),
+            ("Sus2", "Csus2"),
+            ("Sus7", "C7sus4"),
+            ("Add9", "Cadd9"),
+            ("Add11", "Cadd11"),
+            ("Add13", "Cadd13"),
+            ("Maj6", "C6"),
+            ("Min6", "Cm6"),
+            ("Maj69", "C6/9"),
+            ("Min69", "Cm6/9"),
+        ];
+        for (name, symbol) in old {
+            let mut json = serde_json::json!({
+                "version": 1,
+                "title": "old",
+                "sections": [{
+                    "label": "A",
+                    "repeats": false,
+                    "repeat_count": 1,
+                    "wrap": 4,
+                    "bars": [{
+                        "beats": 4,
+                        "subdivision": 1,
+                        "chords": {"0": {
+                            "note": "C",
+                            "accidental": "None",
+                            "quality": name,
+                            "over": null,
+                            "alterations": [{"accidental": "Sharp", "degree": 11}],
+                            "special": false,
+                            "question": false,
+                        }},
+                    }],
+                }],
+            });
+            Song::migrate(&mut json).unwrap();
+            let song: Song = serde_json::from_value(json).unwrap();
+            assert_eq!(song.version, FORMAT_VERSION);
+            let migrated = &song.sections[0].bars[0].chords[&0];
+            let expected = match symbol {
+                "C" => "C(#11)".to_string(),
+                _ => format!("{}#11", symbol),
+            };
+            assert_eq!(migrated.to_string(), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn unknown_old_quality_is_an_error() {
+        let mut json = serde_json::json!({
+            "title": "old",
+            "sections": [{"label": "A", "bars": [{"beats": 4, "subdivision": 1, "chords": {
+                "0": {"note": "C", "accidental": "None", "quality": "Dom15", "over": null,
+                      "special": false, "question": false},
+            }}]}],
+        });
+        assert!(Song::migrate(&mut json).is_err());
+    }
+
+    #[test]
+    fn current_files_are_left_alone() {
+        let song = Song::new();
+        let mut json = serde_json::to_value(&song).unwrap();
+        let before = json.clone();
+        Song::migrate(&mut json).unwrap();
+        assert_eq!(json, before);
+    }
+
+    #[test]
+    fn newer_files_are_refused() {
+        let mut json = serde_json::json!({"version": FORMAT_VERSION + 1});
+        assert!(Song::migrate(&mut json).is_err());
+    }
+
+    #[test]
+    fn extensions_combine_with_alterations() {
+        let c = chord("C-7add11");
+        assert_eq!(
+            (c.quality, c.extensions.as_slice()),
+            (Quality::Min, &[Extension::Seventh][..])
+        );
+        assert_eq!(c.to_string(), "C-7add11");
+        let f = chord("Fmaj7#11");
+        assert_eq!(
+            (f.quality, f.extensions.as_slice()),
+            (Quality::Maj, &[Extension::Seventh][..])
+        );
+        assert_eq!(f.to_string(), "F^#11");
+        assert_eq!(chord("Cm7b5"), chord("Cø7"));
+        assert_eq!(chord("C7b9"), chord("Cdom7b9"));
+    }
+
+    #[test]
+    fn stacked_alterations_keep_their_order() {
+        let alterations = |s: &str| {
+            chord(s)
+                .alterations
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(alterations("C7b9"), ["b9"]);
+        assert_eq!(alterations("F7#9#5"), ["#9", "#5"]);
+        assert_eq!(alterations("Bb13b9"), ["b9"]);
+        assert_eq!(chord("Bb13b9").extensions, [Extension::Thirteenth]);
+        for s in ["C7b9", "F7#9#5", "Bb13b9", "G7(#5,b9)"] {
+            assert_eq!(chord(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parentheses_dont_change_the_chord() {
+        let hash = |c: &Chord| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            c.hash(&mut hasher);
+            hasher.finish()
+        };
+        for (plain, listed) in [
+            ("C7b9", "C7(b9)"),
+            ("G7#5b9", "G7(#5,b9)"),
+            ("F^#11", "Fmaj7(#11)"),
+            ("Cm7b5", "C-7(b5)"),
+            ("Cadd9", "C(add9)"),
+        ] {
+            assert_eq!(chord(plain), chord(listed));
+            assert_eq!(hash(&chord(plain)), hash(&chord(listed)));
+        }
+        // still written the way they were typed
+        assert_eq!(chord("C7(b9)").to_string(), "C7(b9)");
+        assert_ne!(chord("C7b9"), chord("C7#9"));
+        assert_eq!(chord("C7#5b9"), chord("C7b9#5"));
+        assert_eq!(hash(&chord("C7#5b9")), hash(&chord("C7b9#5")));
+    }
+
+    #[test]
+    fn every_chord_round_trips_through_its_symbol() {
+        use Accidental::{Flat, Sharp};
+        let natural = Accidental::None;
+        let notes = [
+            Note::A,
+            Note::B,
+            Note::C,
+            Note::D,
+            Note::E,
+            Note::F,
+            Note::G,
+        ];
+        let alterations = |list: &[(Accidental, u8)]| {
+            list.iter()
+                .map(|&(accidental, degree)| Alteration { accidental, degree })
+                .collect::<Vec<_>>()
+        };
+        let mut overs = vec![(None, natural)];
+        for note in notes {
+            for accidental in [natural, Sharp, Flat] {
+                overs.push((Some(note), accidental));
+            }
+        }
+        let mut checked = 0;
+        for (quality, extensions) in Quality::shapes() {
+            for note in notes {
+                for accidental in [natural, Sharp, Flat] {
+                    for &(over, over_accidental) in &overs {
+                        for tensions in [
+                            alterations(&[]),
+                            alterations(&[(Flat, 9)]),
+                            alterations(&[(Sharp, 5), (Flat, 9)]),
+                            alterations(&[(natural, 11)]),
+                        ] {
+                            for parenthesized in [false, true] {
+                                let chord = Chord {
+                                    note,
+                                    accidental,
+                                    quality,
+                                    extensions: extensions.clone(),
+                                    over,
+                                    over_accidental,
+                                    alterations: tensions.clone(),
+                                    parenthesized,
+                                    ..chord("C")
+                                };
+                                let symbol = chord.to_string();
+                                assert_eq!(Chord::parse(&symbol), Ok(chord), "{}", symbol);
+                                checked += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert!(checked > 10_000);
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        for s in ["C7!", "C7?", "Db-7b5/Ab!?", "%", "N.C.", "%!", "N.C.?"] {
+            assert_eq!(chord(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn left_from_a_section_start_lands_on_the_previous_sections_end() {
+        let song = song(&[&[4, 2], &[4]]);
+        assert_eq!(song.prev_subdivision(at(1, 0, 0)), at(0, 1, 1));
+        assert_eq!(song.prev_subdivision(at(0, 1, 0)), at(0, 0, 3));
+        assert_eq!(song.prev_subdivision(at(0, 0, 0)), at(0, 0, 0));
+        // and from there on without leaving the bars
+        let mut cursor = at(1, 0, 0);
+        for _ in 0..10 {
+            cursor = song.prev_subdivision(cursor);
+            assert!(
+                cursor.subdivision < song.sections[cursor.section].bars[cursor.bar].subdivision
+            );
+        }
+    }
+
+    #[test]
+    fn left_from_a_section_start_after_an_empty_section() {
+        let song = song(&[&[4], &[], &[2]]);
+        assert_eq!(song.prev_subdivision(at(2, 0, 0)), at(1, 0, 0));
+        assert_eq!(song.prev_subdivision(at(1, 0, 0)), at(0, 0, 3));
+        assert_eq!(song.prev_bar(at(2, 0, 0)), at(1, 0, 0));
+        assert_eq!(song.prev_bar(at(1, 0, 0)), at(0, 0, 0));
+    }
+
+    #[test]
+    fn up_keeps_the_column_inside_shorter_bars() {
+        let song = song(&[&[2], &[4, 8, 4]]);
+        assert_eq!(song.prev_bar(at(1, 1, 6)), at(1, 0, 3));
+        assert_eq!(song.prev_bar(at(1, 2, 2)), at(1, 1, 2));
+        assert_eq!(song.prev_bar(at(1, 0, 2)), at(1, 0, 0));
+        assert_eq!(song.prev_bar(at(1, 0, 0)), at(0, 0, 0));
+    }
+
+    #[test]
+    fn wrap_wider_than_the_section() {
+        let mut song = song(&[&[4], &[4, 4, 4], &[2, 2]]);
+        for section in &mut song.sections {
+            section.wrap = 8;
+        }
+        let settings = Settings::default();
+        for (section, bars) in song.sections.iter().zip([1, 3, 2]) {
+            assert_eq!(section.effective_wrap(), bars);
+            assert_eq!(section.rows(bars), 1);
+            assert_eq!(
+                song.calc_widths(&settings, section, Notation::Letters, &[], bars)
+                    .len(),
+                bars
+            );
+        }
+        let layout = song.layout(&settings, Notation::Letters);
+        let rows: Vec<_> = layout.sections.iter().map(|s| s.rows.len()).collect();
+        assert_eq!(rows, [1, 3, 2].map(|_| 1));
+        // a label line, one row and a blank line each
+        let ys: Vec<_> = layout.sections.iter().map(|s| s.rows[0].y).collect();
+        assert_eq!(ys[1] - ys[0], 3);
+        assert_eq!(ys[2] - ys[1], 3);
+        // up and down stay in the section's single row
+        assert_eq!(song.row_below(at(0, 0, 2)), at(0, 0, 3));
+        assert_eq!(song.row_above(at(0, 0, 2)), at(0, 0, 0));
+        assert_eq!(song.row_below(at(1, 0, 1)), at(1, 2, 3));
+        assert_eq!(song.row_above(at(1, 2, 1)), at(1, 0, 0));
+        assert_eq!(song.row_above(at(1, 1, 3)), at(1, 0, 0));
+        // only the start of the top row leaves the section
+        assert_eq!(song.row_above(at(1, 0, 0)), at(0, 0, 0));
+        // the short bars of the last section keep the cursor inside them
+        assert_eq!(song.row_below(at(2, 0, 1)), at(2, 1, 1));
+    }
+
+    #[test]
+    fn wrap_narrower_than_the_section() {
+        let mut song = song(&[&[4; 5]]);
+        song.sections[0].wrap = 2;
+        assert_eq!(song.sections[0].rows(song.sections[0].effective_wrap()), 3);
+        assert_eq!(song.row_below(at(0, 0, 2)), at(0, 2, 2));
+        assert_eq!(song.row_below(at(0, 4, 2)), at(0, 4, 3));
+        assert_eq!(song.row_above(at(0, 3, 2)), at(0, 1, 2));
+    }
+
+    #[test]
+    fn transpose_preview_lists_bars_with_chords() {
+        let mut song = song(&[&[4, 4], &[4]]);
+        put(&mut song, 0, 0, &[(0, "C"), (2, "G7")]);
+        put(&mut song, 1, 0, &[(0, "F#-7")]);
+        let (transposed, lines) = song.transpose_preview(2, None);
+        assert_eq!(
+            lines,
+            [
+                "spelling: sharps",
+                "",
+                "A1   C G7  ->  D A7",
+                "B1   F#-7  ->  G#-7",
+            ]
+        );
+        assert_eq!(transposed.sections[1].bars[0].chords_string(), "G#-7");
+        // nothing applied until asked
+        assert_eq!(song.sections[0].bars[0].chords_string(), "C G7");
+    }
+
+    #[test]
+    fn transpose_preview_of_one_section() {
+        let mut song = song(&[&[4], &[4]]);
+        put(&mut song, 0, 0, &[(0, "Bb")]);
+        put(&mut song, 1, 0, &[(0, "Eb^"), (2, "C-7")]);
+        let (transposed, lines) = song.transpose_preview(-1, Some(1));
+        assert_eq!(lines, ["spelling: flats", "", "B1   Eb^ C-7  ->  D^ B-7"]);
+        assert_eq!(transposed.sections[0].bars[0].chords_string(), "Bb");
+    }
+
+    #[test]
+    fn distribute_chords_splits_evenly_on_beats() {
+        assert_eq!(distribute_chords(0, 1, 4, 16), Some(vec![0]));
+        assert_eq!(distribute_chords(0, 2, 4, 16), Some(vec![0, 8]));
+        assert_eq!(distribute_chords(0, 3, 4, 16), Some(vec![0, 4, 12]));
+        assert_eq!(distribute_chords(0, 4, 4, 4), Some(vec![0, 1, 2, 3]));
+        assert_eq!(distribute_chords(8, 2, 4, 16), Some(vec![8, 12]));
+        assert_eq!(distribute_chords(0, 2, 3, 6), Some(vec![0, 4]));
+    }
+
+    #[test]
+    fn distribute_chords_refuses_what_doesnt_fit() {
+        assert_eq!(distribute_chords(0, 0, 4, 4), None);
+        assert_eq!(distribute_chords(0, 5, 4, 4), None);
+        assert_eq!(distribute_chords(3, 2, 4, 4), None);
+        assert_eq!(distribute_chords(5, 1, 4, 4), None);
+    }
+
+    #[test]
+    fn distribute_chords_positions_are_in_order_and_in_the_bar() {
+        for subdivision in 1..=16 {
+            for beats in 0..=7 {
+                for start in 0..=subdivision {
+                    for count in 1..=subdivision - start {
+                        let positions = distribute_chords(start, count, beats, subdivision)
+                            .unwrap_or_else(|| {
+                                panic!("{} {} {} {}", start, count, beats, subdivision)
+                            });
+                        assert_eq!(positions.len(), count);
+                        assert_eq!(positions[0], start);
+                        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+                        assert!(*positions.last().unwrap() < subdivision);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn delete_bars_cursor_matrix() {
+        // (cursor, count) -> cursor after, bars left in the cursor's section
+        let cases = [
+            (at(1, 0, 2), 1, at(1, 0, 0), 2),
+            (at(1, 1, 2), 1, at(1, 0, 0), 2),
+            (at(1, 2, 2), 1, at(1, 1, 0), 2),
+            (at(1, 0, 0), 2, at(1, 0, 0), 1),
+            (at(1, 1, 0), 2, at(1, 0, 0), 1),
+            // cut short at the section's end
+            (at(1, 2, 0), 5, at(1, 1, 0), 2),
+            (at(1, 1, 0), 5, at(1, 0, 0), 1),
+            // a count of 0 is one bar
+            (at(1, 1, 0), 0, at(1, 0, 0), 2),
+        ];
+        for (cursor, count, after, left) in cases {
+            let mut song = song(&[&[4], &[4, 4, 4], &[4]]);
+            assert_eq!(song.bar_range(cursor, count).len(), 3 - left);
+            assert_eq!(
+                song.delete_bars(cursor, count),
+                after,
+                "{:?} {}",
+                cursor,
+                count
+            );
+            assert_eq!(song.sections.len(), 3);
+            assert_eq!(song.sections[1].bars.len(), left);
+        }
+    }
+
+    #[test]
+    fn deleting_every_bar_deletes_the_section() {
+        for (section, after) in [(0, at(0, 0, 0)), (1, at(1, 0, 0)), (2, at(1, 0, 0))] {
+            let mut song = song(&[&[4, 4], &[2], &[4, 8]]);
+            let label = song.sections[section].label.clone();
+            let bars = song.sections[section].bars.len();
+            assert_eq!(song.delete_bars(at(section, 0, 1), bars), after);
+            assert_eq!(song.sections.len(), 2);
+            assert!(song.sections.iter().all(|s| s.label != label));
+        }
+    }
+
+    #[test]
+    fn deleting_every_bar_of_the_only_section_leaves_an_empty_bar() {
+        let mut song = song(&[&[4, 8]]);
+        put(&mut song, 0, 1, &[(0, "C")]);
+        assert_eq!(song.delete_bars(at(0, 0, 3), 2), at(0, 0, 0));
+        assert_eq!(song.sections.len(), 1);
+        let bars = &song.sections[0].bars;
+        assert_eq!(bars.len(), 1);
+        // in the meter of the last bar
+        assert_eq!(bars[0].subdivision, 8);
+        assert!(bars[0].chords.is_empty());
+    }
+
+    #[test]
+    fn split_oversized_bars_keeps_order_and_meter() {
+        let mut song = song(&[&[16, 4], &[8]]);
+        put(
+            &mut song,
+            0,
+            0,
+            &[
+                (0, "C"),
+                (2, "D-"),
+                (4, "E-"),
+                (6, "F"),
+                (8, "G"),
+                (10, "A-"),
+            ],
+        );
+        put(&mut song, 0, 1, &[(0, "C"), (1, "G")]);
+        song.sections[0].bars[0].marker = Some(Marker::Segno);
+        put(
+            &mut song,
+            1,
+            0,
+            &(0..8).map(|i| (i, "Bb7")).collect::<Vec<_>>(),
+        );
+        song.sections[1].bars[0].beats = 3;
+
+        assert_eq!(song.split_oversized_bars(4), 2);
+        let a = &song.sections[0].bars;
+        let strings: Vec<_> = a.iter().map(|b| b.chords_string()).collect();
+        assert_eq!(strings, ["C D- E- F", "G A-", "C G"]);
+        // one cell per beat while that's enough
+        assert_eq!(
+            a[0].chords.keys().copied().collect::<Vec<_>>(),
+            [0, 1, 2, 3]
+        );
+        assert_eq!((a[1].beats, a[1].subdivision), (4, 4));
+        assert_eq!(a[1].chords.keys().copied().collect::<Vec<_>>(), [0, 2]);
+        // the marker stays on the first part, the small bar is untouched
+        assert!(a[0].marker == Some(Marker::Segno) && a[1].marker.is_none());
+        assert_eq!(a[2].subdivision, 4);
+        // three beats can't hold four chords, so the cells halve
+        let b = &song.sections[1].bars;
+        assert_eq!(b.len(), 2);
+        assert!(b.iter().all(|bar| bar.beats == 3 && bar.subdivision == 6));
+        assert!(b.iter().all(|bar| bar.chords.len() == 4));
+    }
+
+    #[test]
+    fn split_oversized_bars_off_or_under_the_limit() {
+        let mut song = song(&[&[8]]);
+        put(
+            &mut song,
+            0,
+            0,
+            &(0..8).map(|i| (i, "C")).collect::<Vec<_>>(),
+        );
+        assert_eq!(song.split_oversized_bars(0), 0);
+        assert_eq!(song.split_oversized_bars(8), 0);
+        assert_eq!(song.sections[0].bars.len(), 1);
+        assert_eq!(song.split_oversized_bars(1), 7);
+        assert_eq!(song.sections[0].bars.len(), 8);
+    }
+
+    #[test]
+    fn set_meter_never_loses_a_chord_silently() {
+        let symbols = ["C", "D-", "E-", "F", "G7", "A-", "Bo", "C^"];
+        // every set of occupied cells in bars of up to 8 subdivisions, into
+        // every meter, both ways of retiming, with and without truncate
+        for from in 1..=8usize {
+            for occupied in 0..1u32 << from {
+                let mut bar = Bar::new(4, from);
+                for i in (0..from).filter(|i| occupied & 1 << i != 0) {
+                    bar.chords.insert(i, chord(symbols[i]));
+                }
+                let before: Vec<_> = bar.chords.values().cloned().collect();
+                for to in 0..=17 {
+                    for retime in [Retime::Keep, Retime::Rescale] {
+                        for truncate in [false, true] {
+                            let mut changed = bar.clone();
+                            match changed.set_meter(3, to, retime, truncate) {
+                                Err(MeterError::OutOfRange) => {
+                                    assert!(to == 0 || to > 16);
+                                    assert_eq!(changed.subdivision, from);
+                                }
+                                Err(MeterError::Conflicts(positions)) => {
+                                    assert!(!truncate && !positions.is_empty());
+                                    assert_eq!(changed.chords.len(), before.len());
+                                    assert_eq!(changed.subdivision, from);
+                                }
+                                Ok(dropped) => {
+                                    assert!((1..=16).contains(&to));
+                                    assert!(truncate || dropped.is_empty());
+                                    assert_eq!((changed.beats, changed.subdivision), (3, to));
+                                    assert!(changed.chords.keys().all(|&i| i < to));
+                                    // what's kept keeps its order, and with
+                                    // what was dropped adds up to what was there
+                                    let kept: Vec<_> = changed.chords.values().collect();
+                                    assert_eq!(kept.len() + dropped.len(), before.len());
+                                    let mut rest = before.iter();
+                                    assert!(kept.iter().all(|k| rest.any(|c| c == *k)));
+                                    if matches!(retime, Retime::Keep) {
+                                        assert!(changed
+                                            .chords
+                                            .iter()
+                                            .all(|(i, c)| bar.chords.get(i) == Some(c)));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extended_qualities_parse() {
+        use Extension::*;
+        for (symbol, quality, extensions) in [
+            ("C9", Quality::Dom, &[Ninth][..]),
+            ("C11", Quality::Dom, &[Eleventh]),
+            ("Bb13", Quality::Dom, &[Thirteenth]),
+            ("Dm9", Quality::Min, &[Ninth]),
+            ("Dm11", Quality::Min, &[Eleventh]),
+            ("D-13", Quality::Min, &[Thirteenth]),
+            ("F^9", Quality::Maj, &[Ninth]),
+            ("FM11", Quality::Maj, &[Eleventh]),
+            ("Fmaj13", Quality::Maj, &[Thirteenth]),
+            ("G6/9", Quality::Maj, &[Sixth, Ninth]),
+            ("Gm69", Quality::Min, &[Sixth, Ninth]),
+            ("A7alt", Quality::Dom, &[Alt]),
+        ] {
+            let c = chord(symbol);
+            assert_eq!(
+                (c.quality, c.extensions.as_slice()),
+                (quality, extensions),
+                "{}",
+                symbol
+            );
+        }
+        assert_eq!(chord("C9").voicing(), [60, 64, 67, 70, 74]);
+        assert_eq!(chord("C^13").voicing(), [60, 64, 67, 71, 81]);
+    }
+
+    #[test]
+    fn unknown_qualities_are_errors() {
+        assert_eq!(
+            Chord::parse("C15"),
+            Err(ChordParseError::UnknownQuality("15".to_string()))
+        );
+        assert!(matches!(
+            Chord::parse("Dmaj8"),
+            Err(ChordParseError::UnknownQuality(_))
+        ));
+        assert_eq!(Chord::parse("H7"), Err(ChordParseError::BadRoot('H')));
+        assert_eq!(Chord::parse(""), Err(ChordParseError::Empty));
+        // the regex isn't anchored at the end, so a word that starts like a
+        // chord is read as one with a quality nobody knows
+        assert_eq!(
+            Chord::parse("Chicken"),
+            Err(ChordParseError::UnknownQuality("hicken".to_string()))
+        );
+        assert_eq!(
+            Chord::parse("Cxyz"),
+            Err(ChordParseError::UnknownQuality("xyz".to_string()))
+        );
+        // and anything after a bass, parentheses, ! or ? is left over
+        for (s, rest) in [
+            ("G/Bxyz", "xyz"),
+            ("C7(b9)x", "x"),
+            ("F!!", "!"),
+            ("A-7?ish", "ish"),
+        ] {
+            assert_eq!(
+                Chord::parse(s),
+                Err(ChordParseError::TrailingGarbage(rest.to_string())),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn inverting_and_swapping_the_bass() {
+        for (from, to) in [
+            ("C", "C/E"),
+            ("C/E", "C/G"),
+            ("C/G", "C"),
+            ("C/C", "C/E"),
+            ("C7", "C7/E"),
+            ("C7/G", "C7/Bb"),
+            ("C7/Bb", "C7"),
+            ("C-7", "C-7/Eb"),
+            ("C-7/Eb", "C-7/G"),
+            ("Eb", "Eb/G"),
+            ("Eb/G", "Eb/Bb"),
+            ("F#-", "F#-/A"),
+            ("Co7/Gb", "Co7/A"),
+            ("C/D", "C/E"),
+            ("A-7/F#", "A-7/C"),
+            ("N.C.", "N.C."),
+        ] {
+            assert_eq!(
+                chord(from).inverted(Spelling::Sharps).to_string(),
+                to,
+                "{}",
+                from
+            );
+        }
+        assert_eq!(chord("Bb").inverted(Spelling::Flats).to_string(), "Bb/D");
+
+        for (from, to) in [("G/B", "B/G?"), ("D-7/C", "C-7/D?"), ("Eb^/Bb", "Bb^/Eb?")] {
+            let swapped = chord(from).swapped_bass().unwrap();
+            assert_eq!(swapped.to_string(), to, "{}", from);
+            assert!(swapped.question, "{}", from);
+        }
+        assert_eq!(chord("G7").swapped_bass(), None);
+    }
+
+    #[test]
+    fn transpose_arguments() {
+        assert_eq!(parse_transpose(&["+5"]), Some((5, false, false)));
+        assert_eq!(parse_transpose(&["-1"]), Some((-1, false, false)));
+        assert_eq!(parse_transpose(&["2"]), Some((2, false, false)));
+        assert_eq!(parse_transpose(&["section", "-1"]), Some((-1, true, false)));
+        assert_eq!(
+            parse_transpose(&["--dry-run", "+5", "section"]),
+            Some((5, true, true))
+        );
+        assert_eq!(parse_transpose(&[]), None);
+        assert_eq!(parse_transpose(&["up"]), None);
+        assert_eq!(parse_transpose(&["--dry-run"]), None);
+    }
+
+    #[test]
+    fn transpose_up_a_fourth_and_down_a_semitone() {
+        let mut song = song(&[&[4]]);
+        put(
+            &mut song,
+            0,
+            0,
+            &[(0, "C^"), (1, "A-7"), (2, "D-7/C"), (3, "G7b9")],
+        );
+        let up = song.transposed(5, song.spelling());
+        assert_eq!(up.sections[0].bars[0].chords_string(), "F^ D-7 G-7/F C7b9");
+        let down = song.transposed(-1, song.spelling());
+        assert_eq!(
+            down.sections[0].bars[0].chords_string(),
+            "B^ Ab-7 Db-7/B Gb7b9"
+        );
+        let down = song.transposed(-1, Spelling::Sharps);
+        assert_eq!(
+            down.sections[0].bars[0].chords_string(),
+            "B^ G#-7 C#-7/B F#7b9"
+        );
+        // a round trip, and the octave, come back to the same chords
+        let back = up.transposed(-5, Spelling::Flats);
+        assert_eq!(
+            back.sections[0].bars[0].chords,
+            song.sections[0].bars[0].chords
+        );
+        let octave = song.transposed(-12, Spelling::Flats);
+        assert_eq!(
+            octave.sections[0].bars[0].chords,
+            song.sections[0].bars[0].chords
+        );
+        // N.C. and % have nothing to move
+        put(&mut song, 0, 0, &[(1, "N.C."), (2, "%")]);
+        let up = song.transposed(5, Spelling::Flats);
+        assert_eq!(up.sections[0].bars[0].chords_string(), "F^ N.C. % C7b9");
+    }
+
+    #[test]
+    fn musicxml_harmony_frames() {
+        assert_eq!(
+            chord("F#-7").to_musicxml(),
+            "      <harmony><root><root-step>F</root-step><root-alter>1</root-alter></root>\
+             <kind text=\"-7\">minor-seventh</kind></harmony>\n"
+        );
+        assert_eq!(
+            chord("Bb7b9/D").to_musicxml(),
+            "      <harmony><root><root-step>B</root-step><root-alter>-1</root-alter></root>\
+             <kind text=\"7\">dominant</kind><bass><bass-step>D</bass-step></bass>\
+             <degree><degree-value>9</degree-value><degree-alter>-1</degree-alter>\
+             <degree-type>add</degree-type></degree></harmony>\n"
+        );
+        assert!(chord("N.C.")
+            .to_musicxml()
+            .contains("<kind text=\"N.C.\">none</kind>"));
+        assert!(chord("C6/9")
+            .to_musicxml()
+            .contains("major-sixth</kind><degree><degree-value>9</degree-value>"));
+    }
+
+    #[test]
+    fn musicxml_measures() {
+        let mut song = song(&[&[4, 2], &[3]]);
+        song.title = "Salt & Pepper".to_string();
+        song.sections[1].bars[0].beats = 3;
+        put(&mut song, 0, 0, &[(0, "C"), (2, "A-")]);
+        put(&mut song, 0, 1, &[(0, "%")]);
+        put(&mut song, 1, 0, &[(1, "G7")]);
+        let xml = song.to_musicxml();
+        assert!(xml.contains("<work-title>Salt &amp; Pepper</work-title>"));
+        assert_eq!(xml.matches("<measure ").count(), 3);
+        assert!(xml.contains("<measure number=\"3\">"));
+        // divisions fit 4, 2 and 3 cells to a bar, the time only when it changes
+        assert_eq!(xml.matches("<divisions>12</divisions>").count(), 2);
+        assert!(xml.contains("<time><beats>4</beats>"));
+        assert!(xml.contains("<time><beats>3</beats>"));
+        assert_eq!(xml.matches("<rehearsal>").count(), 2);
+        // every measure's rests fill its beats, 4 * 12 / 4 per cell here
+        for measure in xml.split("<measure ").skip(1) {
+            let total: usize = measure
+                .split("<duration>")
+                .skip(1)
+                .map(|d| d.split('<').next().unwrap().parse::<usize>().unwrap())
+                .sum();
+            let beats = if measure.contains("<rehearsal>B") {
+                3
+            } else {
+                4
+            };
+            assert_eq!(total, beats * 12);
+        }
+        // % sounds as the chord it repeats
+        assert_eq!(xml.matches("<root-step>A</root-step>").count(), 2);
+        assert_eq!(xml.matches("<harmony>").count(), 4);
+    }
+
+    #[test]
+    fn alternate_quality_spellings() {
+        for (typed, same_as) in [
+            ("Cmaj7", "C^"),
+            ("CM7", "C^"),
+            ("C^7", "C^"),
+            ("CΔ", "C^"),
+            ("CΔ7", "C^"),
+            ("Cmaj", "C"),
+            ("Cmaj9", "C^9"),
+            ("Dmin", "D-"),
+            ("Dm", "D-"),
+            ("Dmin7", "D-7"),
+            ("Dm7", "D-7"),
+            ("Gdom7", "G7"),
+            ("Gdom9", "G9"),
+            ("Bhd", "B-7b5"),
+            ("Bø", "B-7b5"),
+            ("Bdim7", "Bo7"),
+            ("Caug", "C+"),
+            ("Caug7", "C+7"),
+        ] {
+            assert_eq!(chord(typed), chord(same_as), "{}", typed);
+            assert_eq!(chord(typed).to_string(), same_as, "{}", typed);
+        }
+    }
+
+    fn text_fixture() -> Song {
+        let mut song = song(&[&[4, 4, 2], &[4, 4]]);
+        song.title = "Blue".to_string();
+        song.sections[0].wrap = 2;
+        song.sections[1].repeats = true;
+        song.sections[1].repeat_count = 2;
+        put(&mut song, 0, 0, &[(0, "C^"), (2, "A-7")]);
+        put(&mut song, 0, 1, &[(0, "D-7"), (2, "G7b9")]);
+        put(&mut song, 0, 2, &[(0, "C6/9")]);
+        put(&mut song, 1, 0, &[(0, "F#-7b5")]);
+        put(&mut song, 1, 1, &[(0, "B7"), (3, "%")]);
+        song
+    }
+
+    #[test]
+    fn text_export_draws_the_grid() {
+        let text = text_fixture().to_text(&Settings::default());
+        assert_eq!(
+            text,
+            "SONG: Blue
+
+[A]
+|C^        A-7       |D-7       G7b9      |
+|C6/9      |
+
+[B]
+|:F#-7b5                      |B7       %  :|"
+        );
+        let settings = Settings {
+            bar_numbers: true,
+            section_headers: HeaderStyle::Inline,
+            ..Settings::default()
+        };
+        assert_eq!(
+            text_fixture().to_text(&settings),
+            "SONG: Blue
+
+[A] 1 |C^        A-7       |D-7       G7b9      |
+    3 |C6/9      |
+
+[B] 4 |:F#-7b5                      |B7       %  :|"
+        );
+    }
+
+    #[test]
+    fn normalized_keeps_only_what_sounds() {
+        let mut flagged = chord("C^9!?");
+        flagged.question_note = Some("check the bass".to_string());
+        assert_ne!(flagged, chord("Cmaj9"));
+        assert_eq!(flagged.normalized(false), chord("Cmaj9"));
+        assert_eq!(
+            chord("C^7").normalized(false),
+            chord("CM7").normalized(false)
+        );
+        assert_eq!(
+            chord("G7(b9)").normalized(false),
+            chord("G7b9").normalized(false)
+        );
+        assert_ne!(
+            chord("C-7").normalized(false),
+            chord("C7").normalized(false)
+        );
+        assert_ne!(chord("C/E").normalized(false), chord("C").normalized(false));
+        // N.C. and % are the same whatever they carry, but not each other
+        assert_eq!(
+            chord("N.C.!").normalized(false),
+            chord("N.C.").normalized(false)
+        );
+        assert_eq!(chord("%?").normalized(true), chord("%").normalized(true));
+        assert_ne!(chord("N.C.").normalized(true), chord("%").normalized(true));
+    }
+
+    #[test]
+    fn normalized_enharmonic_roots_and_basses() {
+        for (a, b) in [
+            ("C#-7", "Db-7"),
+            ("Cb", "B"),
+            ("E#7", "F7"),
+            ("Fb^", "E^"),
+            ("B#o7", "Co7"),
+            ("Ab/C", "G#/B#"),
+            ("D/F#", "D/Gb"),
+        ] {
+            assert_ne!(
+                chord(a).normalized(false),
+                chord(b).normalized(false),
+                "{}",
+                a
+            );
+            assert_eq!(
+                chord(a).normalized(true),
+                chord(b).normalized(true),
+                "{}",
+                a
+            );
+        }
+        assert_ne!(chord("C#").normalized(true), chord("D").normalized(true));
+    }
+
+    #[test]
+    fn transposed_chords_take_the_target_keys_letters() {
+        let key = |s: &str| Key::parse(s).unwrap();
+        let moved = |symbol: &str, semitones: i32, spelling: Spelling, into: &str| {
+            chord(symbol)
+                .transposed(semitones, spelling, Some(key(into)))
+                .to_string()
+        };
+        // in Eb it's Ab and Bb even when the chart leans on sharps
+        assert_eq!(moved("G#^", 0, Spelling::Sharps, "Eb"), "Ab^");
+        assert_eq!(moved("F7/A", 5, Spelling::Sharps, "Eb"), "Bb7/D");
+        assert_eq!(moved("C#-7", -5, Spelling::Sharps, "Eb"), "Ab-7");
+        // the scale's own letters, even the unusual ones
+        assert_eq!(moved("C", -1, Spelling::Sharps, "Gb"), "Cb");
+        assert_eq!(moved("E/G", 1, Spelling::Flats, "F#"), "E#/G#");
+        // F minor, Ab Db and Eb
+        assert_eq!(moved("G#", 0, Spelling::Sharps, "Fm"), "Ab");
+        assert_eq!(moved("C#^", 0, Spelling::Sharps, "Fm"), "Db^");
+        assert_eq!(moved("D#7", 0, Spelling::Sharps, "Fm"), "Eb7");
+        // off the scale the spelling decides
+        assert_eq!(moved("C", 1, Spelling::Flats, "C"), "Db");
+        assert_eq!(moved("C", 1, Spelling::Sharps, "C"), "C#");
+        // no key at all
+        assert_eq!(
+            chord("A").transposed(1, Spelling::Sharps, None).to_string(),
+            "A#"
+        );
+        assert_eq!(
+            chord("A").transposed(1, Spelling::Flats, None).to_string(),
+            "Bb"
+        );
+    }
+
+    #[test]
+    fn widths_count_chars_not_bytes() {
+        let mut song = song(&[&[2, 2, 2, 2], &[2]]);
+        song.sections[0].label = "Überleitung".to_string();
+        song.sections[0].wrap = 2;
+        put(&mut song, 0, 0, &[(0, "Bb7"), (1, "F#-7")]);
+        put(&mut song, 0, 3, &[(0, "C")]);
+        song.sections[0].bars[1].marker = Some(Marker::DaCapo);
+        let settings = Settings {
+            unicode: true,
+            section_headers: HeaderStyle::Inline,
+            ..Settings::default()
+        };
+        // the label is 11 chars in 12 bytes, plus brackets and a space
+        let layout = song.layout(&settings, Notation::Letters);
+        assert_eq!(layout.gutter, 14);
+        // the at-end marker sits flush with the bar's last cell
+        let bar = &layout.sections[0].rows[0].bars[1];
+        let end = bar.cells.last().map(|c| c.x + c.width).unwrap();
+        assert_eq!(bar.marker_x(Marker::DaCapo), end - 4);
+
+        let text = song.to_text(&settings);
+        assert!(text.contains("B♭7") && text.contains("F♯-7"));
+        let barlines = |line: &str| -> Vec<usize> {
+            line.chars()
+                .enumerate()
+                .filter(|&(_, c)| c == '|')
+                .map(|(i, _)| i)
+                .collect()
+        };
+        let rows: Vec<_> = text
+            .lines()
+            .filter(|l| l.contains('|'))
+            .map(barlines)
+            .collect();
+        // both rows of the first section have their barlines in the same columns
+        assert_eq!(rows[0], rows[1]);
+        assert_eq!(rows[0].len(), 3);
+        assert_eq!(rows[0][0], 14);
+        // the marker over the second bar lines up with its cells, not its bytes
+        let header = text.lines().find(|l| l.contains("D.C.")).unwrap();
+        assert!(header.starts_with("[Überleitung]"));
+        let dc = header[..header.find("D.C.").unwrap()].chars().count();
+        assert_eq!(dc, rows[0][2] - 4);
+    }
+
+    #[test]
+    fn read_song_migrates_unversioned_files() {
+        // a file from before versioning, with the old flat quality names
+        let v0 = r#"{
+            "title": "old",
+            "sections": [{
+                "label": "A",
+                "bars": [{
+                    "beats": 4,
+                    "subdivision": 4,
+                    "chords": {
+                        "0": {"note": "G", "accidental": "None", "quality": "Flat9",
+                              "over": null, "special": false, "question": false},
+                        "2": {"note": "B", "accidental": "None", "quality": "HalfDim",
+                              "over": null, "special": false, "question": false}
+                    }
+                }],
+                "repeats": false,
+                "wrap": 4
+            }]
+        }"#;
+        let path = std::env::temp_dir().join(format!("chordchart-v0-{}.json", std::process::id()));
+        fs::write(&path, v0).unwrap();
+        let (song, data) = read_song(&path, u64::MAX).unwrap();
+        assert_eq!(song.version, FORMAT_VERSION);
+        assert_eq!(data, v0);
+        let chords = &song.sections[0].bars[0].chords;
+        assert_eq!(chords[&0], chord("G7b9"));
+        assert_eq!(chords[&2], chord("B-7b5"));
+
+        // and one from a newer build is refused rather than half-read
+        fs::write(&path, format!(r#"{{"version": {}}}"#, FORMAT_VERSION + 1)).unwrap();
+        let newer = read_song(&path, u64::MAX);
+        fs::remove_file(&path).unwrap();
+        assert!(newer.err().unwrap().contains("newer chordchart"));
+    }
+
+    #[test]
+    fn bar_numbers_run_on_across_sections() {
+        let mut song = song(&[&[4; 11], &[4; 3]]);
+        song.sections[0].wrap = 4;
+        song.sections[1].wrap = 4;
+        let settings = Settings {
+            bar_numbers: true,
+            ..Settings::default()
+        };
+        let layout = song.layout(&settings, Notation::Letters);
+        let numbers: Vec<Vec<Option<usize>>> = layout
+            .sections
+            .iter()
+            .map(|s| s.rows.iter().map(|r| r.number).collect())
+            .collect();
+        assert_eq!(numbers, [vec![Some(1), Some(5), Some(9)], vec![Some(12)]]);
+        // two digits and a space ahead of every barline
+        assert_eq!(layout.gutter, 3);
+
+        let text = song.to_text(&settings);
+        let starts: Vec<&str> = text
+            .lines()
+            .filter(|l| l.contains('|'))
+            .map(|l| &l[..4])
+            .collect();
+        assert_eq!(starts, [" 1 |", " 5 |", " 9 |", "12 |"]);
+
+        let off = song.layout(&Settings::default(), Notation::Letters);
+        assert_eq!(off.gutter, 0);
+        assert!(off
+            .sections
+            .iter()
+            .flat_map(|s| &s.rows)
+            .all(|r| r.number.is_none()));
+    }
+
+    #[test]
+    fn cancelled_auto_exports_keep_what_was_written() {
+        let dir = std::env::temp_dir().join(format!("chordchart-exports-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.json");
+        let mut song = text_fixture();
+        let export = |format, name: &str| AutoExport {
+            format,
+            path: PathBuf::from(name),
+        };
+        song.auto_exports = vec![
+            export(ExportFormat::Text, "song.txt"),
+            export(ExportFormat::Markdown, "missing/song.md"),
+            export(ExportFormat::ChordPro, "song.chordpro"),
+            export(ExportFormat::Html, "song.html"),
+        ];
+        let settings = Settings::default();
+
+        // escape lands while the third export is up next
+        let mut asked = Vec::new();
+        let run = song.write_auto_exports(&path, &settings, |i| {
+            asked.push(i);
+            i < 2
+        });
+        assert_eq!(asked, [0, 1, 2]);
+        assert_eq!(
+            fs::read(dir.join("song.txt")).unwrap(),
+            ExportFormat::Text.render(&song, &settings)
+        );
+        assert!(!dir.join("song.chordpro").exists());
+        assert!(!dir.join("song.html").exists());
+        let summary = run.summary(&path);
+        assert!(
+            summary.contains("exports cancelled after 2/4"),
+            "{}",
+            summary
+        );
+        assert!(summary.contains("missing/song.md"), "{}", summary);
+
+        // left alone, a failure doesn't stop the ones after it
+        let run = song.write_auto_exports(&path, &settings, |_| true);
+        assert_eq!(run.attempted, 4);
+        assert!(!run.summary(&path).contains("cancelled"));
+        assert!(dir.join("song.chordpro").exists() && dir.join("song.html").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // answers the doctor without a terminal, receiving only the listed keys
+    struct FakeTerminfo {
+        works: Vec<Input>,
+        asked: Vec<String>,
+    }
+
+    impl Terminfo for FakeTerminfo {
+        fn size(&self) -> (i32, i32) {
+            (80, 24)
+        }
+        fn colors(&self) -> Option<(i32, i32)> {
+            None
+        }
+        fn locale(&self) -> Option<String> {
+            Some("en_US.UTF-8".to_string())
+        }
+        fn receives(&mut self, name: &str, key: Input) -> bool {
+            self.asked.push(name.to_string());
+            self.works.contains(&key)
+        }
+    }
+
+    fn keys(text: &str) -> KeyConfig {
+        KeyConfig {
+            rebinds: KeyConfig::parse(text).unwrap(),
+            ..KeyConfig::default()
+        }
+    }
+
+    #[test]
+    fn fallback_keys_are_opt_in() {
+        // with no keys file every plain key types what it says
+        let none = KeyConfig::default();
+        for (_, _, fallback) in KEY_FALLBACKS {
+            let typed = Some(Input::Character(fallback));
+            assert_eq!(none.translate(typed), typed);
+        }
+        let config = keys("# from :doctor\n\ndelete = X\nPgUp = ;\n");
+        assert_eq!(
+            config.translate(Some(Input::Character('X'))),
+            Some(Input::KeyDC)
+        );
+        assert_eq!(
+            config.translate(Some(Input::Character(';'))),
+            Some(Input::KeyPPage)
+        );
+        // only what was asked for
+        assert_eq!(
+            config.translate(Some(Input::Character(']'))),
+            Some(Input::Character(']'))
+        );
+
+        let error = |text| KeyConfig::parse(text).err().unwrap();
+        assert_eq!(error("Delete X"), "line 1: expected <key> = <char>");
+        assert_eq!(error("\nHome = h"), "line 2: unknown key 'Home'");
+        assert_eq!(error("F3 = ab"), "line 1: 'ab' isn't one character");
+        assert_eq!(error("F3 = x\nF4 = x"), "line 2: x is already bound");
+    }
+
+    #[test]
+    fn rebind_advice() {
+        use KeyAdvice::*;
+        let config = keys("Delete = X\nF3 = ]");
+        let cases = [
+            // received, key, fallback, advice
+            (true, Input::KeyF4, '}', Works),
+            (true, Input::KeyDC, 'X', Redundant('X')),
+            (false, Input::KeyDC, 'X', Rebound('X')),
+            (false, Input::KeyF3, '{', Rebound(']')),
+            (false, Input::KeyF4, '}', Suggest('}')),
+            // PgUp's usual ] already went to F3
+            (false, Input::KeyPPage, ']', Taken(']')),
+        ];
+        for (received, key, fallback, advice) in cases {
+            assert_eq!(
+                KeyAdvice::decide(received, key, fallback, &config),
+                advice,
+                "{:?}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn doctor_suggests_only_missing_keys() {
+        let mut term = FakeTerminfo {
+            works: KEY_FALLBACKS
+                .iter()
+                .map(|(_, key, _)| *key)
+                .filter(|key| !matches!(key, Input::KeyDC | Input::KeyF3 | Input::KeyF4))
+                .collect(),
+            asked: Vec::new(),
+        };
+        let mut config = keys("F3 = q");
+        config.path = Some(PathBuf::from("/home/me/.config/chordchart/keys"));
+        config.status = "ok, 1 rebinds".to_string();
+        let (lines, suggested) = doctor(&mut term, &config);
+        assert_eq!(term.asked.len(), KEY_FALLBACKS.len());
+        assert_eq!(suggested, [("Delete", 'X'), ("F4", '}')]);
+        assert!(lines.contains(&"terminal size: 80x24".to_string()));
+        assert!(lines.contains(&"colors: none".to_string()));
+        assert!(lines.contains(&"locale: en_US.UTF-8 (UTF-8)".to_string()));
+        assert!(lines
+            .contains(&"keys file: /home/me/.config/chordchart/keys (ok, 1 rebinds)".to_string()));
+        assert!(lines.contains(&"F3: not received, rebound to q".to_string()));
+        assert!(lines.contains(&"PgUp: ok".to_string()));
+    }
+
+    #[test]
+    fn doctor_appends_to_the_keys_file() {
+        let dir = std::env::temp_dir().join(format!("chordchart-keys-{}", std::process::id()));
+        let path = dir.join("chordchart").join("keys");
+        let mut config = KeyConfig {
+            path: Some(path.clone()),
+            ..KeyConfig::default()
+        };
+        config.append(&[("Delete", 'X')]).unwrap();
+        fs::write(&path, "# mine\nDelete = X").unwrap();
+        config.append(&[("F4", '}')]).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# mine\nDelete = X\nF4 = }\n"
+        );
+        assert_eq!(
+            config.translate(Some(Input::Character('}'))),
+            Some(Input::KeyF4)
+        );
+        // a clash leaves the file as it was
+        assert!(config.append(&[("F5", 'X')]).is_err());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# mine\nDelete = X\nF4 = }\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // n sections of 1 to 7 bars, with a marker or a meter change here and
+    // there so their heights differ
+    fn long_song(n: usize) -> Song {
+        let bars: Vec<Vec<usize>> = (0..n).map(|i| vec![4; i % 7 + 1]).collect();
+        let bars: Vec<&[usize]> = bars.iter().map(Vec::as_slice).collect();
+        let mut song = song(&bars);
+        for (i, section) in song.sections.iter_mut().enumerate() {
+            section.wrap = 4;
+            if i % 5 == 0 {
+                section.bars[0].marker = Some(Marker::Segno);
+            }
+            if i % 11 == 3 {
+                section.bars.last_mut().unwrap().beats = 3;
+            }
+        }
+        song
+    }
+
+    #[test]
+    fn a_hundred_sections() {
+        let labels: Vec<String> = (0..100).map(section_label).collect();
+        assert_eq!(
+            [&labels[25], &labels[26], &labels[51], &labels[99]],
+            ["Z", "AA", "AZ", "CV"]
+        );
+        for (i, label) in labels.iter().enumerate() {
+            assert_eq!(section_label_index(label), Some(i));
+        }
 
-                    if selected {
-                        self.win.attroff(Attribute::Reverse);
-                    }
-                }
+        let song = long_song(100);
+        let settings = Settings::default();
+        let full = song.layout(&settings, Notation::Letters);
+        let outline = song.outline(&settings, Notation::Letters, None);
+        let tops: Vec<i32> = full.sections.iter().map(|s| s.label_y).collect();
+        assert_eq!(outline.tops[..100], tops);
+        assert_eq!(outline.first_bars[99], song.bar_count() - 1);
+
+        // a 24-line screen scrolled anywhere through the song
+        let screen = 24 - 1 - song.header_height();
+        for scroll in (0..outline.tops[100] - song.header_height()).step_by(7) {
+            let (from_y, to_y) = (
+                song.header_height() + scroll,
+                song.header_height() + scroll + screen,
+            );
+            let shown = outline.visible(from_y, to_y);
+            let part = song.layout_sections(&settings, Notation::Letters, &outline, shown.clone());
+            for (i, placed) in full.sections.iter().enumerate() {
+                let on_screen =
+                    placed.label_y < to_y && placed.rows.last().unwrap().y + 1 >= from_y;
+                assert!(
+                    !on_screen || shown.contains(&i),
+                    "{} at scroll {}",
+                    i,
+                    scroll
+                );
             }
-            self.win.addch('|'); // terminating
-            self.win
-                .addstr(" ".repeat((self.win.get_max_x() - self.win.get_cur_x() - 1) as usize));
+            for (i, placed) in shown.clone().zip(&part.sections) {
+                assert_eq!(
+                    serde_json::to_value(placed).unwrap(),
+                    serde_json::to_value(&full.sections[i]).unwrap()
+                );
+            }
+            // the cursor is found from its own section alone
+            let cursor = at(shown.start, song.sections[shown.start].bars.len() - 1, 3);
+            let own = song.layout_sections(
+                &settings,
+                Notation::Letters,
+                &outline,
+                cursor.section..cursor.section + 1,
+            );
+            assert_eq!(own.find(cursor), full.find(cursor));
         }
-        self.draw_toast();
-        self.win.refresh();
+
+        // what a draw lays out depends on the screen, not the song
+        let longer = long_song(1000);
+        let longer_outline = longer.outline(&settings, Notation::Letters, None);
+        let from_y = outline.tops[60];
+        assert_eq!(
+            outline.visible(from_y, from_y + screen).len(),
+            longer_outline.visible(from_y, from_y + screen).len()
+        );
+        assert!(longer_outline.visible(from_y, from_y + screen).len() <= screen as usize);
     }
-    fn current_section(&self) -> &Section {
-        &self.song.sections[self.cursor.section]
+
+    #[test]
+    fn outline_picks_up_from_the_first_change() {
+        let mut song = long_song(100);
+        let settings = Settings::default();
+        let mut outline = song.outline(&settings, Notation::Letters, None);
+        let before = serde_json::to_value(&song).unwrap();
+        // a marker over its one row gives section 51 a line more
+        song.sections[51].bars[1].marker = Some(Marker::Coda);
+        let from = first_changed_section(&before, &serde_json::to_value(&song).unwrap());
+        assert_eq!(from, 51);
+        outline.refresh(&song, &settings, Notation::Letters, from);
+        let fresh = song.outline(&settings, Notation::Letters, None);
+        assert_eq!(outline.tops, fresh.tops);
+        assert_eq!(outline.first_bars, fresh.first_bars);
+        // label, markers, the row and a blank line
+        assert_eq!(outline.tops[52] - outline.tops[51], 4);
+
+        // anything outside the sections starts over
+        let before = serde_json::to_value(&song).unwrap();
+        song.subtitle = Some("live".to_string());
+        assert_eq!(
+            first_changed_section(&before, &serde_json::to_value(&song).unwrap()),
+            0
+        );
+        // and a deleted section counts from where it was
+        let before = serde_json::to_value(&song).unwrap();
+        song.sections.truncate(80);
+        assert_eq!(
+            first_changed_section(&before, &serde_json::to_value(&song).unwrap()),
+            80
+        );
+        outline.refresh(&song, &settings, Notation::Letters, 80);
+        assert_eq!(outline.tops.len(), 81);
     }
-    fn current_section_mut(&mut self) -> &mut Section {
-        &mut self.song.sections[self.cursor.section]
+
+    #[test]
+    fn long_forms_are_cut_short() {
+        let song = long_song(100);
+        assert_eq!(long_song(4).form(40), "A B C D");
+        let form = song.form(30);
+        assert_eq!(form, "A B C D E F G H I ... 91 more");
+        assert!(form.chars().count() <= 30);
+        assert_eq!(song.form(3), "... 100 more");
+
+        assert_eq!(page_of(0, 100, 20), (0..20, 1, 5));
+        assert_eq!(page_of(45, 100, 20), (40..60, 3, 5));
+        assert_eq!(page_of(99, 100, 20), (80..100, 5, 5));
+        assert_eq!(page_of(3, 4, 20), (0..4, 1, 1));
     }
-    fn current_chord_mut(&mut self) -> Option<&mut Chord> {
-        let cursor = self.cursor;
-        self.current_section_mut().bars[cursor.bar]
-            .chords
-            .get_mut(&cursor.subdivision)
+
+    // A twice round three times, B a vamp that also has repeats set, then C
+    fn vamp_song() -> Song {
+        let mut song = song(&[&[4, 4], &[4], &[4]]);
+        song.sections[0].repeats = true;
+        song.sections[0].repeat_count = 3;
+        song.sections[1].vamp = true;
+        song.sections[1].repeats = true;
+        put(&mut song, 1, 0, &[(0, "D-7")]);
+        song
     }
-    fn next_bar(&mut self) {
-        if self.cursor.bar + 1 == self.current_section().bars.len() {
-            self.cursor.subdivision = self.current_section().bars.last().unwrap().subdivision - 1;
-            return;
-        }
-        self.cursor.bar += 1;
+
+    #[test]
+    fn vamp_flag_is_optional() {
+        let song = vamp_song();
+        let json = serde_json::to_value(&song).unwrap();
+        assert_eq!(json["sections"][1]["vamp"], true);
+        // left out when off, and read as off when missing
+        assert!(json["sections"][0].get("vamp").is_none());
+        let back: Song = serde_json::from_value(json).unwrap();
+        let vamps: Vec<bool> = back.sections.iter().map(|s| s.vamp).collect();
+        assert_eq!(vamps, [false, true, false]);
     }
-    fn next_or_create_bar(&mut self) {
-        let cursor = self.cursor;
-        let section = self.current_section();
-        if section.bars.is_empty() {
-            self.current_section_mut().bars.push(Bar::default());
-            self.cursor.bar = 0;
-            self.cursor.subdivision = 0;
-            return;
-        }
 
-        let previous = section.bars.last().unwrap();
-        let new = Bar::new(previous.beats, previous.subdivision);
-        if section.bars.len() == cursor.bar + 1 && self.song.sections.len() == cursor.section + 1 {
-            // last bar last section
-            self.current_section_mut().bars.push(new);
-            self.cursor.bar += 1;
-            self.cursor.subdivision = 0;
-            self.win.clear();
-        } else if section.bars.len() == cursor.bar + 1 {
-            // last bar not last section
-            self.next_or_create_section();
-        } else {
-            self.cursor.bar += 1;
-            self.cursor.subdivision = 0;
-        }
+    #[test]
+    fn vamps_stay_one_pass_when_expanded() {
+        use Pass::*;
+        let song = vamp_song();
+        assert_eq!(song.expand(), [Once(0), Once(0), Once(0), Vamp(1), Once(2)]);
+        let played: Vec<usize> = song.played(2).map(|(i, _)| i).collect();
+        assert_eq!(played, [0, 0, 0, 1, 1, 2]);
     }
-    fn prev_bar(&mut self) {
-        if self.cursor.bar == 0 && self.cursor.subdivision > 0 {
-            self.cursor.subdivision = 0;
-            return;
-        } else if self.cursor.bar == 0 && self.cursor.subdivision == 0 {
-            self.prev_section();
-        }
 
-        self.cursor.bar = self.cursor.bar.saturating_sub(1);
+    #[test]
+    fn vamp_durations_are_estimates() {
+        let mut song = vamp_song();
+        // 24 beats of A, 4 a time round the vamp, 4 of C
+        assert_eq!(song.duration(120, 4), ((24 + 16 + 4) * 60 / 120, true));
+        assert_eq!(song.duration(120, 1), (16, true));
+        // part seconds round up
+        assert_eq!(song.duration(90, 4), (30, true));
+
+        let mut settings = Settings::default();
+        assert_eq!(song.duration_text(120, &settings), "~0:22");
+        settings.unicode = true;
+        assert_eq!(song.duration_text(120, &settings), "≈0:22");
+        settings.set("vamp_times", "40").unwrap();
+        assert_eq!(song.duration_text(120, &settings), "≈1:34");
+        assert!(settings.set("vamp_times", "0").is_err());
+
+        song.sections[1].vamp = false;
+        // B's own repeat counts again once it stops vamping, exactly
+        assert_eq!(song.duration_text(120, &settings), "0:18");
     }
-    fn next_subdivision(&mut self) {
-        let current_bar = self.cursor.bar;
-        let current_sub = self.cursor.subdivision;
-        let section = self.current_section();
 
-        if current_sub + 1 == section.bars[current_bar].subdivision {
-            self.next_or_create_bar();
-        } else {
-            self.cursor.subdivision += 1;
+    #[test]
+    fn midi_takes_vamps_round_vamp_times() {
+        // ticks from the start to the end of the track
+        fn length(midi: &[u8]) -> usize {
+            let mut i = 22; // past the header and the track's own
+            let mut ticks = 0;
+            let vlq = |i: &mut usize| {
+                let mut n = 0;
+                loop {
+                    let b = midi[*i];
+                    *i += 1;
+                    n = n << 7 | (b & 0x7f) as usize;
+                    if b & 0x80 == 0 {
+                        return n;
+                    }
+                }
+            };
+            while i < midi.len() {
+                ticks += vlq(&mut i);
+                match midi[i] {
+                    0xff => {
+                        i += 2;
+                        i += vlq(&mut i);
+                    }
+                    _ => i += 3,
+                }
+            }
+            ticks
         }
+        let song = vamp_song();
+        assert_eq!(length(&song.to_midi(1)), (24 + 4 + 4) * 480);
+        assert_eq!(length(&song.to_midi(5)), (24 + 20 + 4) * 480);
+        let mut settings = Settings::default();
+        settings.set("vamp_times", "5").unwrap();
+        assert_eq!(ExportFormat::Midi.render(&song, &settings), song.to_midi(5));
     }
-    fn prev_subdivision(&mut self) {
-        let cursor = self.cursor;
 
-        if cursor.subdivision == 0 {
-            self.prev_bar();
-            let current_bar = self.cursor.bar;
-            self.cursor.subdivision = self.current_section().bars[current_bar].subdivision - 1;
-        } else {
-            self.cursor.subdivision -= 1;
-        }
+    fn held_fixture() -> Song {
+        let mut song = song(&[&[2, 2, 2], &[2]]);
+        put(&mut song, 0, 0, &[(0, "C^")]);
+        put(&mut song, 0, 1, &[(0, "%"), (1, "N.C.")]);
+        put(&mut song, 0, 2, &[(1, "G7")]);
+        song
     }
-    fn chord_input(&mut self, first: Option<char>) -> Result<String, ()> {
-        let mut buf = String::with_capacity(8);
-        if let Some(f) = first {
-            buf.push(f);
-        }
-        let mut finished = false;
-        // find current cursor position
-        let (y, x) = self.find_cursor();
 
-        self.win.attron(Attribute::Reverse);
-        while !finished {
-            self.win.mvaddstr(y, x, &buf);
-            let ch = self.win.getch();
-            if let Some(Input::Character(c)) = ch {
-                if c.is_ascii_alphanumeric() || c.is_ascii_punctuation() {
-                    buf.push(c);
-                } else if c.is_whitespace() {
-                    if c == ' ' {
-                        self.next_subdivision();
-                    } else if c == '\t' {
-                        self.next_or_create_bar();
+    #[test]
+    fn layout_export_at_two_widths() {
+        let song = held_fixture();
+        let settings = Settings::default();
+        let natural = song.layout_at(&settings, Notation::Letters, None);
+        assert_eq!(
+            serde_json::to_value(&natural).unwrap(),
+            serde_json::json!({
+                "gutter": 0,
+                "sections": [
+                    {
+                        "label": "A",
+                        "label_y": 2,
+                        "rows": [
+                            {
+                                "y": 3,
+                                "end_x": 25,
+                                "bars": [
+                                    {
+                                        "bar": 0,
+                                        "x": 0,
+                                        "cells": [
+                                            {"subdivision": 0, "x": 1, "width": 3, "symbol": "C^", "special": false, "question": false, "held": false, "simile": false},
+                                            {"subdivision": 1, "x": 4, "width": 3, "special": false, "question": false, "held": true, "simile": false}
+                                        ]
+                                    },
+                                    {
+                                        "bar": 1,
+                                        "x": 7,
+                                        "cells": [
+                                            {"subdivision": 0, "x": 8, "width": 5, "symbol": "%", "special": false, "question": false, "held": false, "simile": true},
+                                            {"subdivision": 1, "x": 13, "width": 5, "symbol": "N.C.", "special": false, "question": false, "held": false, "simile": false}
+                                        ]
+                                    },
+                                    {
+                                        "bar": 2,
+                                        "x": 18,
+                                        "cells": [
+                                            {"subdivision": 0, "x": 19, "width": 3, "special": false, "question": false, "held": false, "simile": false},
+                                            {"subdivision": 1, "x": 22, "width": 3, "symbol": "G7", "special": false, "question": false, "held": false, "simile": false}
+                                        ]
+                                    }
+                                ]
+                            }
+                        ]
+                    },
+                    {
+                        "label": "B",
+                        "label_y": 5,
+                        "rows": [
+                            {
+                                "y": 6,
+                                "end_x": 5,
+                                "bars": [
+                                    {
+                                        "bar": 0,
+                                        "x": 0,
+                                        "cells": [
+                                            {"subdivision": 0, "x": 1, "width": 2, "special": false, "question": false, "held": true, "simile": false},
+                                            {"subdivision": 1, "x": 3, "width": 2, "special": false, "question": false, "held": true, "simile": false}
+                                        ]
+                                    }
+                                ]
+                            }
+                        ]
                     }
-                    finished = true;
-                } else if c == '\u{8}' {
-                    buf.pop();
-                    self.win.mvaddstr(y, x, &buf);
-                    self.win.addch(' ');
-                } else {
-                    finished = true;
+                ]
+            })
+        );
+        // too narrow for all of A, so it breaks a bar early and B moves down
+        let narrow = song.layout_at(&settings, Notation::Letters, Some(20));
+        assert_eq!(
+            serde_json::to_value(&narrow).unwrap(),
+            serde_json::json!({
+                "width": 20,
+                "gutter": 0,
+                "sections": [
+                    {
+                        "label": "A",
+                        "label_y": 2,
+                        "rows": [
+                            {
+                                "y": 3,
+                                "end_x": 18,
+                                "bars": [
+                                    {
+                                        "bar": 0,
+                                        "x": 0,
+                                        "cells": [
+                                            {"subdivision": 0, "x": 1, "width": 3, "symbol": "C^", "special": false, "question": false, "held": false, "simile": false},
+                                            {"subdivision": 1, "x": 4, "width": 3, "special": false, "question": false, "held": true, "simile": false}
+                                        ]
+                                    },
+                                    {
+                                        "bar": 1,
+                                        "x": 7,
+                                        "cells": [
+                                            {"subdivision": 0, "x": 8, "width": 5, "symbol": "%", "special": false, "question": false, "held": false, "simile": true},
+                                            {"subdivision": 1, "x": 13, "width": 5, "symbol": "N.C.", "special": false, "question": false, "held": false, "simile": false}
+                                        ]
+                                    }
+                                ]
+                            },
+                            {
+                                "y": 4,
+                                "end_x": 7,
+                                "bars": [
+                                    {
+                                        "bar": 2,
+                                        "x": 0,
+                                        "cells": [
+                                            {"subdivision": 0, "x": 1, "width": 3, "special": false, "question": false, "held": false, "simile": false},
+                                            {"subdivision": 1, "x": 4, "width": 3, "symbol": "G7", "special": false, "question": false, "held": false, "simile": false}
+                                        ]
+                                    }
+                                ]
+                            }
+                        ]
+                    },
+                    {
+                        "label": "B",
+                        "label_y": 6,
+                        "rows": [
+                            {
+                                "y": 7,
+                                "end_x": 5,
+                                "bars": [
+                                    {
+                                        "bar": 0,
+                                        "x": 0,
+                                        "cells": [
+                                            {"subdivision": 0, "x": 1, "width": 2, "special": false, "question": false, "held": true, "simile": false},
+                                            {"subdivision": 1, "x": 3, "width": 2, "special": false, "question": false, "held": true, "simile": false}
+                                        ]
+                                    }
+                                ]
+                            }
+                        ]
+                    }
+                ]
+            })
+        );
+        // room for the closing barline is enough
+        let exact = song.layout_at(&settings, Notation::Letters, Some(26));
+        assert_eq!(exact.sections[0].rows.len(), 1);
+    }
+
+    // just what layout.schema.json uses: types, properties, required and $ref
+    fn check_schema(
+        value: &serde_json::Value,
+        schema: &serde_json::Value,
+        root: &serde_json::Value,
+        at: &str,
+    ) {
+        use serde_json::Value;
+        if let Some(name) = schema["$ref"].as_str() {
+            let name = name.trim_start_matches("#/$defs/");
+            return check_schema(value, &root["$defs"][name], root, at);
+        }
+        let fits = match schema["type"].as_str().unwrap() {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "integer" => value.is_i64(),
+            "string" => value.is_string(),
+            "boolean" => value.is_boolean(),
+            other => panic!("{} isn't handled", other),
+        };
+        assert!(fits, "{} should be {}", at, schema["type"]);
+        if let Some(allowed) = schema["enum"].as_array() {
+            assert!(allowed.contains(value), "{} is {}", at, value);
+        }
+        match value {
+            Value::Object(fields) => {
+                for required in schema["required"].as_array().unwrap() {
+                    assert!(
+                        fields.contains_key(required.as_str().unwrap()),
+                        "{} needs {}",
+                        at,
+                        required
+                    );
+                }
+                for (key, field) in fields {
+                    let Some(property) = schema["properties"].get(key) else {
+                        panic!("{}.{} isn't in the schema", at, key);
+                    };
+                    check_schema(field, property, root, &format!("{}.{}", at, key));
+                }
+            }
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    check_schema(item, &schema["items"], root, &format!("{}[{}]", at, i));
                 }
-            } else {
-                finished = true;
             }
+            _ => {}
         }
-        self.win.attroff(Attribute::Reverse);
-        Ok(buf)
     }
-    fn input_or_edit_in_place_chord(&mut self, first: char) {
-        let Ok(note) = Note::try_from(first) else {
-            return;
-        };
-
-        let cursor = self.cursor;
-        // if let Some(prev_chord) = self.current_section_mut().bars[cursor.bar].get_chord_mut(cursor.subdivision) {
-        //     // already a chord there
-        //     // just change the root
-        //     prev_chord.note = note;
-        //     return;
-        // }
 
-        let new = self.chord_input(Some(first)).unwrap();
-        if let Ok(chord) = Chord::parse(&new) {
-            self.current_section_mut().bars[cursor.bar]
-                .chords
-                .insert(cursor.subdivision, chord);
+    #[test]
+    fn layout_export_follows_the_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(include_str!("../layout.schema.json")).unwrap();
+        let mut song = held_fixture();
+        song.sections[0].repeats = true;
+        song.sections[1].vamp = true;
+        song.sections[0].bars[2].marker = Some(Marker::Fine);
+        song.sections[1].bars[0] = Bar::new(3, 2);
+        put(&mut song, 0, 0, &[(0, "%")]);
+        let settings = Settings {
+            bar_numbers: true,
+            ..Settings::default()
+        };
+        for width in [None, Some(20)] {
+            let layout = song.layout_at(&settings, Notation::Letters, width);
+            let value = serde_json::to_value(&layout).unwrap();
+            check_schema(&value, &schema, &schema, "layout");
+        }
+        // every optional field turned up at least once
+        let dump =
+            serde_json::to_string(&song.layout_at(&settings, Notation::Letters, Some(20))).unwrap();
+        for key in [
+            "width",
+            "vamp",
+            "repeats",
+            "number",
+            "marker",
+            "time",
+            "symbol",
+            "unresolved",
+        ] {
+            assert!(
+                dump.contains(&format!("\"{}\":", key)),
+                "no {} in the dump",
+                key
+            );
         }
     }
-    fn do_command_line(&mut self) {
-        self.toast.ticks = 0;
-        let mut buf = String::new();
 
-        let mut finished = false;
-        let y = self.win.get_max_y() - 1;
-        let x = 1;
-        self.win.attron(Attribute::Reverse);
-        self.win.mvaddch(y, 0, ':');
-        curs_set(1);
+    // characters as a terminal would show them, attributes only counted
+    #[derive(Default)]
+    struct FakeGrid {
+        lines: Vec<Vec<char>>,
+        attrs: i32,
+    }
 
-        while !finished {
-            self.win.mvaddstr(y, x, &buf);
-            self.win.hline(' ', self.win.get_max_x() - buf.len() as i32);
-            let ch = self.win.getch();
-            if let Some(Input::Character(c)) = ch {
-                if c.is_ascii_alphanumeric() || c.is_ascii_punctuation() {
-                    buf.push(c);
-                } else if c == '\u{8}' {
-                    buf.pop();
-                    self.win.mvaddstr(y, x, &buf);
-                    self.win.addch(' ');
-                } else if c == ' ' {
-                    // autoexpand stuff
-                    if buf == "t" {
-                        buf = "title ".to_string();
-                    } else if buf == "q" {
-                        buf = "quit".to_string();
-                    } else if buf == "s" {
-                        buf = "save ".to_string();
-                    } else if buf == "e" {
-                        buf = "edit ".to_string();
-                    } else if buf == "p" {
-                        buf = "print".to_string();
-                    } else if buf == "n" {
-                        buf = "new".to_string();
-                    } else {
-                        if buf.is_empty() {
-                            continue;
-                        }
-                        buf.push(' ');
-                    }
-                } else if c == '\t' {
-                    continue;
-                } else {
-                    finished = true;
+    impl Grid for FakeGrid {
+        fn put(&mut self, y: i32, x: i32, text: &str) {
+            let line = y as usize;
+            if self.lines.len() <= line {
+                self.lines.resize(line + 1, Vec::new());
+            }
+            for (i, c) in text.chars().enumerate() {
+                let x = x as usize + i;
+                if self.lines[line].len() <= x {
+                    self.lines[line].resize(x + 1, ' ');
                 }
-            } else {
-                finished = true;
+                self.lines[line][x] = c;
             }
         }
-        self.win.attroff(Attribute::Reverse);
-        curs_set(0);
-        // now parse
-        if buf.is_empty() {
-            return;
+        fn attr(&mut self, _: chtype, on: bool) {
+            self.attrs += if on { 1 } else { -1 };
         }
-        let components = buf.split_ascii_whitespace().collect::<Vec<&str>>();
-        if components.first() == Some(&"title") && components.get(1).is_some() {
-            // set title
-            let title = components.get(1..).unwrap().join(" ");
-            self.song.title = title;
-            self.schedule_clear();
-            self.toast(&format!("Set title to '{}'.", self.song.title));
-        } else if components.first() == Some(&"quit") || components.first() == Some(&"q") {
-            self.quit();
-        } else if components.first() == Some(&"save") || components.first() == Some(&"s") {
-            if let Some(name) = components.get(1) {
-                let path = PathBuf::from(name);
-                self.filename = Some(path.clone());
-                self.save_to_disk(&path);
-                self.toast(&format!("Saved to {}", path.to_str().unwrap()));
-            } else {
-                match &self.filename {
-                    Some(path) => {
-                        self.save_to_disk(path);
-                        self.toast(&format!("Saved to {}", path.to_str().unwrap()));
-                    }
-                    None => {
-                        if let Some(name) = self.prompt_line("filename? ") {
-                            if name.is_empty() {
-                                self.toast("need a file name to save");
-                            } else {
-                                self.save_to_disk(&PathBuf::from(name));
-                            }
+    }
+
+    impl FakeGrid {
+        fn text(&self) -> Vec<String> {
+            let lines = self.lines.iter().map(|l| l.iter().collect::<String>());
+            lines.map(|l| l.trim_end().to_string()).collect()
+        }
+    }
+
+    #[test]
+    fn drawing_puts_everything_where_the_dump_says() {
+        let mut song = held_fixture();
+        song.sections[0].repeats = true;
+        song.sections[0].bars[1].marker = Some(Marker::Segno);
+        let settings = Settings::default();
+        for width in [None, Some(20)] {
+            let layout = song.layout_at(&settings, Notation::Letters, width);
+            let mut drawn = FakeGrid::default();
+            let cursor = at(9, 0, 0); // nowhere, so no dots
+            song.paint(&mut drawn, &settings, &layout, cursor, false, 0..100);
+            assert_eq!(drawn.attrs, 0);
+
+            // the same picture, from nothing but the JSON
+            let dump = serde_json::to_value(&layout).unwrap();
+            let mut expected = FakeGrid::default();
+            for section in dump["sections"].as_array().unwrap() {
+                let label = format!("[{}]", section["label"].as_str().unwrap());
+                expected.put(section["label_y"].as_i64().unwrap() as i32, 0, &label);
+                let rows = section["rows"].as_array().unwrap();
+                for (row_i, row) in rows.iter().enumerate() {
+                    let y = row["y"].as_i64().unwrap() as i32;
+                    for (bar_i, bar) in row["bars"].as_array().unwrap().iter().enumerate() {
+                        let x = bar["x"].as_i64().unwrap() as i32;
+                        expected.put(y, x, "|");
+                        if section.get("repeats").is_some() && bar_i == 0 {
+                            expected.put(y, x + 1, if row_i == 0 { ":" } else { " " });
+                        }
+                        if bar["marker"] == "Segno" {
+                            expected.put(y - 1, x + 1, "Segno");
+                        }
+                        for cell in bar["cells"].as_array().unwrap() {
+                            let symbol = cell["symbol"].as_str().unwrap_or("");
+                            expected.put(y, cell["x"].as_i64().unwrap() as i32, symbol);
                         }
                     }
+                    let end_x = row["end_x"].as_i64().unwrap() as i32;
+                    let last = row_i + 1 == rows.len() && section.get("repeats").is_some();
+                    expected.put(y, end_x, if last { ":|" } else { "|" });
                 }
             }
-        } else if components.first() == Some(&"edit") {
-            if let Some(path) = components.get(1) {
-                self.load_from_disk(&PathBuf::from(path));
-            }
-        } else if components.first() == Some(&"print") || components.first() == Some(&"p") {
-            self.print();
-        } else if (components.first() == Some(&"new") || components.first() == Some(&"n"))
-            && self.prompt_bool("Are you sure you want to clear your song?")
-        {
-            self.song = Song::new();
-            self.cursor = CursorPos::default();
-            self.filename = None;
+            assert_eq!(drawn.text(), expected.text());
+        }
+    }
+
+    #[test]
+    fn print_texts_number_the_pages() {
+        let mut song = song(&[&[4, 4], &[4, 4], &[4, 4], &[4, 4], &[4, 4], &[4, 4]]);
+        song.title = "Blue".to_string();
+        let unpaged = song.to_text(&Settings::default());
+        song.print_header = Some("{title} {page}/{pages}".to_string());
+        // the song has no footer of its own, so the setting's is used
+        let settings = Settings {
+            print_footer: Some("page {page} of {pages}".to_string()),
+            page_lines: 10,
+            ..Settings::default()
+        };
+
+        // the title and A, then two sections a page
+        let text = song.to_text(&settings);
+        let pages: Vec<&str> = text.split('\x0c').collect();
+        assert_eq!(pages.len(), 4);
+        for (i, page) in pages.iter().enumerate() {
+            let lines: Vec<&str> = page.trim_start_matches('\n').lines().collect();
+            assert!(lines.len() <= 10, "{:?}", lines);
+            assert_eq!(lines[0], format!("Blue {}/4", i + 1));
+            assert_eq!(lines.last().unwrap(), &format!("page {} of 4", i + 1));
+        }
+        // and nothing of the chart lost or moved round
+        let chart = |text: &str| {
+            let lines = text.lines().map(|l| l.trim_start_matches('\x0c'));
+            lines
+                .filter(|l| !l.is_empty() && !l.starts_with("Blue ") && !l.starts_with("page "))
+                .map(String::from)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(chart(&text), chart(&unpaged));
+
+        let md = song.to_markdown(&settings);
+        assert!(md.starts_with("Blue 1/"));
+        let breaks = md.matches("page-break-after").count();
+        assert!(md.contains(&format!("page {0} of {0}", breaks + 1)));
+        let cho = song.to_chordpro(&settings);
+        let new_pages = cho.matches("{new_page}").count();
+        assert!(new_pages > 0);
+        assert!(cho.contains(&format!("{{comment: Blue {0}/{0}}}", new_pages + 1)));
+
+        // the browser numbers printed pages
+        let html = song.to_html(&settings, 0);
+        assert!(
+            html.contains(r#"@top-center { content: "Blue " counter(page) "/" counter(pages);"#)
+        );
+        assert!(html
+            .contains(r#"@bottom-center { content: "page " counter(page) " of " counter(pages);"#));
+    }
+
+    #[test]
+    fn roman_numerals_with_and_without_unicode() {
+        let c = Key::parse("C").unwrap();
+        let roman = |symbol: &str, unicode: bool| chord(symbol).roman(c, unicode).unwrap();
+        for (symbol, ascii, unicode) in [
+            ("F", "IV", "IV"),
+            ("D-7", "ii7", "ii7"),
+            ("Bo7", "viio7", "vii°7"),
+            ("Bo", "viio", "vii°"),
+            ("D-7b5", "ii7b5", "iiø7"),
+            ("D-7b5b9", "ii7b5b9", "iiø7b9"),
+            ("E+", "III+", "III+"),
+            ("G7/B", "V7/7", "V7/7"),
+        ] {
+            assert_eq!(roman(symbol, false), ascii, "{}", symbol);
+            assert_eq!(roman(symbol, true), unicode, "{}", symbol);
+        }
+        assert_eq!(chord("Db").roman(c, true), None);
+        // the editor draws them once :set unicode is on
+        let notation = Notation::Roman {
+            key: c,
+            unicode: true,
+        };
+        assert_eq!(chord("Bo7").symbol(notation), "vii°7");
+    }
+
+    #[test]
+    fn editor_draws_unicode_accidentals() {
+        let mut song = song(&[&[2, 2]]);
+        put(&mut song, 0, 0, &[(0, "Bb7"), (1, "F#-7")]);
+        put(&mut song, 0, 1, &[(0, "C")]);
+        let unicode = Settings {
+            unicode: true,
+            ..Settings::default()
+        };
+        let draw = |settings: &Settings| {
+            let layout = song.layout(settings, Notation::Letters);
+            let mut grid = FakeGrid::default();
+            song.paint(&mut grid, settings, &layout, at(9, 0, 0), false, 0..100);
+            (layout, grid.text())
+        };
+        let (layout, lines) = draw(&unicode);
+        assert_eq!(lines[3], "|B♭7  F♯-7 |C   |");
+        // one column a glyph, so the barlines stay where the ASCII ones are
+        let (_, ascii) = draw(&Settings::default());
+        assert_eq!(ascii[3], "|Bb7  F#-7 |C   |");
+        // and the cursor lands on the cell that was drawn
+        let (y, x) = layout.find(at(0, 0, 1)).unwrap();
+        let drawn: String = lines[y as usize].chars().skip(x as usize).take(4).collect();
+        assert_eq!(drawn, "F♯-7");
+    }
+
+    #[test]
+    fn duplicates_wait_for_a_second_commit() {
+        let mut song = song(&[&[4]]);
+        put(&mut song, 0, 0, &[(0, "C7"), (2, "N.C.")]);
+        let bar = &song.sections[0].bars[0];
+        let c7 = chord("C7");
+
+        // C7 again straight after C7 is held back, then kept on the second commit
+        assert!(confirm_duplicate(bar, at(0, 0, 1), &c7, None));
+        let warned = (at(0, 0, 1), c7.clone());
+        assert!(!confirm_duplicate(bar, at(0, 0, 1), &c7, Some(&warned)));
+        // spelled differently or flagged it's still the same chord
+        assert!(confirm_duplicate(bar, at(0, 0, 1), &chord("C7!"), None));
+
+        // anything else in between drops the warning: moving on, moving away
+        // and back (the main loop forgets it), or typing another chord
+        assert!(confirm_duplicate(bar, at(0, 0, 1), &c7, None));
+        let elsewhere = (at(0, 0, 3), c7.clone());
+        assert!(confirm_duplicate(bar, at(0, 0, 1), &c7, Some(&elsewhere)));
+        let other = (at(0, 0, 1), chord("F"));
+        assert!(confirm_duplicate(bar, at(0, 0, 1), &c7, Some(&other)));
+
+        // a different chord, a %, the first subdivision and a re-hit after
+        // the N.C. are never questioned
+        assert!(!confirm_duplicate(bar, at(0, 0, 1), &chord("F"), None));
+        assert!(!confirm_duplicate(bar, at(0, 0, 1), &chord("%"), None));
+        assert!(!confirm_duplicate(bar, at(0, 0, 0), &c7, None));
+        assert!(!confirm_duplicate(bar, at(0, 0, 3), &c7, None));
+        assert!(!confirm_duplicate(bar, at(0, 0, 3), &chord("N.C."), None));
+    }
+
+    #[test]
+    fn accenting_a_cell() {
+        let mut song = song(&[&[4, 4], &[4]]);
+        put(&mut song, 0, 1, &[(0, "G7?")]);
+
+        // nothing sounding yet, so nothing to accent and nothing changes
+        assert!(!song.accent(at(0, 0, 2)));
+        assert!(song.sections[0].bars[0].chords.is_empty());
+
+        // a chord under the cursor has its accent flipped
+        assert!(song.accent(at(0, 1, 0)));
+        assert_eq!(song.sections[0].bars[1].chords[&0].to_string(), "G7!?");
+        assert!(song.accent(at(0, 1, 0)));
+        assert_eq!(song.sections[0].bars[1].chords[&0].to_string(), "G7?");
+
+        // an empty cell gets the chord still ringing, accented but not questioned,
+        // later in the bar or across the barline into the next section
+        assert!(song.accent(at(0, 1, 2)));
+        assert!(song.accent(at(1, 0, 1)));
+        assert_eq!(song.sections[0].bars[1].chords_string(), "G7? G7!");
+        assert_eq!(song.sections[1].bars[0].chords_string(), "G7!");
+    }
+
+    #[test]
+    fn question_notes_are_optional_and_go_with_the_flag() {
+        let plain = serde_json::to_value(chord("G7?")).unwrap();
+        assert!(plain.get("question_note").is_none());
+        let back: Chord = serde_json::from_value(plain).unwrap();
+        assert_eq!(back.question_note, None);
+
+        let mut noted = chord("G7?");
+        noted.question_note = Some("b9?".to_string());
+        let value = serde_json::to_value(&noted).unwrap();
+        assert_eq!(value["question_note"], "b9?");
+        assert_eq!(serde_json::from_value::<Chord>(value).unwrap(), noted);
+
+        noted.toggle_question();
+        assert!(!noted.question);
+        assert_eq!(noted.question_note, None);
+        noted.toggle_question();
+        assert!(noted.question);
+        assert_eq!(noted.question_note, None);
+    }
+
+    #[test]
+    fn triage_walks_the_flagged_chords() {
+        let mut song = song(&[&[2, 2], &[2]]);
+        put(&mut song, 0, 0, &[(0, "C^?"), (1, "A-7")]);
+        put(&mut song, 0, 1, &[(0, "D-7?")]);
+        put(&mut song, 1, 0, &[(1, "G7?")]);
+        song.sections[0].bars[0]
+            .chords
+            .get_mut(&0)
+            .unwrap()
+            .question_note = Some("or C6?".to_string());
+        let positions: Vec<_> = song.questions().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(positions, [at(0, 0, 0), at(0, 1, 0), at(1, 0, 1)]);
+
+        assert_eq!(
+            triage_step(&mut song, positions[0], Some('c')),
+            TriageStep::Next
+        );
+        let cleared = &song.sections[0].bars[0].chords[&0];
+        assert!(!cleared.question);
+        assert_eq!(cleared.question_note, None);
+
+        assert_eq!(
+            triage_step(&mut song, positions[1], Some('s')),
+            TriageStep::Next
+        );
+        assert_eq!(
+            triage_step(&mut song, positions[1], Some('e')),
+            TriageStep::Edit
+        );
+        assert!(song.sections[0].bars[1].chords[&0].question);
+
+        assert_eq!(
+            triage_step(&mut song, positions[2], Some('q')),
+            TriageStep::Stop
+        );
+        assert_eq!(triage_step(&mut song, positions[2], None), TriageStep::Stop);
+        assert_eq!(song.questions().len(), 2);
+    }
+
+    #[test]
+    fn unreadable_files_fail_with_a_message() {
+        let dir =
+            std::env::temp_dir().join(format!("chordchart-unreadable-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mb = 1024 * 1024;
+
+        let blob = dir.join("blob.json");
+        fs::write(&blob, [0u8; 512]).unwrap();
+        let sparse = dir.join("sparse.json");
+        fs::File::create(&sparse).unwrap().set_len(3 * mb).unwrap();
+        let latin1 = dir.join("latin1.json");
+        fs::write(&latin1, b"{\"title\": \"Caf\xe9\"}").unwrap();
+
+        for (path, error) in [
+            (&blob, "not a chordchart song"),
+            (&sparse, "3 MB is over the 1 MB limit (:set max_file_mb)"),
+            (&latin1, "not UTF-8 text (bad byte at 14)"),
+        ] {
+            assert_eq!(
+                read_song(path, mb).err(),
+                Some(format!("{}: {}", path.display(), error))
+            );
         }
-    }
 
-    fn draw_toast(&mut self) {
-        if let Some(message) = &self.toast.message {
-            if self.toast.ticks == 0 {
-                return;
-            }
-            self.win.attron(Attribute::Reverse);
-            self.win.mvaddstr(self.win.get_max_y() - 1, 0, message);
-            self.win.attroff(Attribute::Reverse);
-            self.toast.ticks -= 1;
+        // and :edit on one keeps the open song
+        let settings = Settings {
+            max_file_mb: 1,
+            ..Settings::default()
+        };
+        let mut open = text_fixture();
+        let before = serde_json::to_value(&open).unwrap();
+        for path in [&blob, &sparse, &latin1] {
+            assert!(open.replace_from(path, &settings).is_err());
+            assert_eq!(serde_json::to_value(&open).unwrap(), before);
         }
+        let good = dir.join("good.json");
+        fs::write(&good, serde_json::to_string_pretty(&song(&[&[4]])).unwrap()).unwrap();
+        let replaced = open.replace_from(&good, &settings);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(replaced, Ok((JsonStyle::Pretty, 0)));
+        assert_eq!(open.sections.len(), 1);
     }
 
-    fn toast(&mut self, message: &str) {
-        self.toast.message = Some(message.to_owned());
-        self.toast.ticks = 2;
-    }
+    #[test]
+    fn json_style_of_a_file_and_for_saving() {
+        let song = text_fixture();
+        let pretty = serde_json::to_string_pretty(&song).unwrap();
+        let compact = serde_json::to_string(&song).unwrap();
+        assert_eq!(JsonStyle::detect(&pretty), JsonStyle::Pretty);
+        assert_eq!(JsonStyle::detect(&compact), JsonStyle::Compact);
+        // a trailing newline doesn't make a one-line file pretty
+        assert_eq!(
+            JsonStyle::detect(&format!("{}\n", compact)),
+            JsonStyle::Compact
+        );
 
-    fn prompt_bool(&mut self, message: &str) -> bool {
-        self.prompt_char(&format!("{} (y/n)", message), vec!['y', 'n'])
-            .is_some_and(|c| c == 'y')
+        use JsonStyle::*;
+        let never = || -> Option<char> { panic!("asked with a style to hand") };
+        assert_eq!(
+            JsonStyle::for_save(Some(Compact), Some(Pretty), never),
+            Compact
+        );
+        assert_eq!(JsonStyle::for_save(Some(Pretty), None, never), Pretty);
+        assert_eq!(JsonStyle::for_save(None, Some(Compact), never), Compact);
+        assert_eq!(JsonStyle::for_save(None, None, || Some('c')), Compact);
+        assert_eq!(JsonStyle::for_save(None, None, || Some('p')), Pretty);
+        assert_eq!(JsonStyle::for_save(None, None, || None), Pretty);
     }
 
-    fn prompt_char(&mut self, message: &str, allowable: Vec<char>) -> Option<char> {
-        self.win.attron(Attribute::Reverse);
-        curs_set(1);
-        self.win.mvaddstr(self.win.get_max_y() - 1, 0, message);
-        self.win.attroff(Attribute::Reverse);
-        let mut response: Option<Option<char>> = None;
-        while response.is_none() {
-            let ch = self.win.getch();
-            if let Some(Input::Character(c)) = ch {
-                if allowable.contains(&c) {
-                    response = Some(Some(c));
-                }
-            } else {
-                response = Some(None);
-            }
+    #[test]
+    fn practice_log() {
+        let bare: Practice = serde_json::from_str("{}").unwrap();
+        assert_eq!(bare.to_string(), "last never");
+        assert_eq!(serde_json::to_string(&bare).unwrap(), "{}");
+        let practice: Practice =
+            serde_json::from_str(r#"{"target_tempo": 180, "last_practiced": "2024-03-01"}"#)
+                .unwrap();
+        assert_eq!(practice.to_string(), "goal 180, last 2024-03-01");
+        // a song without a log doesn't grow one
+        let value = serde_json::to_value(song(&[&[4]])).unwrap();
+        assert!(value.get("practice").is_none());
+
+        let practiced = |date: Option<&str>| {
+            let mut song = song(&[&[4]]);
+            song.practice = date.map(|d| Practice {
+                last_practiced: Some(d.to_string()),
+                ..Practice::default()
+            });
+            song
+        };
+        let mut songs = vec![
+            ("recent", practiced(Some("2024-03-01"))),
+            ("never", practiced(None)),
+            ("old", practiced(Some("2023-11-20"))),
+            ("logged, never done", {
+                let mut song = song(&[&[4]]);
+                song.practice = Some(Practice::default());
+                song
+            }),
+        ];
+        stalest_first(&mut songs);
+        let order: Vec<_> = songs.iter().map(|(name, _)| *name).collect();
+        assert_eq!(order, ["never", "logged, never done", "old", "recent"]);
+
+        use PracticeCommand::*;
+        assert_eq!(PracticeCommand::parse(&["tempo", "160"]), Ok(Tempo(160)));
+        assert_eq!(
+            PracticeCommand::parse(&["tempo", "fast"]),
+            Err("usage: practice tempo <bpm>")
+        );
+        assert_eq!(
+            PracticeCommand::parse(&["note", "watch", "the", "bridge"]),
+            Ok(Note("watch the bridge".to_string()))
+        );
+        assert_eq!(PracticeCommand::parse(&["done"]), Ok(Done));
+        for args in [&[][..], &["note"], &["tempo"], &["done", "twice"]] {
+            assert_eq!(
+                PracticeCommand::parse(args),
+                Err("usage: practice tempo <bpm> | note <text> | done")
+            );
         }
-        curs_set(0);
-        response.unwrap()
     }
 
-    fn prompt_line(&mut self, message: &str) -> Option<String> {
-        let mut buf = String::new();
-        let mut finished = false;
-        let y = self.win.get_max_y() - 1;
-        let x = message.len() as i32;
-        self.win.attron(Attribute::Reverse);
-        curs_set(1);
-        self.win.mvaddstr(y, 0, message);
-
-        let mut cancelled = false;
+    #[test]
+    fn a_subtitle_moves_the_chart_down_a_line() {
+        let plain = text_fixture();
+        let mut subtitled = text_fixture();
+        subtitled.subtitle = Some("live".to_string());
+        for headers in [HeaderStyle::Line, HeaderStyle::Inline, HeaderStyle::Margin] {
+            let settings = Settings {
+                section_headers: headers,
+                ..Settings::default()
+            };
+            let before = plain.layout(&settings, Notation::Letters);
+            let after = subtitled.layout(&settings, Notation::Letters);
+            assert_eq!(after.sections[0].label_y, before.sections[0].label_y + 1);
 
-        while !finished {
-            self.win.mvaddstr(y, x, &buf);
-            self.win.hline(' ', self.win.get_max_x() - buf.len() as i32);
-            let ch = self.win.getch();
-            if let Some(Input::Character(c)) = ch {
-                if c.is_ascii_alphanumeric() || c.is_ascii_punctuation() || c == ' ' {
-                    buf.push(c);
-                } else if c == '\u{8}' {
-                    buf.pop();
-                    self.win.mvaddstr(y, x, &buf);
-                    self.win.addch(' ');
-                } else if c == '\u{1b}' {
-                    finished = true;
-                    cancelled = true;
-                } else if c == '\t' {
-                    continue;
-                } else {
-                    finished = true;
+            let mut drawn = FakeGrid::default();
+            subtitled.paint(&mut drawn, &settings, &after, at(9, 0, 0), false, 0..100);
+            let drawn = drawn.text();
+            for (section_i, section) in subtitled.sections.iter().enumerate() {
+                for (bar_i, bar) in section.bars.iter().enumerate() {
+                    for (&sub, chord) in &bar.chords {
+                        let pos = at(section_i, bar_i, sub);
+                        let (y, x) = before.find(pos).unwrap();
+                        assert_eq!(after.find(pos), Some((y + 1, x)));
+                        let symbol = chord.symbol(Notation::Letters);
+                        let line: String = drawn[y as usize + 1].chars().skip(x as usize).collect();
+                        assert!(line.starts_with(&symbol), "{} at {:?}", symbol, pos);
+                    }
                 }
-            } else {
-                finished = true;
-                cancelled = true;
             }
         }
-        self.win.attroff(Attribute::Reverse);
-        curs_set(0);
-        if cancelled {
-            None
-        } else {
-            Some(buf)
-        }
     }
 
-    fn delete_chord_or_empty_bar(&mut self) {
-        let cursor = self.cursor;
-        // maybe even remove empty section
-        if self.current_section().bars.len() == 1
-            && self.current_section().bars[0].chords.is_empty()
-            && self.song.sections.len() > 1
-        {
-            self.song.sections.remove(self.cursor.section);
-            self.cursor.section -= 1;
-            self.cursor.bar = self.current_section().bars.len() - 1;
-            self.cursor.subdivision = self.current_section().bars[self.cursor.bar].beats - 1;
-            self.schedule_clear();
-            return;
-        }
-        let section = self.current_section_mut();
-        let current_bar = &section.bars[cursor.bar];
+    #[test]
+    fn line_and_margin_headers_with_long_labels() {
+        let mut song = song(&[&[4, 4, 4], &[4]]);
+        song.title = "Blue".to_string();
+        song.sections[0].label = "Verse".to_string();
+        song.sections[0].wrap = 1;
+        song.sections[1].label = "Bridge".to_string();
+        put(&mut song, 0, 0, &[(0, "C^"), (2, "A-7")]);
+        put(&mut song, 0, 1, &[(0, "D-7")]);
+        put(&mut song, 0, 2, &[(0, "G7")]);
+        put(&mut song, 1, 0, &[(0, "F^"), (2, "Bb7")]);
+        let with = |headers| Settings {
+            section_headers: headers,
+            ..Settings::default()
+        };
+        assert_eq!(
+            song.to_text(&with(HeaderStyle::Line)),
+            "SONG: Blue
 
-        if current_bar.chords.is_empty() && section.bars.len() > 1 {
-            section.bars.remove(cursor.bar);
-            // put the cursor somewhere nice
-            if cursor.bar >= section.bars.len() {
-                self.cursor.bar -= 1;
+[Verse]
+|C^      A-7     |
+|D-7             |
+|G7              |
+
+[Bridge]
+|F^      Bb7     |"
+        );
+        // the label runs down the gutter and is cut short by the rows
+        assert_eq!(
+            song.to_text(&with(HeaderStyle::Margin)),
+            "SONG: Blue
+
+V |C^      A-7     |
+e |D-7             |
+r |G7              |
+
+B |F^      Bb7     |"
+        );
+
+        for name in ["line", "inline", "margin"] {
+            let settings = with(HeaderStyle::parse(name).unwrap());
+            let layout = song.layout(&settings, Notation::Letters);
+            let text = song.to_text(&settings);
+            let lines: Vec<&str> = text.lines().collect();
+            for (pos, symbol) in [
+                (at(0, 0, 2), "A-7"),
+                (at(0, 2, 0), "G7"),
+                (at(1, 0, 0), "F^"),
+                (at(1, 0, 2), "Bb7"),
+            ] {
+                let (y, x) = layout.find(pos).unwrap();
+                let found: String = lines[y as usize].chars().skip(x as usize).collect();
+                assert!(found.starts_with(symbol), "{} {:?}", name, pos);
             }
-            self.schedule_clear();
-        } else {
-            section.bars[cursor.bar].chords.remove(&cursor.subdivision);
         }
     }
-    fn next_or_create_section(&mut self) {
-        if self.cursor.section + 1 < self.song.sections.len() {
-            // next
-            self.cursor.section += 1;
-            self.cursor.bar = 0;
-            self.cursor.subdivision = 0;
-            return;
-        }
-        // create
-        let previous = self.song.sections.last().unwrap();
-        let new = Section {
-            label: SECTION_LABELS
+
+    #[test]
+    fn global_align_lines_up_the_barlines() {
+        let mut song = song(&[&[4, 4], &[4, 4]]);
+        put(&mut song, 0, 0, &[(0, "F13b9/Ab"), (2, "C")]);
+        put(&mut song, 0, 1, &[(0, "G7")]);
+        put(&mut song, 1, 0, &[(0, "C")]);
+        put(&mut song, 1, 1, &[(0, "D-7"), (2, "Bb^7#11")]);
+        let barlines = |song: &Song, align| {
+            let settings = Settings {
+                align,
+                ..Settings::default()
+            };
+            let layout = song.layout(&settings, Notation::Letters);
+            layout
+                .sections
                 .iter()
-                .position(|&x| x == previous.label)
-                .map(|n| *SECTION_LABELS.get(n + 1).unwrap_or(&"?"))
-                .unwrap_or("?")
-                .to_owned(),
-            bars: vec![Bar::new(
-                previous.bars.last().unwrap().beats,
-                previous.bars.last().unwrap().subdivision,
-            )],
-            repeats: false,
-            wrap: previous.wrap,
+                .map(|s| {
+                    let row = &s.rows[0];
+                    let mut xs: Vec<i32> = row.bars.iter().map(|b| b.x).collect();
+                    xs.push(row.end_x);
+                    xs
+                })
+                .collect::<Vec<_>>()
         };
-        self.song.sections.push(new);
-        self.cursor.section += 1;
-        self.cursor.bar = 0;
-        self.cursor.subdivision = 0;
-    }
-    fn prev_section(&mut self) {
-        if self.cursor.section > 0 {
-            self.cursor.section -= 1;
-            self.cursor.bar = self.song.sections[self.cursor.section].bars.len();
-        }
+        let global = barlines(&song, Align::Global);
+        assert_eq!(global[0], global[1]);
+        let section = barlines(&song, Align::Section);
+        assert_ne!(section[0], section[1]);
+
+        // and in the text export, where the same columns hold |
+        let settings = Settings {
+            align: Align::Global,
+            ..Settings::default()
+        };
+        let text = song.to_text(&settings);
+        let columns: Vec<Vec<usize>> = text
+            .lines()
+            .filter(|l| l.starts_with('|'))
+            .map(|l| l.match_indices('|').map(|(i, _)| i).collect())
+            .collect();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns[0], columns[1]);
+        assert_eq!(
+            columns[0],
+            global[0].iter().map(|&x| x as usize).collect::<Vec<_>>()
+        );
+
+        // a section can opt out, and then doesn't widen the others either
+        song.sections[1].align = Some(Align::Section);
+        let mixed = barlines(&song, Align::Global);
+        assert_eq!(mixed, section);
     }
-    fn save_to_disk(&self, path: &Path) {
-        let encoded = serde_json::to_string_pretty(&self.song).unwrap();
-        fs::write(path, encoded.as_bytes()).unwrap();
+
+    #[test]
+    fn structure_overlay_keys() {
+        use StructureKey::*;
+        let mut song = long_song(5);
+        let labels = |song: &Song| {
+            song.sections
+                .iter()
+                .map(|s| s.label.clone())
+                .collect::<Vec<_>>()
+        };
+        let (mut cursor, mut selected) = (at(2, 1, 0), 2);
+        // two sections to a page
+        let press =
+            |song: &mut Song, cursor: &mut CursorPos, selected: &mut usize, keys: Vec<_>| {
+                let mut result = Ok(true);
+                for key in keys {
+                    result = structure_key(song, cursor, selected, 2, key);
+                }
+                result
+            };
+
+        // moving takes the selection and the cursor along
+        assert_eq!(
+            press(&mut song, &mut cursor, &mut selected, vec![MoveDown]),
+            Ok(true)
+        );
+        assert_eq!(labels(&song), ["A", "B", "D", "C", "E"]);
+        assert_eq!((selected, cursor), (3, at(3, 1, 0)));
+        press(&mut song, &mut cursor, &mut selected, vec![MoveUp, MoveUp]).unwrap();
+        assert_eq!(labels(&song), ["A", "C", "B", "D", "E"]);
+        assert_eq!((selected, cursor), (1, at(1, 1, 0)));
+        // and does nothing past the ends
+        press(&mut song, &mut cursor, &mut selected, vec![Up, MoveUp]).unwrap();
+        assert_eq!(labels(&song), ["A", "C", "B", "D", "E"]);
+        assert_eq!(selected, 0);
+
+        // renaming trims, and an empty label is refused
+        let rename = |label: &str| vec![Rename(label.to_string())];
+        press(&mut song, &mut cursor, &mut selected, rename(" Intro ")).unwrap();
+        assert_eq!(song.sections[0].label, "Intro");
+        assert_eq!(
+            press(&mut song, &mut cursor, &mut selected, rename("  ")),
+            Err("label can't be empty")
+        );
+        assert_eq!(song.sections[0].label, "Intro");
+
+        // deleting above the cursor keeps it on its section
+        assert_eq!(
+            press(&mut song, &mut cursor, &mut selected, vec![Delete]),
+            Ok(true)
+        );
+        assert_eq!(labels(&song), ["C", "B", "D", "E"]);
+        assert_eq!((selected, cursor), (0, at(0, 1, 0)));
+        // and deleting the cursor's own section starts it on the next
+        press(&mut song, &mut cursor, &mut selected, vec![Delete]).unwrap();
+        assert_eq!(labels(&song), ["B", "D", "E"]);
+        assert_eq!((selected, cursor), (0, at(0, 0, 0)));
+        // the last one going moves the selection up
+        press(&mut song, &mut cursor, &mut selected, vec![PageDown, Down]).unwrap();
+        assert_eq!(selected, 2);
+        press(&mut song, &mut cursor, &mut selected, vec![Delete]).unwrap();
+        assert_eq!(selected, 1);
+
+        // jumping closes the overlay with the cursor on the selected section
+        assert_eq!(
+            press(&mut song, &mut cursor, &mut selected, vec![Go]),
+            Ok(false)
+        );
+        assert_eq!(cursor, at(1, 0, 0));
+        assert_eq!(
+            press(&mut song, &mut cursor, &mut selected, vec![Close]),
+            Ok(false)
+        );
+
+        assert_eq!(
+            press(&mut song, &mut cursor, &mut selected, vec![Delete, Delete]),
+            Err("can't delete the only section")
+        );
+        assert_eq!(labels(&song), ["B"]);
     }
-    fn load_from_disk(&mut self, path: &Path) {
-        let mut data = fs::File::open(path).unwrap();
-        self.song = serde_json::from_reader(data).unwrap();
-        self.filename = Some(path.to_path_buf())
+
+    #[test]
+    fn pasting_bars_from_another_song() {
+        // the other chart is in 3/4 and spells its chords differently
+        let mut other = Song::new();
+        other.sections[0].bars = vec![Bar::new(3, 3), Bar::new(3, 3)];
+        other.sections.push(Section {
+            label: "B".to_string(),
+            bars: vec![Bar::new(3, 6)],
+            ..other.sections[0].clone()
+        });
+        put(&mut other, 0, 0, &[(0, "Dm7")]);
+        put(&mut other, 0, 1, &[(0, "G7(b9)"), (2, "Db7")]);
+        put(&mut other, 1, 0, &[(0, "CM7"), (4, "C6")]);
+
+        // the second bar of A and the first of B
+        let picked = other.bars_in(1..=2);
+        assert_eq!(picked.len(), 2);
+
+        let mut song = song(&[&[4, 4]]);
+        put(&mut song, 0, 0, &[(0, "C^")]);
+        put(&mut song, 0, 1, &[(0, "A-7")]);
+        song.insert_bars(0, 1, picked);
+        let bars = &song.sections[0].bars;
+        let meters: Vec<_> = bars.iter().map(|b| (b.beats, b.subdivision)).collect();
+        assert_eq!(meters, [(4, 4), (3, 3), (3, 6), (4, 4)]);
+        assert_eq!(bars[1].chords[&0], chord("G7b9"));
+        assert_eq!(bars[1].chords[&2], chord("Db7"));
+        assert_eq!(bars[2].chords[&0], chord("C^7"));
+        assert_eq!(bars[2].chords[&4], chord("C6"));
+        // and are drawn in this chart's symbols, parentheses kept as written
+        assert_eq!(bars[1].chords_string(), "G7(b9) Db7");
+        assert_eq!(bars[2].chords_string(), "C^ C6");
+        assert_eq!(bars[3].chords_string(), "A-7");
+
+        // the other song is only read
+        assert_eq!(other.sections[0].bars[1].chords[&0].to_string(), "G7(b9)");
     }
-    fn print(&self) {
-        // render pleasingly
-        // oh we should use html that would be funny
-        let preamble = "<style>
-            html {
-                font-size: 24px;
-                font-family: sans-serif;
-            }
 
-            Section {
-                width: 100%;
-                display: flex;
-                flex-wrap: wrap;
-                gap: 2px 0;
-            }
+    #[test]
+    fn autosaves_sit_next_to_the_song() {
+        assert_eq!(
+            autosave_path(Some(Path::new("charts/blue.json"))),
+            Path::new("charts/blue.autosave.json")
+        );
+        assert_eq!(
+            autosave_path(Some(Path::new("blue"))),
+            Path::new("blue.autosave.json")
+        );
+        assert_eq!(
+            autosave_path(None),
+            std::env::temp_dir().join("chordchart.autosave.json")
+        );
 
-            Bar {
-                display: flex;
-                box-sizing: border-box;
-                border-left: 1px solid black;
-                padding: 2px;
-            }
+        let dir = std::env::temp_dir().join(format!("chordchart-recovery-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let song = dir.join("blue.json");
+        let recovery = autosave_path(Some(&song));
+        let touch = |path: &Path, secs: u64| {
+            let file = fs::File::create(path).unwrap();
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            file.set_modified(time).unwrap();
+        };
 
-            Sub {
-                display: flex;
-            }
-        </style>\n";
-        let mut content = String::from(preamble);
-        for (section_i, section) in self.song.sections.iter().enumerate() {
-            // section header
-            content.push_str(&format!("<h2>{}</h2>", section.label));
-            content.push_str("<Section>");
-            for (bar_i, bar) in section.bars.iter().enumerate() {
-                content.push_str(&format!(
-                    "<Bar style=\"width: calc(100%/{});\">",
-                    section.wrap
-                ));
-                for s in 0..bar.subdivision {
-                    content.push_str(&format!(
-                        "<Sub style=\"width: calc(100%/{});\">",
-                        bar.subdivision
-                    ));
-                    if let Some(chord) = bar.get_chord(s) {
-                        // print chord
-                        content.push_str(&format!("{}", chord));
-                    }
-                    content.push_str("</Sub>\n");
-                }
-                content.push_str("</Bar>\n");
+        assert!(!needs_recovery(&recovery, Some(&song)));
+        touch(&recovery, 2_000);
+        // a song that was never saved, or whose file has gone
+        assert!(needs_recovery(&recovery, None));
+        assert!(needs_recovery(&recovery, Some(&song)));
+        touch(&song, 1_000);
+        assert!(needs_recovery(&recovery, Some(&song)));
+        // saved since, or in the same second
+        touch(&song, 2_000);
+        assert!(!needs_recovery(&recovery, Some(&song)));
+        touch(&song, 3_000);
+        assert!(!needs_recovery(&recovery, Some(&song)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bar_jumps_count_like_the_bar_numbers() {
+        // an empty section in the middle takes no numbers
+        let mut song = song(&[&[4; 3], &[4; 2], &[], &[4; 5]]);
+        for section in &mut song.sections {
+            section.wrap = 2;
+        }
+        assert_eq!(song.first_bars(), [1, 4, 6, 6]);
+        for (n, pos) in [
+            (1, Some(at(0, 0, 0))),
+            (3, Some(at(0, 2, 0))),
+            (4, Some(at(1, 0, 0))),
+            (5, Some(at(1, 1, 0))),
+            (6, Some(at(3, 0, 0))),
+            (10, Some(at(3, 4, 0))),
+            (11, None),
+            (0, None),
+            (usize::MAX, None),
+        ] {
+            assert_eq!(song.bar_position(n), pos, "bar {}", n);
+        }
+
+        // every numbered row starts on the bar :bar goes to with its number
+        let settings = Settings {
+            bar_numbers: true,
+            ..Settings::default()
+        };
+        let layout = song.layout(&settings, Notation::Letters);
+        let mut numbered = 0;
+        for (section_i, section) in layout.sections.iter().enumerate() {
+            for row in &section.rows {
+                let n = row.number.unwrap();
+                assert_eq!(
+                    song.bar_position(n),
+                    Some(at(section_i, row.bars[0].bar, 0))
+                );
+                numbered += 1;
             }
-            content.push_str("</Section>\n");
         }
-        println!("{}", content);
+        assert_eq!(numbered, 2 + 1 + 3);
     }
-}
-
-fn main() {
-    let window = initscr();
-    window.keypad(true);
-    noecho();
-    curs_set(0);
 
-    let mut state = State {
-        win: window,
-        song: Song::new(),
-        cursor: CursorPos::default(),
-        should_clear: true,
-        should_quit: false,
-        toast: Toast::default(),
-        filename: None,
-    };
+    #[test]
+    fn only_editing_keys_snapshot_for_undo() {
+        for input in [
+            None,
+            Some(Input::KeyUp),
+            Some(Input::KeyLeft),
+            Some(Input::Character('7')),
+            Some(Input::Character('u')),
+            Some(Input::KeyF5),
+        ] {
+            assert!(leaves_song_alone(input), "{:?}", input);
+        }
+        for input in [
+            Some(Input::Character(' ')),
+            Some(Input::KeyRight),
+            Some(Input::Character('\t')),
+            Some(Input::Character('C')),
+            Some(Input::Character(':')),
+            Some(Input::KeyDC),
+            Some(Input::KeySLeft),
+        ] {
+            assert!(!leaves_song_alone(input), "{:?}", input);
+        }
+    }
 
-    loop {
-        // draw
-        state.draw();
-        // get input
-        match state.win.getch() {
-            Some(Input::Character(c)) => match c {
-                '\t' => state.next_or_create_bar(),
-                ' ' => state.next_subdivision(),
-                's' => state.next_or_create_section(),
-                ':' => state.do_command_line(),
-                '?' => state
-                    .current_chord_mut()
-                    .into_iter()
-                    .for_each(|c| c.toggle_question()),
-                '!' => state
-                    .current_chord_mut()
-                    .into_iter()
-                    .for_each(|c| c.toggle_special()),
-                _ => state.input_or_edit_in_place_chord(c),
-            },
-            Some(Input::KeyDC) => {
-                // DEL
-                state.delete_chord_or_empty_bar();
-            }
-            Some(Input::KeyNPage) => {
-                state.song.sections[state.cursor.section].bars[state.cursor.bar]
-                    .try_reduce_subdivision();
-                state.win.touch();
-                state.toast(&format!(
-                    "{} subdivisions",
-                    state.song.sections[state.cursor.section].bars[state.cursor.bar].subdivision
-                ))
-            }
-            Some(Input::KeyPPage) => {
-                state.song.sections[state.cursor.section].bars[state.cursor.bar]
-                    .double_subdivision();
-                state.toast(&format!(
-                    "{} subdivisions",
-                    state.song.sections[state.cursor.section].bars[state.cursor.bar].subdivision
-                ))
-            }
+    #[test]
+    fn long_chords_widen_their_column() {
+        let mut chart = song(&[&[4, 4, 4, 4]]);
+        chart.sections[0].wrap = 2;
+        put(&mut chart, 0, 0, &[(0, "F13b9/Ab"), (2, "C")]);
+        put(&mut chart, 0, 1, &[(0, "C^13"), (1, "D-"), (3, "G")]);
+        put(&mut chart, 0, 2, &[(0, "C")]);
+        put(&mut chart, 0, 3, &[(0, "F")]);
+        let section = &chart.sections[0];
+        // a symbol and a space, empty cells take two
+        let settings = Settings::default();
+        assert_eq!(
+            section.column_widths(&settings, Notation::Letters, 2),
+            ["F13b9/Ab ".len(), "C^13 ".len()]
+        );
+        // the same in glyphs, counted in characters rather than bytes
+        let unicode = Settings {
+            unicode: true,
+            ..Settings::default()
+        };
+        assert_eq!(
+            section.column_widths(&unicode, Notation::Letters, 2),
+            [9, 5]
+        );
+        // short chords alone keep columns narrow
+        let mut short = song(&[&[4, 4]]);
+        put(&mut short, 0, 0, &[(0, "C")]);
+        assert_eq!(
+            short.sections[0].column_widths(&settings, Notation::Letters, 2),
+            [2, 2]
+        );
 
-            Some(Input::KeyF4) => {
-                state.next_or_create_bar();
-            }
-            Some(Input::KeyF3) => {
-                state.prev_bar();
-            }
-            Some(Input::KeyRight) => {
-                state.next_subdivision();
-            }
-            Some(Input::KeyLeft) => {
-                state.prev_subdivision();
-            }
-            Some(Input::KeyUp) => {
-                for _ in 0..state.current_section_mut().wrap {
-                    state.prev_bar();
-                }
-            }
-            Some(Input::KeyDown) => {
-                for _ in 0..state.current_section_mut().wrap {
-                    state.next_bar();
+        // every cell in a column gets its width, so the long chords fit and
+        // the rows below keep their barlines under the ones above
+        for settings in [&settings, &unicode] {
+            let layout = chart.layout(settings, Notation::Letters);
+            let rows = &layout.sections[0].rows;
+            for row in rows {
+                for (bar, width) in row.bars.iter().zip([9, 5]) {
+                    assert!(bar.cells.iter().all(|c| c.width == width));
+                    for cell in &bar.cells {
+                        if let Some(symbol) = &cell.symbol {
+                            assert!(symbol.chars().count() < cell.width as usize);
+                        }
+                    }
                 }
             }
-            Some(input) => {}
-            None => (),
-        }
-        if state.should_quit {
-            break;
+            let xs = |row: &RowLayout| row.bars.iter().map(|b| b.x).collect::<Vec<_>>();
+            assert_eq!(xs(&rows[0]), xs(&rows[1]));
+            assert_eq!(rows[0].end_x, rows[1].end_x);
         }
     }
-    endwin();
 }